@@ -0,0 +1,35 @@
+// --chunk-sizeの比較。64MiBのファイルを-cで全部書き出すのにかかる時間を測る
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use headr::{Config, Count};
+use std::io::{self, Write};
+
+const FILE_SIZE: usize = 64 << 20;
+
+fn chunk_size(c: &mut Criterion) {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    let line =
+        b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ\n";
+    for _ in 0..FILE_SIZE / line.len() {
+        file.write_all(line).unwrap();
+    }
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap().to_string();
+
+    let mut group = c.benchmark_group("chunk_size");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(20);
+    for (name, size) in [("8K", 8 << 10), ("64K", 64 << 10), ("1M", 1 << 20)] {
+        let config = Config::builder()
+            .files([path.as_str()])
+            .bytes(Count::First(FILE_SIZE))
+            .chunk_size(size)
+            .build();
+        group.bench_function(name, |b| {
+            b.iter(|| headr::run_with(&config, &mut io::sink()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, chunk_size);
+criterion_main!(benches);
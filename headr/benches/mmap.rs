@@ -0,0 +1,45 @@
+// mmap機能の比較。256MiBのファイルを-cで全部書き出すのに、メモリにマップした場合と
+// 読み込んでコピーする場合(openerで開いてサイズが分からないもの)でかかる時間を測る
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use headr::{Config, Count};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+const FILE_SIZE: usize = 256 << 20;
+
+fn mmap(c: &mut Criterion) {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    let line =
+        b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ\n";
+    for _ in 0..FILE_SIZE / line.len() {
+        file.write_all(line).unwrap();
+    }
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap().to_string();
+
+    let mut group = c.benchmark_group("mmap");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+    let mapped = Config::builder()
+        .files([path.as_str()])
+        .bytes(Count::First(FILE_SIZE))
+        .build();
+    group.bench_function("mmap", |b| {
+        b.iter(|| headr::run_with(&mapped, &mut io::sink()).unwrap())
+    });
+    let copied = Config::builder()
+        .files([path.as_str()])
+        .bytes(Count::First(FILE_SIZE))
+        .opener(|name| {
+            Ok(Box::new(BufReader::new(File::open(name)?))
+                as Box<dyn BufRead>)
+        })
+        .build();
+    group.bench_function("read", |b| {
+        b.iter(|| headr::run_with(&copied, &mut io::sink()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mmap);
+criterion_main!(benches);
@@ -1,139 +1,11532 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
-    fs::File,
-    io::{BufRead, BufReader, Read},
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
 };
 
-type MyResult<T> = Result<T, Box<dyn Error>>;
+pub type MyResult<T> = Result<T, HeadError>;
 
+/* ヘッダーの既定の書式。{}の所にファイル名が入る */
+const DEFAULT_HEADER_FORMAT: &str = "==> {} <==";
+
+/* --cacheに置いたものを使い続ける既定の秒数(5分) */
+#[cfg(feature = "http")]
+const DEFAULT_CACHE_TTL: std::time::Duration =
+    std::time::Duration::from_secs(300);
+
+/* 負の-n/-cで末尾を除くために手元に置くバイト数の既定の上限(1G) */
+const DEFAULT_MAX_BUFFER: usize = 1 << 30;
+
+/* ファイルと標準入力を読むバッファの既定の大きさ(64KiB)。-cではこの単位でコピーする */
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/* --chunk-sizeの上限(1G)。バッファは最初にまとめて確保するので、大きすぎる値は断る */
+const MAX_CHUNK_SIZE: usize = 1 << 30;
+
+#[derive(Debug)]
+pub enum HeadError {
+    Io(io::Error),
+    InvalidLineCount(String),
+    InvalidByteCount(String),
+    FileNotFound(String),
+    FilesFailed(usize),
+    InvalidArgument(String),
+    IsADirectory(String),
+    BinaryFile(String),
+    ShortInput(String, usize, usize, &'static str),
+    BufferLimit(String, usize),
+    InvalidUtf8(String, u64),
+    DirectoryLoop(String),
+}
+
+impl fmt::Display for HeadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadError::Io(e) => write!(f, "{}", e),
+            HeadError::InvalidLineCount(val) => {
+                write!(f, "illegal line count -- {}", val)
+            }
+            HeadError::InvalidByteCount(val) => {
+                write!(f, "illegal byte count -- {}", val)
+            }
+            HeadError::FileNotFound(filename) => write!(
+                f,
+                "{}: No such file or directory (os error 2)",
+                filename
+            ),
+            HeadError::FilesFailed(num) => {
+                write!(f, "{} file(s) could not be read", num)
+            }
+            HeadError::InvalidArgument(msg) => write!(f, "{}", msg),
+            HeadError::IsADirectory(filename) => {
+                write!(f, "{}: Is a directory", filename)
+            }
+            HeadError::BinaryFile(filename) => {
+                write!(f, "{}: binary file matches", filename)
+            }
+            HeadError::ShortInput(filename, found, requested, unit) => write!(
+                f,
+                "{}: only {} of {} {} available",
+                filename, found, requested, unit
+            ),
+            HeadError::BufferLimit(filename, max_buffer) => write!(
+                f,
+                "{}: dropping the end needs more than {} bytes of memory \
+                 (raise --max-buffer, default 1G)",
+                filename, max_buffer
+            ),
+            HeadError::InvalidUtf8(filename, offset) => {
+                write!(f, "{}: invalid UTF-8 at byte {}", filename, offset)
+            }
+            HeadError::DirectoryLoop(dirname) => {
+                write!(f, "{}: recursive directory loop, skipping", dirname)
+            }
+        }
+    }
+}
+
+impl HeadError {
+    /* stderrに表示する文。開けたが出力できなかったものには、headrが出したエラーだと分かるよう
+    "headr: "を付ける。--error-format jsonのerrorなどにはDisplayのほうを使う */
+    pub fn message(&self) -> String {
+        match self {
+            HeadError::BinaryFile(_)
+            | HeadError::ShortInput(..)
+            | HeadError::BufferLimit(..)
+            | HeadError::InvalidUtf8(..) => format!("headr: {}", self),
+            e => e.to_string(),
+        }
+    }
+
+    /* --error-format jsonのkind。Ioはio::ErrorKindのうち、よくあるものだけを分ける */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HeadError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => "not_found",
+                io::ErrorKind::PermissionDenied => "permission_denied",
+                io::ErrorKind::InvalidData => "invalid_data",
+                _ => "io",
+            },
+            HeadError::InvalidLineCount(_)
+            | HeadError::InvalidByteCount(_)
+            | HeadError::InvalidArgument(_) => "invalid_argument",
+            HeadError::FileNotFound(_) => "not_found",
+            HeadError::FilesFailed(_) => "files_failed",
+            HeadError::IsADirectory(_) => "is_a_directory",
+            HeadError::BinaryFile(_) => "binary_file",
+            HeadError::ShortInput(..) => "short_input",
+            HeadError::BufferLimit(..) => "buffer_limit",
+            HeadError::InvalidUtf8(..) => "invalid_utf8",
+            HeadError::DirectoryLoop(_) => "directory_loop",
+        }
+    }
+}
+
+impl Error for HeadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HeadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HeadError {
+    fn from(e: io::Error) -> Self {
+        HeadError::Io(e)
+    }
+}
+
+/* serde機能を有効にすると、--dump-configでJSONとして書き出したものを読み戻せる */
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Config {
     files: Vec<String>,
-    lines: usize,
-    bytes: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    implicit_stdin: bool, // ファイルを指定せず、既定の"-"を読む
+    #[cfg_attr(feature = "serde", serde(skip))]
+    walk_errors: Vec<(String, HeadError)>, // -rでディレクトリを読めなかったもの
+    lines: Count,
+    bytes: Option<ByteSpec>,
+    quiet: bool,
+    verbose: bool,
+    zero_terminated: bool,
+    delimiter: Option<u8>,
+    jobs: usize,
+    chars: Option<usize>,
+    graphemes: Option<usize>,
+    words: Option<usize>,
+    fields: Option<usize>,
+    field_sep: char,
+    columns: Option<Vec<(usize, usize)>>,
+    trim: Option<Trim>,
+    wrap: usize,
+    line_ranges: Option<Vec<(usize, usize)>>,
+    number: bool,
+    unix_lines: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    squeeze_blank: bool,
+    paragraph: bool,
+    reverse_lines: bool,
+    byte_offset: bool,
+    no_final_newline: bool,
+    context: Option<usize>,
+    hex: bool,
+    #[cfg(feature = "encoding")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    encoding: Option<&'static encoding_rs::Encoding>,
+    output: Option<String>,
+    tee: Option<String>,
+    output_dir: Option<String>,
+    build_index: bool,
+    use_index: bool,
+    split: Option<usize>,
+    split_prefix: String,
+    split_width: usize,
+    color: ColorChoice,
+    pager: PagerChoice,
+    format: OutputFormat,
+    error_format: ErrorFormat,
+    percent: Option<f64>,
+    bytes_percent: Option<f64>,
+    max_total_lines: Option<usize>,
+    max_total_bytes: Option<usize>,
+    stats: bool,
+    timing: bool,
+    pretty_bytes: Option<ByteUnits>,
+    total_lines: bool,
+    with_total: bool,
+    line_buffered: bool,
+    atomic_headers: bool,
+    exact: bool,
+    min_lines: Option<usize>,
+    print0: bool,
+    header_format: String,
+    separator_lines: usize,
+    absolute_paths: bool,
+    skip_empty: bool,
+    dedup_heads: bool,
+    interleave: bool,
+    diff: bool,
+    ignore_errors: bool,
+    fail_fast: bool,
+    force_success: bool,
+    first_limit: bool,
+    utf8_clean: bool,
+    repeat: Option<std::time::Duration>,
+    timeout: Option<std::time::Duration>,
+    retry: usize,
+    retry_delay: std::time::Duration,
+    max_buffer: usize,
+    spill_to_disk: bool,
+    limit_rate: Option<u64>,
+    chunk_size: usize,
+    strip_bom: bool,
+    skip: usize,
+    keep_header: bool,
+    #[cfg(feature = "regex")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    until: Option<regex::Regex>,
+    until_exclusive: bool,
+    #[cfg(feature = "regex")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    files_with_head_matching: Option<regex::Regex>,
+    #[cfg(feature = "decode")]
+    decode_base64: bool,
+    #[cfg(feature = "decrypt")]
+    decrypt: bool,
+    #[cfg(feature = "decrypt")]
+    decrypt_key: Option<PathBuf>,
+    #[cfg(feature = "watch")]
+    watch_dir: Option<PathBuf>,
+    #[cfg(feature = "http")]
+    cache: Option<PathBuf>,
+    #[cfg(feature = "http")]
+    cache_ttl: std::time::Duration,
+    #[cfg(feature = "checksum")]
+    checksum: Option<Checksum>,
+    #[cfg(feature = "checksum")]
+    checksum_stdout: bool,
+    #[cfg(feature = "progress")]
+    progress: bool,
+    text: bool,
+    raw: bool,
+    validate_utf8: bool,
+    count: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dump_config: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dry_run: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    opener: Option<Opener>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filters: LineFilters,
+}
+
+/// 行ごとの変換。`line`は行末の区切り文字(改行区切りのCRLFならCRも)を除いた中身で、書き換えてよい。
+/// `false`を返した行は出力せず、-nの数にも入れない。
+///
+/// [`ConfigBuilder::filter`]で足した順に、--trim、--squeeze-blank、--columns、--fieldsの後で適用する。
+/// -jのスレッドからも呼ぶので`Send + Sync`にする。--rawとは一緒に使えない。
+pub trait LineFilter: Send + Sync {
+    fn apply(&self, line: &mut String) -> bool;
+}
+
+impl<F: Fn(&mut String) -> bool + Send + Sync> LineFilter for F {
+    fn apply(&self, line: &mut String) -> bool {
+        self(line)
+    }
+}
+
+#[derive(Clone, Default)]
+struct LineFilters(Vec<Arc<dyn LineFilter>>);
+
+impl fmt::Debug for LineFilters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LineFilters({})", self.0.len())
+    }
+}
+
+/* ファイル名から入力を開く関数。run_with_openerで差し替えると、ディスクの代わりに
+メモリ上の内容などを読める。-jのスレッドからも呼ぶのでSend + Syncにする */
+type OpenFn = dyn Fn(&str) -> MyResult<Box<dyn BufRead>> + Send + Sync;
+
+#[derive(Clone)]
+struct Opener(Arc<OpenFn>);
+
+impl fmt::Debug for Opener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Opener")
+    }
+}
+
+/* 先頭からN個か、末尾のN個を除いた全てか */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Count {
+    First(usize),
+    AllButLast(usize),
+}
+
+/* -cの指定。個数か、START-ENDの範囲(1始まりで両端を含む)か */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteSpec {
+    Count(Count),
+    Range(usize, usize),
+}
+
+/* ヘッダーを色付きで表示するか。Autoは出力先が端末のときだけ色を付ける */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/* ページャーを使うか。Autoは端末に出力していて、端末の高さを超えるときだけ使う */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PagerChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/* --pretty-bytesの単位。Iecは1024ごと(KiB, MiB)、Siは1000ごと(kB, MB) */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteUnits {
+    Iec,
+    Si,
+}
+
+/* --trim、--trim-start、--trim-endのどれで行の空白を除くか */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trim {
+    Both,
+    Start,
+    End,
+}
+
+/* --checksumのハッシュ関数 */
+#[cfg(feature = "checksum")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Checksum {
+    Sha256,
+    Md5,
+}
+
+#[cfg(feature = "checksum")]
+impl Checksum {
+    fn name(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "sha256",
+            Checksum::Md5 => "md5",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn sha2::digest::DynDigest + Send> {
+        match self {
+            Checksum::Sha256 => Box::new(sha2::Sha256::default()),
+            Checksum::Md5 => Box::new(md5::Md5::default()),
+        }
+    }
+}
+
+/* 出力の形式。Jsonはファイルごとのオブジェクトを配列にして出力する。
+Ndjsonは1行ごとに1つのオブジェクトを1行で書く。JsonとNdjsonはjson機能、Csvはcsv機能を有効にしたときだけ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputFormat {
+    Text,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "csv")]
+    Csv,
+    #[cfg(feature = "json")]
+    Ndjson,
+}
+
+/* --error-format。Jsonは1つのエラーを1行のオブジェクトにしてstderrに書く(json機能) */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorFormat {
+    Plain,
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl From<usize> for Count {
+    fn from(num: usize) -> Self {
+        Count::First(num)
+    }
+}
+
+impl From<Count> for ByteSpec {
+    fn from(count: Count) -> Self {
+        ByteSpec::Count(count)
+    }
+}
+
+impl From<usize> for ByteSpec {
+    fn from(num: usize) -> Self {
+        ByteSpec::Count(Count::First(num))
+    }
+}
+
+/* get_argsと同じデフォルト値(標準入力から10行) */
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            files: vec!["-".to_string()],
+            implicit_stdin: false,
+            walk_errors: Vec::new(),
+            lines: Count::First(10),
+            bytes: None,
+            quiet: false,
+            verbose: false,
+            zero_terminated: false,
+            delimiter: None,
+            jobs: 1,
+            chars: None,
+            graphemes: None,
+            words: None,
+            fields: None,
+            columns: None,
+            field_sep: '\t',
+            trim: None,
+            wrap: 0,
+            line_ranges: None,
+            unix_lines: false,
+            show_ends: false,
+            show_tabs: false,
+            squeeze_blank: false,
+            paragraph: false,
+            reverse_lines: false,
+            byte_offset: false,
+            no_final_newline: false,
+            context: None,
+            hex: false,
+            #[cfg(feature = "encoding")]
+            encoding: None,
+            number: false,
+            output: None,
+            tee: None,
+            output_dir: None,
+            build_index: false,
+            use_index: false,
+            split: None,
+            split_prefix: "x".to_string(),
+            split_width: 3,
+            color: ColorChoice::Auto,
+            pager: PagerChoice::Auto,
+            format: OutputFormat::Text,
+            error_format: ErrorFormat::Plain,
+            percent: None,
+            bytes_percent: None,
+            max_total_lines: None,
+            max_total_bytes: None,
+            stats: false,
+            timing: false,
+            pretty_bytes: None,
+            total_lines: false,
+            with_total: false,
+            line_buffered: false,
+            atomic_headers: false,
+            exact: false,
+            min_lines: None,
+            print0: false,
+            header_format: DEFAULT_HEADER_FORMAT.to_string(),
+            separator_lines: 1,
+            absolute_paths: false,
+            skip_empty: false,
+            dedup_heads: false,
+            interleave: false,
+            diff: false,
+            ignore_errors: false,
+            fail_fast: false,
+            force_success: false,
+            first_limit: false,
+            utf8_clean: false,
+            repeat: None,
+            timeout: None,
+            retry: 0,
+            retry_delay: std::time::Duration::from_millis(100),
+            max_buffer: DEFAULT_MAX_BUFFER,
+            spill_to_disk: false,
+            limit_rate: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            strip_bom: false,
+            skip: 0,
+            keep_header: false,
+            #[cfg(feature = "regex")]
+            until: None,
+            until_exclusive: false,
+            #[cfg(feature = "regex")]
+            files_with_head_matching: None,
+            #[cfg(feature = "decode")]
+            decode_base64: false,
+            #[cfg(feature = "decrypt")]
+            decrypt: false,
+            #[cfg(feature = "decrypt")]
+            decrypt_key: None,
+            #[cfg(feature = "watch")]
+            watch_dir: None,
+            #[cfg(feature = "http")]
+            cache: None,
+            #[cfg(feature = "http")]
+            cache_ttl: DEFAULT_CACHE_TTL,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+            #[cfg(feature = "checksum")]
+            checksum_stdout: false,
+            #[cfg(feature = "progress")]
+            progress: false,
+            text: false,
+            raw: false,
+            validate_utf8: false,
+            count: false,
+            dump_config: false,
+            dry_run: false,
+            opener: None,
+            filters: LineFilters::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// 読み込むファイル名。ワイルドカードや`~`は展開済みで、標準入力は`"-"`になる。
+    ///
+    /// ```
+    /// use headr::{ByteSpec, Count};
+    ///
+    /// let config = headr::get_args_from(["headr", "-n", "-5", "a.txt"]).unwrap();
+    /// assert_eq!(config.files(), ["a.txt"]);
+    /// assert_eq!(config.lines(), Count::AllButLast(5));
+    /// assert_eq!(config.bytes(), None);
+    ///
+    /// let config = headr::get_args_from(["headr", "-c", "1K"]).unwrap();
+    /// assert_eq!(config.files(), ["-"]);
+    /// assert_eq!(config.bytes(), Some(ByteSpec::Count(Count::First(1024))));
+    /// ```
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// -nの行数。-cなど他の単位で出力するときも既定の10行のまま残る
+    pub fn lines(&self) -> Count {
+        self.lines
+    }
+
+    /// -cの指定。指定が無ければNone
+    pub fn bytes(&self) -> Option<ByteSpec> {
+        self.bytes
+    }
+
+    /* -cか--bytes-percentのバイト数で出力するか。--first-limitでは-cは行単位の出力の上限になる */
+    fn byte_mode(&self) -> bool {
+        (self.bytes.is_some() && !self.first_limit)
+            || self.bytes_percent.is_some()
+    }
+
+    /* 行の区切り文字。-zならNUL、-dで指定されていればその文字、それ以外は改行 */
+    fn delimiter(&self) -> u8 {
+        if self.zero_terminated {
+            b'\0'
+        } else {
+            self.delimiter.unwrap_or(b'\n')
+        }
+    }
+}
+
+/* clapを使わずにConfigを組み立てるためのビルダー */
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn files<I, S>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn lines(mut self, lines: impl Into<Count>) -> Self {
+        self.config.lines = lines.into();
+        self
+    }
+
+    pub fn bytes(mut self, bytes: impl Into<ByteSpec>) -> Self {
+        self.config.bytes = Some(bytes.into());
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    pub fn zero_terminated(mut self, zero_terminated: bool) -> Self {
+        self.config.zero_terminated = zero_terminated;
+        self
+    }
+
+    /* --null-data。-zと--raw、--textをまとめて指定する */
+    pub fn null_data(mut self, null_data: bool) -> Self {
+        self.config.zero_terminated |= null_data;
+        self.config.raw |= null_data;
+        self.config.text |= null_data;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.config.delimiter = Some(delimiter);
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.config.jobs = jobs;
+        self
+    }
+
+    pub fn chars(mut self, chars: usize) -> Self {
+        self.config.chars = Some(chars);
+        self
+    }
+
+    #[cfg(feature = "graphemes")]
+    pub fn graphemes(mut self, graphemes: usize) -> Self {
+        self.config.graphemes = Some(graphemes);
+        self
+    }
+
+    pub fn words(mut self, words: usize) -> Self {
+        self.config.words = Some(words);
+        self
+    }
+
+    pub fn fields(mut self, fields: usize) -> Self {
+        self.config.fields = Some(fields);
+        self
+    }
+
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.config.trim = Some(trim);
+        self
+    }
+
+    pub fn field_sep(mut self, field_sep: char) -> Self {
+        self.config.field_sep = field_sep;
+        self
+    }
+
+    /* --columns。line_rangesと同じく1から数えた文字の位置の範囲 */
+    pub fn columns(
+        mut self,
+        ranges: impl IntoIterator<Item = (usize, usize)>,
+    ) -> Self {
+        self.config.columns = Some(merge_ranges(ranges.into_iter().collect()));
+        self
+    }
+
+    /* 1から数えた(最初, 最後)の範囲。順番や重なりは気にしなくてよい */
+    pub fn line_ranges(
+        mut self,
+        ranges: impl IntoIterator<Item = (usize, usize)>,
+    ) -> Self {
+        self.config.line_ranges =
+            Some(merge_ranges(ranges.into_iter().collect()));
+        self
+    }
+
+    /* --line。1から数えたline_num行目だけを出力する */
+    pub fn line(self, line_num: usize) -> Self {
+        self.line_ranges([(line_num, line_num)])
+    }
+
+    /* 0なら折り返さない */
+    pub fn wrap(mut self, width: usize) -> Self {
+        self.config.wrap = width;
+        self
+    }
+
+    pub fn unix_lines(mut self, unix_lines: bool) -> Self {
+        self.config.unix_lines = unix_lines;
+        self
+    }
+
+    pub fn show_ends(mut self, show_ends: bool) -> Self {
+        self.config.show_ends = show_ends;
+        self
+    }
+
+    pub fn show_tabs(mut self, show_tabs: bool) -> Self {
+        self.config.show_tabs = show_tabs;
+        self
+    }
+
+    pub fn squeeze_blank(mut self, squeeze_blank: bool) -> Self {
+        self.config.squeeze_blank = squeeze_blank;
+        self
+    }
+
+    pub fn paragraph(mut self, paragraph: bool) -> Self {
+        self.config.paragraph = paragraph;
+        self
+    }
+
+    pub fn reverse_lines(mut self, reverse_lines: bool) -> Self {
+        self.config.reverse_lines = reverse_lines;
+        self
+    }
+
+    pub fn no_final_newline(mut self, no_final_newline: bool) -> Self {
+        self.config.no_final_newline = no_final_newline;
+        self
+    }
+
+    pub fn byte_offset(mut self, byte_offset: bool) -> Self {
+        self.config.byte_offset = byte_offset;
+        self
+    }
+
+    pub fn context(mut self, num_lines: usize) -> Self {
+        self.config.context = Some(num_lines);
+        self
+    }
+
+    pub fn hex(mut self, hex: bool) -> Self {
+        self.config.hex = hex;
+        self
+    }
+
+    #[cfg(feature = "encoding")]
+    pub fn encoding(
+        mut self,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> Self {
+        self.config.encoding = Some(encoding);
+        self
+    }
+
+    pub fn number(mut self, number: bool) -> Self {
+        self.config.number = number;
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<String>) -> Self {
+        self.config.output = Some(output.into());
+        self
+    }
+
+    pub fn tee(mut self, tee: impl Into<String>) -> Self {
+        self.config.tee = Some(tee.into());
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.output_dir = Some(dir.into());
+        self
+    }
+
+    pub fn build_index(mut self, build_index: bool) -> Self {
+        self.config.build_index = build_index;
+        self
+    }
+
+    pub fn use_index(mut self, use_index: bool) -> Self {
+        self.config.use_index = use_index;
+        self
+    }
+
+    pub fn split(mut self, num_lines: usize) -> Self {
+        self.config.split = Some(num_lines);
+        self
+    }
+
+    pub fn split_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.split_prefix = prefix.into();
+        self
+    }
+
+    pub fn split_width(mut self, width: usize) -> Self {
+        self.config.split_width = width;
+        self
+    }
+
+    pub fn pager(mut self, pager: PagerChoice) -> Self {
+        self.config.pager = pager;
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.config.format = format;
+        self
+    }
+
+    pub fn error_format(mut self, error_format: ErrorFormat) -> Self {
+        self.config.error_format = error_format;
+        self
+    }
+
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.config.color = color;
+        self
+    }
+
+    pub fn percent(mut self, percent: f64) -> Self {
+        self.config.percent = Some(percent);
+        self
+    }
+
+    pub fn bytes_percent(mut self, percent: f64) -> Self {
+        self.config.bytes_percent = Some(percent);
+        self
+    }
+
+    pub fn max_total_lines(mut self, max_total_lines: usize) -> Self {
+        self.config.max_total_lines = Some(max_total_lines);
+        self
+    }
+
+    pub fn max_total_bytes(mut self, max_total_bytes: usize) -> Self {
+        self.config.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.config.stats = stats;
+        self
+    }
+
+    pub fn timing(mut self, timing: bool) -> Self {
+        self.config.timing = timing;
+        self
+    }
+
+    pub fn pretty_bytes(mut self, units: ByteUnits) -> Self {
+        self.config.pretty_bytes = Some(units);
+        self
+    }
+
+    pub fn total_lines(mut self, total_lines: bool) -> Self {
+        self.config.total_lines = total_lines;
+        self
+    }
+
+    pub fn with_total(mut self, with_total: bool) -> Self {
+        self.config.with_total = with_total;
+        self
+    }
+
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        self.config.line_buffered = line_buffered;
+        self
+    }
+
+    pub fn atomic_headers(mut self, atomic_headers: bool) -> Self {
+        self.config.atomic_headers = atomic_headers;
+        self
+    }
+
+    pub fn exact(mut self, exact: bool) -> Self {
+        self.config.exact = exact;
+        self
+    }
+
+    pub fn min_lines(mut self, min_lines: usize) -> Self {
+        self.config.min_lines = Some(min_lines);
+        self
+    }
+
+    pub fn print0(mut self, print0: bool) -> Self {
+        self.config.print0 = print0;
+        self
+    }
+
+    /* 最初の{}をファイル名に置き換える。{}が無ければ既定の書式で表示する */
+    pub fn header_format(mut self, header_format: impl Into<String>) -> Self {
+        self.config.header_format = header_format.into();
+        self
+    }
+
+    pub fn separator_lines(mut self, separator_lines: usize) -> Self {
+        self.config.separator_lines = separator_lines;
+        self
+    }
+
+    pub fn absolute_paths(mut self, absolute_paths: bool) -> Self {
+        self.config.absolute_paths = absolute_paths;
+        self
+    }
+
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.config.skip_empty = skip_empty;
+        self
+    }
+
+    pub fn dedup_heads(mut self, dedup_heads: bool) -> Self {
+        self.config.dedup_heads = dedup_heads;
+        self
+    }
+
+    pub fn interleave(mut self, interleave: bool) -> Self {
+        self.config.interleave = interleave;
+        self
+    }
+
+    pub fn diff(mut self, diff: bool) -> Self {
+        self.config.diff = diff;
+        self
+    }
+
+    pub fn ignore_errors(mut self, ignore_errors: bool) -> Self {
+        self.config.ignore_errors = ignore_errors;
+        self
+    }
+
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    pub fn force_success(mut self, force_success: bool) -> Self {
+        self.config.force_success = force_success;
+        self
+    }
+
+    pub fn repeat(mut self, interval: std::time::Duration) -> Self {
+        self.config.repeat = Some(interval);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: usize, delay: std::time::Duration) -> Self {
+        self.config.retry = retry;
+        self.config.retry_delay = delay;
+        self
+    }
+
+    pub fn utf8_clean(mut self, utf8_clean: bool) -> Self {
+        self.config.utf8_clean = utf8_clean;
+        self
+    }
+
+    pub fn first_limit(mut self, first_limit: bool) -> Self {
+        self.config.first_limit = first_limit;
+        self
+    }
+
+    pub fn max_buffer(mut self, max_buffer: usize) -> Self {
+        self.config.max_buffer = max_buffer;
+        self
+    }
+
+    pub fn spill_to_disk(mut self, spill_to_disk: bool) -> Self {
+        self.config.spill_to_disk = spill_to_disk;
+        self
+    }
+
+    pub fn limit_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.config.limit_rate = Some(bytes_per_sec);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.config.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn strip_bom(mut self, strip_bom: bool) -> Self {
+        self.config.strip_bom = strip_bom;
+        self
+    }
+
+    pub fn skip(mut self, skip: usize) -> Self {
+        self.config.skip = skip;
+        self
+    }
+
+    pub fn keep_header(mut self, keep_header: bool) -> Self {
+        self.config.keep_header = keep_header;
+        self
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn until(mut self, until: regex::Regex) -> Self {
+        self.config.until = Some(until);
+        self
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn files_with_head_matching(mut self, pattern: regex::Regex) -> Self {
+        self.config.files_with_head_matching = Some(pattern);
+        self
+    }
+
+    pub fn until_exclusive(mut self, until_exclusive: bool) -> Self {
+        self.config.until_exclusive = until_exclusive;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn decode_base64(mut self, decode_base64: bool) -> Self {
+        self.config.decode_base64 = decode_base64;
+        self
+    }
+
+    #[cfg(feature = "decrypt")]
+    pub fn decrypt(mut self, decrypt: bool) -> Self {
+        self.config.decrypt = decrypt;
+        self
+    }
+
+    #[cfg(feature = "decrypt")]
+    pub fn decrypt_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.decrypt_key = Some(path.into());
+        self
+    }
+
+    #[cfg(feature = "watch")]
+    pub fn watch_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.watch_dir = Some(dir.into());
+        self
+    }
+
+    #[cfg(feature = "http")]
+    pub fn cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.cache = Some(dir.into());
+        self
+    }
+
+    #[cfg(feature = "http")]
+    pub fn cache_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.config.cache_ttl = ttl;
+        self
+    }
+
+    #[cfg(feature = "checksum")]
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.config.checksum = Some(checksum);
+        self
+    }
+
+    #[cfg(feature = "checksum")]
+    pub fn checksum_stdout(mut self, checksum_stdout: bool) -> Self {
+        self.config.checksum_stdout = checksum_stdout;
+        self
+    }
+
+    #[cfg(feature = "progress")]
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.config.progress = progress;
+        self
+    }
+
+    pub fn text(mut self, text: bool) -> Self {
+        self.config.text = text;
+        self
+    }
+
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.config.raw = raw;
+        self
+    }
+
+    pub fn validate_utf8(mut self, validate_utf8: bool) -> Self {
+        self.config.validate_utf8 = validate_utf8;
+        self
+    }
+
+    pub fn count(mut self, count: bool) -> Self {
+        self.config.count = count;
+        self
+    }
+
+    pub fn dump_config(mut self, dump_config: bool) -> Self {
+        self.config.dump_config = dump_config;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    pub fn opener<F>(mut self, opener: F) -> Self
+    where
+        F: Fn(&str) -> MyResult<Box<dyn BufRead>> + Send + Sync + 'static,
+    {
+        self.config.opener = Some(Opener(Arc::new(opener)));
+        self
+    }
+
+    /// 行ごとのフィルターを足す。何度でも呼べて、足した順に適用する
+    pub fn filter(mut self, filter: impl LineFilter + 'static) -> Self {
+        self.config.filters.0.push(Arc::new(filter));
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/* 設定ファイルのデフォルト値を読み込み、HEADR_LINESとHEADR_BYTES、コマンドライン引数の順に重ねる */
+pub fn get_args() -> MyResult<Config> {
+    let mut defaults = load_defaults();
+    apply_env_defaults(&mut defaults, |name| std::env::var(name).ok());
+    parse_args(std::env::args_os(), &defaults)
+}
+
+/* 引数を明示的に渡せる版。先頭の要素はプログラム名として扱われる。
+設定ファイルは読まない */
+pub fn get_args_from<I, T>(args: I) -> MyResult<Config>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    parse_args(args, &Defaults::default())
+}
+
+fn parse_args<I, T>(args: I, defaults: &Defaults) -> MyResult<Config>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let formats = enabled_names(&[
+        ("text", true),
+        ("json", cfg!(feature = "json")),
+        ("csv", cfg!(feature = "csv")),
+        ("ndjson", cfg!(feature = "json")),
+    ]);
+    let error_formats =
+        enabled_names(&[("plain", true), ("json", cfg!(feature = "json"))]);
+    let matches = App::new("headr")
+        .version("0.1.0")
+        .author("yo1956")
+        .about("Rust head")
+        .arg(
+            Arg::with_name("files")
+                .value_name("FILES")
+                .help(
+                    "The files to print (\"-\" is standard input; \
+                     it is read only once even if given repeatedly)",
+                )
+                .multiple(true)
+                .default_value("-"),
+        )
+        .arg(
+            Arg::with_name("files0_from")
+                .long("files0-from")
+                .value_name("FILE")
+                .help("Read NUL-separated file names from FILE (\"-\": stdin)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lines")
+                .short("n")
+                .long("lines")
+                .value_name("LINES")
+                .help("Number of lines to print (-N: all but the last N)")
+                .allow_hyphen_values(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .short("c")
+                .long("bytes")
+                .value_name("BYTES")
+                .help(
+                    "Number of bytes to print (-N: all but the last N, \
+                     START-END: bytes START through END); \
+                     accepts suffixes such as K, M, G, KB, MiB",
+                )
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("first_limit")
+                .long("first-limit")
+                .help(
+                    "Allow -n and -c together and stop at whichever \
+                     limit is reached first",
+                )
+                .takes_value(false)
+                .requires("bytes"),
+        )
+        .arg(
+            Arg::with_name("tail_lines")
+                .long("tail-lines")
+                .value_name("LINES")
+                .help("Print all but the last LINES lines (same as -n -LINES)")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_bytes",
+                    "first_limit",
+                ]),
+        )
+        .arg(
+            Arg::with_name("tail_bytes")
+                .long("tail-bytes")
+                .value_name("BYTES")
+                .help("Print all but the last BYTES bytes (same as -c -BYTES)")
+                .takes_value(true)
+                .conflicts_with_all(&["lines", "bytes", "first_limit"]),
+        )
+        .arg(
+            Arg::with_name("chars")
+                .short("C")
+                .long("chars")
+                .value_name("CHARS")
+                .help("Number of characters to print")
+                .takes_value(true)
+                .conflicts_with_all(&["lines", "bytes", "tail_lines", "tail_bytes"]),
+        )
+        .arg(
+            Arg::with_name("percent")
+                .short("p")
+                .long("percent")
+                .value_name("PERCENT")
+                .help("Print the first PERCENT% of the lines")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                ]),
+        )
+        .arg(
+            Arg::with_name("bytes_percent")
+                .long("bytes-percent")
+                .value_name("PERCENT")
+                .help("Print the first PERCENT% of the bytes")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "percent",
+                    "graphemes",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "context",
+                    "paragraph",
+                    "squeeze_blank",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("words")
+                .long("words")
+                .value_name("WORDS")
+                .help("Number of whitespace-delimited words to print")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "percent",
+                ]),
+        )
+        .arg(
+            Arg::with_name("fields")
+                .long("fields")
+                .value_name("N")
+                .help("Print only the first N fields of each line")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "words",
+                ]),
+        )
+        .arg(
+            Arg::with_name("columns")
+                .long("columns")
+                .value_name("RANGES")
+                .help(
+                    "Print only the characters in RANGES of each line, \
+                     such as 1-10,20-30",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "fields",
+                    "raw",
+                ]),
+        )
+        .arg(
+            Arg::with_name("field_sep")
+                .long("field-sep")
+                .value_name("SEP")
+                .help("Field separator for --fields (default: \\t)")
+                .takes_value(true)
+                .requires("fields"),
+        )
+        .arg(
+            Arg::with_name("wrap")
+                .long("wrap")
+                .value_name("WIDTH")
+                .help("Wrap printed lines at WIDTH characters (0: no wrap)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("line_ranges")
+                .long("lines-ranges")
+                .value_name("RANGES")
+                .help("Print only the lines in RANGES, such as 1-3,10,20-22")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "words",
+                ]),
+        )
+        .arg(
+            Arg::with_name("line")
+                .long("line")
+                .value_name("N")
+                .help("Print only line N")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "words",
+                    "line_ranges",
+                ]),
+        )
+        .arg(
+            Arg::with_name("max_total_lines")
+                .long("max-total-lines")
+                .value_name("LINES")
+                .help("Stop after printing LINES lines across all files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_total_bytes")
+                .long("max-total-bytes")
+                .value_name("BYTES")
+                .help("Stop after printing BYTES bytes across all files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("text")
+                .short("a")
+                .long("text")
+                .help("Print binary files instead of skipping them")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("count")
+                .long("count")
+                .help("Print how many lines (bytes with -c) each head has, not the head")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "words",
+                    "hex",
+                    "format",
+                    "interleave",
+                    "output_dir",
+                    "split",
+                ]),
+        )
+        .arg(
+            Arg::with_name("trim")
+                .long("trim")
+                .help(
+                    "Strip leading and trailing whitespace from each line \
+                     (with --skip-empty, lines left empty are dropped)",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "percent",
+                    "bytes_percent",
+                    "raw",
+                    "format",
+                    "trim_start",
+                    "trim_end",
+                ]),
+        )
+        .arg(
+            Arg::with_name("trim_start")
+                .long("trim-start")
+                .help("Strip leading whitespace from each line")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "percent",
+                    "bytes_percent",
+                    "raw",
+                    "format",
+                    "trim",
+                    "trim_end",
+                ]),
+        )
+        .arg(
+            Arg::with_name("trim_end")
+                .long("trim-end")
+                .help("Strip trailing whitespace from each line")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "percent",
+                    "bytes_percent",
+                    "raw",
+                    "format",
+                    "trim",
+                    "trim_start",
+                ]),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .help(
+                    "Copy lines byte for byte without reading them as UTF-8 \
+                     (--stats then reports bytes only)",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "fields",
+                    "wrap",
+                    "encoding",
+                    "utf8_clean",
+                    "strip_bom",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("validate_utf8")
+                .long("validate-utf8")
+                .help(
+                    "Fail at the first invalid UTF-8 byte in the printed head \
+                     instead of replacing it",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&["chars", "graphemes", "hex", "format"]),
+        )
+        .arg(
+            Arg::with_name("skip")
+                .long("skip")
+                .value_name("N")
+                .help("Discard the first N lines (N bytes with -c) first")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep_header")
+                .long("keep-header")
+                .help(
+                    "Always print the first line, then apply -n and --skip \
+                     to the lines after it",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "bytes_percent",
+                    "words",
+                    "paragraph",
+                    "use_index",
+                    "format",
+                    "interleave",
+                    "count",
+                ]),
+        )
+        .arg(
+            Arg::with_name("strip_bom")
+                .long("strip-bom")
+                .help("Skip a leading UTF-8 byte order mark")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("hex")
+                .long("hex")
+                .help(
+                    "Print the bytes as a canonical hexdump \
+                     (with -c; offsets restart for each file)",
+                )
+                .takes_value(false)
+                .requires("bytes"),
+        )
+        .arg(
+            Arg::with_name("utf8_clean")
+                .long("utf8-clean")
+                .help(
+                    "With -c, finish a UTF-8 character cut at the end \
+                     by printing its remaining bytes",
+                )
+                .takes_value(false)
+                .requires("bytes"),
+        )
+        .arg(
+            Arg::with_name("unix_lines")
+                .long("unix-lines")
+                .help("Print CRLF line endings as LF (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("show_ends")
+                .short("E")
+                .long("show-ends")
+                .help("Print $ at the end of each line (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("show_tabs")
+                .short("T")
+                .long("show-tabs")
+                .help("Print TAB characters as ^I (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("squeeze_blank")
+                .short("s")
+                .long("squeeze-blank")
+                .help("Print runs of empty lines as one (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("paragraph")
+                .long("paragraph")
+                .help("Count blank-line separated paragraphs instead of lines")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "squeeze_blank",
+                    "context",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("reverse_lines")
+                .long("reverse-lines")
+                .help("Print the selected lines of each file in reverse order")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "bytes_percent",
+                    "words",
+                    "paragraph",
+                    "context",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("byte_offset")
+                .long("byte-offset")
+                .help(
+                    "Print the byte offset of each line in its file \
+                     before the line (after -N's number)",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "bytes_percent",
+                    "words",
+                    "paragraph",
+                    "context",
+                    "use_index",
+                    "format",
+                    "interleave",
+                    "diff",
+                ]),
+        )
+        .arg(
+            Arg::with_name("context")
+                .long("context")
+                .value_name("N")
+                .help("Also print the last N lines, after a ... line")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help(
+                    "Report the lines, characters, graphemes and bytes printed \
+                     for each file on stderr",
+                )
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("timing")
+                .long("timing")
+                .help(
+                    "Report how long each file took to read on stderr, \
+                     then the total and the slowest file",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&["format", "count", "interleave", "diff", "jobs"]),
+        )
+        .arg(
+            Arg::with_name("total_lines")
+                .long("total-lines")
+                .help(
+                    "Also report each file's total line count with --stats \
+                     (reads every file to the end)",
+                )
+                .takes_value(false)
+                .requires("stats")
+                .conflicts_with_all(&["exact", "min_lines", "jobs"]),
+        )
+        .arg(
+            Arg::with_name("with_total")
+                .long("with-total")
+                .help(
+                    "Print \"(M lines total)\" after each file's lines \
+                     (reads every file to the end; not with -c)",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "exact",
+                    "min_lines",
+                    "jobs",
+                    "format",
+                    "count",
+                    "interleave",
+                    "diff",
+                ]),
+        )
+        .arg(
+            Arg::with_name("pretty_bytes")
+                .long("pretty-bytes")
+                .value_name("UNITS")
+                .help(
+                    "Show --stats byte counts with units \
+                     (iec: 1.0 KiB per 1024, si: 1.0 kB per 1000; default iec)",
+                )
+                .possible_values(&["iec", "si"])
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .requires("stats"),
+        )
+        .arg(
+            Arg::with_name("line_buffered")
+                .long("line-buffered")
+                .help("Flush the output after each line (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("atomic_headers")
+                .long("atomic-headers")
+                .help("Write each file's header and content in a single write")
+                .takes_value(false)
+                .conflicts_with_all(&["line_buffered", "format", "interleave"]),
+        )
+        .arg(
+            Arg::with_name("fail_fast")
+                .long("fail-fast")
+                .help("Stop at the first file that cannot be read")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ignore_errors")
+                .long("ignore-errors")
+                .help("Do not report files that could not be read")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("force_success")
+                .long("force-success")
+                .help("Exit successfully even if some files could not be read")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("repeat")
+                .long("repeat")
+                .value_name("SECONDS")
+                .help("Print again every SECONDS seconds until interrupted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("MILLIS")
+                .help("Fail if stdin or a FIFO sends no data for MILLIS ms")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry")
+                .long("retry")
+                .value_name("N")
+                .help("Retry opening a file up to N times on transient errors")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry_delay")
+                .long("retry-delay")
+                .value_name("MILLIS")
+                .help("Wait MILLIS ms between --retry attempts [default: 100]")
+                .takes_value(true)
+                .requires("retry"),
+        )
+        .arg(
+            Arg::with_name("max_buffer")
+                .long("max-buffer")
+                .value_name("BYTES")
+                .help("Hold at most BYTES bytes for negative counts [default: 1G]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("spill_to_disk")
+                .long("spill-to-disk")
+                .help("Keep input beyond --max-buffer in a temporary file instead of failing")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("limit_rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Read at most RATE bytes per second (e.g. 1M)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chunk_size")
+                .long("chunk-size")
+                .value_name("BYTES")
+                .help("Read files in chunks of BYTES bytes [default: 64K]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print0")
+                .long("print0")
+                .help("Separate files with a NUL byte instead of headers")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("interleave")
+                .long("interleave")
+                .help("Print line 1 of every file, then line 2, and so on")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "format",
+                    "print0",
+                    "min_lines",
+                    "context",
+                ]),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .help(
+                    "Compare two files line by line and print the first N \
+                     lines that differ, prefixed with a: and b:",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "graphemes",
+                    "percent",
+                    "bytes_percent",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "paragraph",
+                    "context",
+                    "format",
+                    "interleave",
+                    "count",
+                    "print0",
+                    "min_lines",
+                    "reverse_lines",
+                    "keep_header",
+                ]),
+        )
+        .arg(
+            Arg::with_name("no_final_newline")
+                .long("no-final-newline")
+                .help("Do not print the newline after the last line of all output (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Print the files that would be read and the limits, then exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("header_format")
+                .long("header-format")
+                .value_name("TEMPLATE")
+                .help("Header template; {} is replaced by the file name")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("absolute_paths")
+                .long("absolute-paths")
+                .help("Show absolute paths in the headers")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("separator_lines")
+                .long("separator-lines")
+                .value_name("N")
+                .help("Number of empty lines before each header after the first [default: 1]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip_empty")
+                .long("skip-empty")
+                .visible_alias("quiet-empty")
+                .help("Omit the header for files with no output")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dedup_heads")
+                .long("dedup-heads")
+                .visible_alias("merge-identical-heads")
+                .help(
+                    "Print only the header, with the earlier file's name, \
+                     for a head identical to one already printed",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "format",
+                    "count",
+                    "interleave",
+                    "diff",
+                    "jobs",
+                    "print0",
+                ]),
+        )
+        .arg(
+            Arg::with_name("exact")
+                .long("exact")
+                .help("Fail if a file has fewer lines or bytes than requested")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("min_lines")
+                .long("min-lines")
+                .value_name("N")
+                .help("Fail if a file has fewer than N lines in total")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("number")
+                .short("N")
+                .long("number")
+                .help("Number output lines (line mode only)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help("Write output to FILE instead of stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tee")
+                .long("tee")
+                .value_name("FILE")
+                .help("Also write output to FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output_dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Write each file's head to the same relative path under DIR")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "output",
+                    "tee",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("build_index")
+                .long("build-index")
+                .help("Write a FILE.headr-idx index of line offsets and exit")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "use_index",
+                    "output",
+                    "output_dir",
+                    "format",
+                    "interleave",
+                    "count",
+                ]),
+        )
+        .arg(
+            Arg::with_name("use_index")
+                .long("use-index")
+                .help("Seek past --skip lines with FILE.headr-idx when it is up to date")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("split")
+                .long("split")
+                .value_name("LINES")
+                .help("Write output to numbered files of LINES lines each")
+                .takes_value(true)
+                .conflicts_with_all(&["output", "output_dir"]),
+        )
+        .arg(
+            Arg::with_name("split_prefix")
+                .long("split-prefix")
+                .value_name("PREFIX")
+                .help("File name prefix for --split [default: x]")
+                .takes_value(true)
+                .requires("split"),
+        )
+        .arg(
+            Arg::with_name("split_width")
+                .long("split-width")
+                .value_name("WIDTH")
+                .help("Number of digits in --split file names [default: 3]")
+                .takes_value(true)
+                .requires("split"),
+        )
+        .arg(
+            Arg::with_name("glob")
+                .long("glob")
+                .help("Expand wildcards in file names (default on Windows)")
+                .overrides_with("no_glob"),
+        )
+        .arg(
+            Arg::with_name("no_glob")
+                .long("no-glob")
+                .help("Take file names literally (default on Unix)")
+                .overrides_with("glob"),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .short("r")
+                .long("recursive")
+                .help("Read all files under each directory, recursively")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Skip files whose name or path matches PATTERN (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("sort_files")
+                .long("sort-files")
+                .help("Process the files in byte-wise name order (- first)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .help("Sort the files in reverse order with --sort-files")
+                .takes_value(false)
+                .requires("sort_files"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Colorize file headers")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help(
+                    "Output format (json: an array of per-file objects, \
+                     csv: file,line_number,content rows, \
+                     ndjson: one object per line; csv and ndjson lines only)",
+                )
+                .possible_values(&formats)
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("error_format")
+                .long("error-format")
+                .value_name("FORMAT")
+                .help(
+                    "How to report per-file errors on stderr \
+                     (json: one {\"file\", \"error\", \"kind\"} object per line)",
+                )
+                .possible_values(&error_formats)
+                .default_value("plain"),
+        )
+        .arg(
+            Arg::with_name("pager")
+                .long("pager")
+                .value_name("WHEN")
+                .help("Page the output through $PAGER (default: less)")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .aliases(&["silent", "no-filename"])
+                .help("Never print headers giving file names")
+                .takes_value(false)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Always print headers giving file names")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("zero_terminated")
+                .short("z")
+                .long("zero-terminated")
+                .help("Line delimiter is NUL, not newline")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("null_data")
+                .long("null-data")
+                .help(
+                    "Count NUL-terminated binary records with -n and copy them \
+                     byte for byte (a last record without NUL is printed as is)",
+                )
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_bytes",
+                    "delimiter",
+                    "chars",
+                    "graphemes",
+                    "words",
+                    "fields",
+                    "columns",
+                    "wrap",
+                    "trim",
+                    "trim_start",
+                    "trim_end",
+                    "encoding",
+                    "strip_bom",
+                    "show_ends",
+                    "show_tabs",
+                    "unix_lines",
+                    "validate_utf8",
+                    "format",
+                    "interleave",
+                ]),
+        )
+        .arg(
+            Arg::with_name("delimiter")
+                .short("d")
+                .long("delimiter")
+                .value_name("CHAR")
+                .help("Use the single byte CHAR as the line delimiter")
+                .takes_value(true)
+                .conflicts_with("zero_terminated"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("Read up to N files concurrently")
+                .default_value("1"),
+        )
+        .args(&optional_args())
+        .get_matches_from(args);
+
+    let lines = match matches.value_of("tail_lines") {
+        Some(val) => Some(parse_tail_lines(val)?),
+        None => matches
+            .value_of("lines")
+            .map(parse_count) // OptionがSomeの場合にのみ関数を適用し、Noneの場合は何もしない
+            .transpose()?, //Option<Result>をResult<Option>に変換する
+    };
+
+    let bytes = match matches.value_of("tail_bytes") {
+        Some(val) => Some(ByteSpec::Count(parse_tail_bytes(val)?)),
+        None => matches.value_of("bytes").map(parse_byte_spec).transpose()?,
+    };
+    let first_limit = matches.is_present("first_limit");
+    /* -nと-cを一緒に使えるのは--first-limitのときだけ */
+    if !first_limit && matches.occurrences_of("lines") > 0 && bytes.is_some() {
+        return Err(HeadError::InvalidArgument(
+            "The argument '--lines <LINES>' cannot be used with \
+             '--bytes <BYTES>'"
+                .to_string(),
+        ));
+    }
+    if matches.is_present("context")
+        && matches!(lines, Some(Count::AllButLast(_)))
+    {
+        return Err(HeadError::InvalidArgument(
+            "--context needs a positive line count".to_string(),
+        ));
+    }
+    if matches.is_present("interleave")
+        && matches!(lines, Some(Count::AllButLast(_)))
+    {
+        return Err(HeadError::InvalidArgument(
+            "--interleave needs a positive line count".to_string(),
+        ));
+    }
+    if matches.is_present("byte_offset")
+        && (matches!(lines, Some(Count::AllButLast(_)))
+            || matches.is_present("tail_lines")
+            || matches.is_present("until"))
+    {
+        return Err(HeadError::InvalidArgument(
+            "--byte-offset needs a positive line count".to_string(),
+        ));
+    }
+    if matches.is_present("diff")
+        && matches!(lines, Some(Count::AllButLast(_)))
+    {
+        return Err(HeadError::InvalidArgument(
+            "--diff needs a positive line count".to_string(),
+        ));
+    }
+    if first_limit
+        && !matches!(
+            (lines, bytes),
+            (
+                Some(Count::First(_)),
+                Some(ByteSpec::Count(Count::First(_)))
+            )
+        )
+    {
+        return Err(HeadError::InvalidArgument(
+            "--first-limit needs positive counts for -n and -c".to_string(),
+        ));
+    }
+
+    let mut files = match matches.value_of("files0_from") {
+        /* --files0-fromの名前は展開せずにそのまま使う */
+        Some(list) => {
+            if matches.occurrences_of("files") > 0 {
+                return Err(HeadError::InvalidArgument(
+                    "The argument '--files0-from <FILE>' cannot be used with \
+                     '<FILES>...'"
+                        .to_string(),
+                ));
+            }
+            read_files0(list)?
+        }
+        None => {
+            let files = matches
+                .values_of_lossy("files")
+                .unwrap() // filesは少なくとも1つの値を持っているはずなので、unwrapしても問題ない
+                .iter()
+                .map(|filename| expand_path(filename))
+                .collect::<MyResult<Vec<_>>>()?;
+            if expand_wildcards(&matches) {
+                expand_globs(files)?
+            } else {
+                files
+            }
+        }
+    };
+    let mut walk_errors = Vec::new();
+    if matches.is_present("recursive") {
+        files = expand_dirs(files, &mut walk_errors);
+    }
+    if let Some(patterns) = matches.values_of("exclude") {
+        files = exclude_files(files, patterns)?;
+    }
+    if matches.is_present("sort_files") {
+        sort_files(&mut files, matches.is_present("reverse"));
+    }
+    /* --clipboardはclip:を入力に加える。ファイルが無ければ標準入力の代わりにする */
+    if matches.is_present("clipboard") {
+        if matches.occurrences_of("files") == 0
+            && !matches.is_present("files0_from")
+        {
+            files.clear();
+        }
+        files.push("clip:".to_string());
+    }
+    dedup_stdin(&mut files);
+    let implicit_stdin = matches.occurrences_of("files") == 0
+        && !matches.is_present("files0_from")
+        && !matches.is_present("clipboard");
+
+    let mut config = Config {
+        files,
+        implicit_stdin,
+        walk_errors,
+        lines: lines.unwrap(),
+        bytes,
+        quiet: matches.is_present("quiet"),
+        verbose: matches.is_present("verbose"),
+        /* --null-dataはバイナリのレコードなので、UTF-8として読まず、バイナリかどうかも調べない。
+        NULで終わらない最後のレコードも1件と数え、NULを足さずにそのまま出力する */
+        zero_terminated: matches.is_present("zero_terminated")
+            || matches.is_present("null_data"),
+        delimiter: matches
+            .value_of("delimiter")
+            .map(parse_delimiter)
+            .transpose()?,
+        jobs: matches
+            .value_of("jobs")
+            .map(parse_jobs)
+            .transpose()?
+            .unwrap(),
+        chars: matches
+            .value_of("chars")
+            .map(parse_char_count)
+            .transpose()?,
+        graphemes: matches
+            .value_of("graphemes")
+            .map(parse_grapheme_count)
+            .transpose()?,
+        words: matches
+            .value_of("words")
+            .map(parse_word_count)
+            .transpose()?,
+        fields: matches
+            .value_of("fields")
+            .map(parse_field_count)
+            .transpose()?,
+        columns: matches
+            .value_of("columns")
+            .map(parse_column_ranges)
+            .transpose()?,
+        trim: if matches.is_present("trim") {
+            Some(Trim::Both)
+        } else if matches.is_present("trim_start") {
+            Some(Trim::Start)
+        } else if matches.is_present("trim_end") {
+            Some(Trim::End)
+        } else {
+            None
+        },
+        field_sep: matches
+            .value_of("field_sep")
+            .map(parse_field_sep)
+            .transpose()?
+            .unwrap_or('\t'),
+        wrap: matches
+            .value_of("wrap")
+            .map(parse_wrap)
+            .transpose()?
+            .unwrap_or(0),
+        line_ranges: match matches.value_of("line") {
+            Some(val) => {
+                parse_line_number(val).map(|num| Some(vec![(num, num)]))?
+            }
+            None => matches
+                .value_of("line_ranges")
+                .map(parse_line_ranges)
+                .transpose()?,
+        },
+        number: matches.is_present("number"),
+        unix_lines: matches.is_present("unix_lines"),
+        show_ends: matches.is_present("show_ends"),
+        show_tabs: matches.is_present("show_tabs"),
+        squeeze_blank: matches.is_present("squeeze_blank"),
+        paragraph: matches.is_present("paragraph"),
+        reverse_lines: matches.is_present("reverse_lines"),
+        byte_offset: matches.is_present("byte_offset"),
+        no_final_newline: matches.is_present("no_final_newline"),
+        context: matches.value_of("context").map(parse_context).transpose()?,
+        hex: matches.is_present("hex"),
+        #[cfg(feature = "encoding")]
+        encoding: matches
+            .value_of("encoding")
+            .map(parse_encoding)
+            .transpose()?,
+        stats: matches.is_present("stats"),
+        timing: matches.is_present("timing"),
+        total_lines: matches.is_present("total_lines"),
+        with_total: matches.is_present("with_total"),
+        pretty_bytes: match matches.value_of("pretty_bytes") {
+            Some("si") => Some(ByteUnits::Si),
+            _ if matches.is_present("pretty_bytes") => Some(ByteUnits::Iec),
+            _ => None,
+        },
+        line_buffered: matches.is_present("line_buffered"),
+        atomic_headers: matches.is_present("atomic_headers"),
+        exact: matches.is_present("exact"),
+        min_lines: matches
+            .value_of("min_lines")
+            .map(parse_min_lines)
+            .transpose()?,
+        print0: matches.is_present("print0"),
+        header_format: matches
+            .value_of("header_format")
+            .map(parse_header_format)
+            .transpose()?
+            .unwrap_or_else(|| DEFAULT_HEADER_FORMAT.to_string()),
+        separator_lines: matches
+            .value_of("separator_lines")
+            .map(parse_separator_lines)
+            .transpose()?
+            .unwrap_or(1),
+        absolute_paths: matches.is_present("absolute_paths"),
+        skip_empty: matches.is_present("skip_empty"),
+        dedup_heads: matches.is_present("dedup_heads"),
+        interleave: matches.is_present("interleave"),
+        diff: matches.is_present("diff"),
+        ignore_errors: matches.is_present("ignore_errors"),
+        fail_fast: matches.is_present("fail_fast"),
+        force_success: matches.is_present("force_success"),
+        first_limit,
+        utf8_clean: matches.is_present("utf8_clean"),
+        repeat: matches.value_of("repeat").map(parse_interval).transpose()?,
+        timeout: matches.value_of("timeout").map(parse_timeout).transpose()?,
+        retry: matches
+            .value_of("retry")
+            .map(parse_retry)
+            .transpose()?
+            .unwrap_or(0),
+        retry_delay: matches
+            .value_of("retry_delay")
+            .map(parse_retry_delay)
+            .transpose()?
+            .unwrap_or(std::time::Duration::from_millis(100)),
+        max_buffer: matches
+            .value_of("max_buffer")
+            .map(parse_max_buffer)
+            .transpose()?
+            .unwrap_or(DEFAULT_MAX_BUFFER),
+        spill_to_disk: matches.is_present("spill_to_disk"),
+        limit_rate: matches
+            .value_of("limit_rate")
+            .map(parse_limit_rate)
+            .transpose()?,
+        chunk_size: matches
+            .value_of("chunk_size")
+            .map(parse_chunk_size)
+            .transpose()?
+            .unwrap_or(DEFAULT_CHUNK_SIZE),
+        strip_bom: matches.is_present("strip_bom"),
+        skip: matches
+            .value_of("skip")
+            .map(parse_skip)
+            .transpose()?
+            .unwrap_or(0),
+        keep_header: matches.is_present("keep_header"),
+        #[cfg(feature = "regex")]
+        until: matches.value_of("until").map(parse_regex).transpose()?,
+        until_exclusive: matches.is_present("until_exclusive"),
+        #[cfg(feature = "regex")]
+        files_with_head_matching: matches
+            .value_of("files_with_head_matching")
+            .map(parse_regex)
+            .transpose()?,
+        #[cfg(feature = "decode")]
+        decode_base64: matches.value_of("decode") == Some("base64"),
+        #[cfg(feature = "decrypt")]
+        decrypt: matches.is_present("decrypt"),
+        #[cfg(feature = "decrypt")]
+        decrypt_key: matches.value_of_os("decrypt_key").map(PathBuf::from),
+        #[cfg(feature = "watch")]
+        watch_dir: matches.value_of_os("watch_dir").map(PathBuf::from),
+        #[cfg(feature = "http")]
+        cache: matches.value_of_os("cache").map(PathBuf::from),
+        #[cfg(feature = "http")]
+        cache_ttl: matches
+            .value_of("cache_ttl")
+            .map(parse_cache_ttl)
+            .transpose()?
+            .unwrap_or(DEFAULT_CACHE_TTL),
+        #[cfg(feature = "checksum")]
+        checksum: match matches.value_of("checksum") {
+            Some("sha256") => Some(Checksum::Sha256),
+            Some("md5") => Some(Checksum::Md5),
+            _ => None,
+        },
+        #[cfg(feature = "checksum")]
+        checksum_stdout: matches.is_present("checksum_stdout"),
+        #[cfg(feature = "progress")]
+        progress: matches.is_present("progress"),
+        text: matches.is_present("text") || matches.is_present("null_data"),
+        raw: matches.is_present("raw") || matches.is_present("null_data"),
+        validate_utf8: matches.is_present("validate_utf8"),
+        count: matches.is_present("count"),
+        dump_config: matches.is_present("dump_config"),
+        dry_run: matches.is_present("dry_run"),
+        opener: None,
+        filters: LineFilters::default(),
+        output: matches.value_of("output").map(String::from),
+        tee: matches.value_of("tee").map(String::from),
+        output_dir: matches.value_of("output_dir").map(String::from),
+        build_index: matches.is_present("build_index"),
+        use_index: matches.is_present("use_index"),
+        split: matches.value_of("split").map(parse_split).transpose()?,
+        split_prefix: matches
+            .value_of("split_prefix")
+            .unwrap_or("x")
+            .to_string(),
+        split_width: matches
+            .value_of("split_width")
+            .map(parse_split_width)
+            .transpose()?
+            .unwrap_or(3),
+        percent: matches.value_of("percent").map(parse_percent).transpose()?,
+        bytes_percent: matches
+            .value_of("bytes_percent")
+            .map(parse_percent)
+            .transpose()?,
+        max_total_lines: matches
+            .value_of("max_total_lines")
+            .map(parse_positive_int)
+            .transpose()?,
+        max_total_bytes: matches
+            .value_of("max_total_bytes")
+            .map(|val| match parse_byte_count(val)? {
+                Count::First(num) => Ok(num),
+                Count::AllButLast(_) => {
+                    Err(HeadError::InvalidByteCount(val.to_string()))
+                }
+            })
+            .transpose()?,
+        color: match matches.value_of("color") {
+            Some("always") => ColorChoice::Always,
+            Some("never") => ColorChoice::Never,
+            _ => ColorChoice::Auto,
+        },
+        format: match matches.value_of("format") {
+            #[cfg(feature = "json")]
+            Some("json") => OutputFormat::Json,
+            #[cfg(feature = "csv")]
+            Some("csv") => OutputFormat::Csv,
+            #[cfg(feature = "json")]
+            Some("ndjson") => OutputFormat::Ndjson,
+            _ => OutputFormat::Text,
+        },
+        error_format: match matches.value_of("error_format") {
+            #[cfg(feature = "json")]
+            Some("json") => ErrorFormat::Json,
+            _ => ErrorFormat::Plain,
+        },
+        pager: match matches.value_of("pager") {
+            Some("always") => PagerChoice::Always,
+            Some("never") => PagerChoice::Never,
+            _ => PagerChoice::Auto,
+        },
+    };
+    apply_defaults(&mut config, &matches, defaults);
+    /* CSVとNDJSONは1行を1つの行にするので、行単位の出力にだけ使える */
+    let per_line: Option<&str> = match config.format {
+        #[cfg(feature = "csv")]
+        OutputFormat::Csv => Some("csv"),
+        #[cfg(feature = "json")]
+        OutputFormat::Ndjson => Some("ndjson"),
+        #[cfg(feature = "json")]
+        OutputFormat::Json => None,
+        OutputFormat::Text => None,
+    };
+    if let Some(format) = per_line.filter(|_| {
+        config.byte_mode()
+            || config.chars.is_some()
+            || config.graphemes.is_some()
+            || config.words.is_some()
+    }) {
+        return Err(HeadError::InvalidArgument(format!(
+            "--format {} works only with lines, not bytes, characters \
+             or words",
+            format
+        )));
+    }
+    log::debug!("files: {:?}", config.files);
+    log::trace!("config: {:?}", config);
+    Ok(config)
+}
+
+/* --formatなどの値のうち、有効になっている機能で使えるものだけを返す */
+fn enabled_names(names: &[(&'static str, bool)]) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter_map(|&(name, enabled)| enabled.then_some(name))
+        .collect()
+}
+
+/* 機能フラグで有効になる引数 */
+fn optional_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = Vec::new();
+    if cfg!(feature = "serde") {
+        args.push(
+            Arg::with_name("dump_config")
+                .long("dump-config")
+                .help("Print the effective configuration as JSON and exit")
+                .takes_value(false),
+        );
+    }
+    if cfg!(feature = "regex") {
+        args.push(
+            Arg::with_name("until")
+                .long("until")
+                .value_name("PATTERN")
+                .help("Print lines until one matches PATTERN (overrides -n)")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "percent",
+                    "words",
+                    "line_ranges",
+                    "line",
+                    "context",
+                    "raw",
+                ]),
+        );
+        args.push(
+            Arg::with_name("until_exclusive")
+                .long("until-exclusive")
+                .help("Do not print the line matched by --until")
+                .takes_value(false)
+                .requires("until"),
+        );
+        args.push(
+            Arg::with_name("files_with_head_matching")
+                .long("files-with-head-matching")
+                .value_name("PATTERN")
+                .help(
+                    "Print only the names of files whose head has a line \
+                     matching PATTERN",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "format",
+                    "count",
+                    "interleave",
+                    "diff",
+                    "until",
+                    "stats",
+                    "hex",
+                ]),
+        );
+    }
+    if cfg!(feature = "checksum") {
+        args.push(
+            Arg::with_name("checksum")
+                .long("checksum")
+                .value_name("ALGORITHM")
+                .help("Print a hash of the bytes printed for each file")
+                .possible_values(&["sha256", "md5"])
+                .takes_value(true),
+        );
+        args.push(
+            Arg::with_name("checksum_stdout")
+                .long("checksum-stdout")
+                .help("Print the --checksum lines on stdout instead of stderr")
+                .takes_value(false)
+                .requires("checksum"),
+        );
+    }
+    if cfg!(feature = "graphemes") {
+        args.push(
+            Arg::with_name("graphemes")
+                .long("graphemes")
+                .value_name("N")
+                .help(
+                    "Number of grapheme clusters (user-perceived characters)",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "lines",
+                    "bytes",
+                    "tail_lines",
+                    "tail_bytes",
+                    "chars",
+                    "percent",
+                    "words",
+                ]),
+        );
+    }
+    if cfg!(feature = "encoding") {
+        args.push(
+            Arg::with_name("encoding")
+                .long("encoding")
+                .value_name("NAME")
+                .help("Decode the input from NAME (e.g. latin1, shift_jis)")
+                .takes_value(true),
+        );
+    }
+    if cfg!(feature = "decode") {
+        args.push(
+            Arg::with_name("decode")
+                .long("decode")
+                .value_name("ENCODING")
+                .help("Decode each input before taking its head")
+                .possible_values(&["base64"])
+                .takes_value(true),
+        );
+    }
+    if cfg!(feature = "decrypt") {
+        args.push(
+            Arg::with_name("decrypt")
+                .long("decrypt")
+                .help(
+                    "Decrypt age or armored gpg input before taking its head",
+                )
+                .takes_value(false),
+        );
+        args.push(
+            Arg::with_name("decrypt_key")
+                .long("decrypt-key")
+                .value_name("FILE")
+                .help("Read age identities from FILE for --decrypt")
+                .takes_value(true)
+                .requires("decrypt"),
+        );
+    }
+    if cfg!(feature = "watch") {
+        args.push(
+            Arg::with_name("watch_dir")
+                .long("watch-dir")
+                .value_name("DIR")
+                .help(
+                    "Print the head of each new file created in DIR \
+                     until interrupted",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "output",
+                    "output_dir",
+                    "split",
+                    "tee",
+                    "repeat",
+                    "build_index",
+                    "format",
+                    "interleave",
+                    "diff",
+                    "count",
+                    "dry_run",
+                ]),
+        );
+    }
+    if cfg!(feature = "http") {
+        args.push(
+            Arg::with_name("cache")
+                .long("cache")
+                .value_name("DIR")
+                .help("Keep what was read from URLs in DIR and reuse it while fresh")
+                .takes_value(true),
+        );
+        args.push(
+            Arg::with_name("cache_ttl")
+                .long("cache-ttl")
+                .value_name("SECONDS")
+                .help(
+                    "Reuse --cache entries for SECONDS seconds unless \
+                     Cache-Control says otherwise [default: 300]",
+                )
+                .takes_value(true)
+                .requires("cache"),
+        );
+    }
+    if cfg!(feature = "clipboard") {
+        args.push(
+            Arg::with_name("clipboard")
+                .long("clipboard")
+                .help("Read the clipboard (same as the file name clip:)")
+                .takes_value(false),
+        );
+    }
+    if cfg!(feature = "progress") {
+        args.push(
+            Arg::with_name("progress")
+                .long("progress")
+                .help(
+                    "With -c, show the bytes read and the throughput \
+                     on stderr when it is a terminal",
+                )
+                .takes_value(false)
+                .requires("bytes"),
+        );
+    }
+    args
+}
+
+/* 設定ファイルの生の値。lines = 20 のような数値と "1K" のような文字列の両方を受け付ける。
+設定ファイルはconfig機能を有効にしたときだけ読む */
+#[cfg(feature = "config")]
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawDefaults {
+    lines: Option<RawValue>,
+    bytes: Option<RawValue>,
+    chars: Option<usize>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+    zero_terminated: Option<bool>,
+    number: Option<bool>,
+    jobs: Option<usize>,
+    color: Option<String>,
+}
+
+#[cfg(feature = "config")]
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum RawValue {
+    Int(i64),
+    Str(String),
+}
+
+#[cfg(feature = "config")]
+impl RawValue {
+    fn to_arg(&self) -> String {
+        match self {
+            RawValue::Int(num) => num.to_string(),
+            RawValue::Str(val) => val.clone(),
+        }
+    }
+}
+
+/* 設定ファイルから読み込んだ、検証済みのデフォルト値 */
+#[derive(Debug, Default, PartialEq)]
+struct Defaults {
+    lines: Option<Count>,
+    bytes: Option<ByteSpec>,
+    chars: Option<usize>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+    zero_terminated: Option<bool>,
+    number: Option<bool>,
+    jobs: Option<usize>,
+    color: Option<ColorChoice>,
+}
+
+/* HEADR_CONFIGがあればそれを、なければ ~/.config/headr/config.toml を使う */
+#[cfg(feature = "config")]
+fn config_path() -> Option<(PathBuf, bool)> {
+    match std::env::var_os("HEADR_CONFIG") {
+        Some(path) => Some((PathBuf::from(path), true)),
+        None => std::env::var_os("HOME").map(|home| {
+            (Path::new(&home).join(".config/headr/config.toml"), false)
+        }),
+    }
+}
+
+/* 設定ファイルの問題は警告するだけで、処理は止めない */
+#[cfg(feature = "config")]
+fn load_defaults() -> Defaults {
+    let (path, explicit) = match config_path() {
+        Some(found) => found,
+        None => return Defaults::default(),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        // 既定の場所にファイルが無いのは普通のことなので黙って無視する
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+            return Defaults::default()
+        }
+        Err(e) => {
+            eprintln!("headr: {}: {}", path.display(), e);
+            return Defaults::default();
+        }
+    };
+    parse_defaults(&text).unwrap_or_else(|e| {
+        eprintln!("headr: {}: {}", path.display(), e);
+        Defaults::default()
+    })
+}
+
+/* config機能が無ければ設定ファイルは読まない。HEADR_CONFIGで指定されていたら、読まないことを警告する */
+#[cfg(not(feature = "config"))]
+fn load_defaults() -> Defaults {
+    if std::env::var_os("HEADR_CONFIG").is_some() {
+        eprintln!("headr: HEADR_CONFIG: config files need the config feature");
+    }
+    Defaults::default()
+}
+
+/* 環境変数の-nと-cのデフォルト値は設定ファイルより優先する。出力の単位は1つの設定なので、
+指定があれば設定ファイルの単位は使わない。不正な値は警告して無視する */
+fn apply_env_defaults(
+    defaults: &mut Defaults,
+    var: impl Fn(&str) -> Option<String>,
+) {
+    let lines = var("HEADR_LINES").and_then(|val| {
+        parse_count(&val)
+            .map_err(|e| eprintln!("headr: HEADR_LINES: {}", e))
+            .ok()
+    });
+    let bytes = var("HEADR_BYTES").and_then(|val| {
+        parse_byte_spec(&val)
+            .map_err(|e| eprintln!("headr: HEADR_BYTES: {}", e))
+            .ok()
+    });
+    if lines.is_some() || bytes.is_some() {
+        defaults.lines = lines;
+        defaults.bytes = bytes;
+        defaults.chars = None;
+    }
+}
+
+#[cfg(feature = "config")]
+fn parse_defaults(text: &str) -> MyResult<Defaults> {
+    let raw: RawDefaults = toml::from_str(text)
+        .map_err(|e| HeadError::InvalidArgument(e.message().to_string()))?;
+    Ok(Defaults {
+        lines: raw
+            .lines
+            .map(|val| parse_count(&val.to_arg()))
+            .transpose()?,
+        bytes: raw
+            .bytes
+            .map(|val| parse_byte_spec(&val.to_arg()))
+            .transpose()?,
+        chars: raw
+            .chars
+            .map(|num| parse_char_count(&num.to_string()))
+            .transpose()?,
+        quiet: raw.quiet,
+        verbose: raw.verbose,
+        zero_terminated: raw.zero_terminated,
+        number: raw.number,
+        jobs: raw
+            .jobs
+            .map(|num| parse_jobs(&num.to_string()))
+            .transpose()?,
+        color: raw
+            .color
+            .map(|val| match val.as_str() {
+                "auto" => Ok(ColorChoice::Auto),
+                "always" => Ok(ColorChoice::Always),
+                "never" => Ok(ColorChoice::Never),
+                _ => Err(HeadError::InvalidArgument(format!(
+                    "invalid color -- {}",
+                    val
+                ))),
+            })
+            .transpose()?,
+    })
+}
+
+/* コマンドラインで指定されなかった項目だけをデフォルト値で埋める */
+fn apply_defaults(
+    config: &mut Config,
+    matches: &ArgMatches,
+    defaults: &Defaults,
+) {
+    let given = |name: &str| matches.occurrences_of(name) > 0;
+
+    // 出力の単位はまとめて1つの設定なので、どれか1つでも指定されたら使わない
+    if ![
+        "lines",
+        "bytes",
+        "tail_lines",
+        "tail_bytes",
+        "chars",
+        "graphemes",
+        "percent",
+        "bytes_percent",
+        "words",
+        "line_ranges",
+        "line",
+    ]
+    .iter()
+    .any(|n| given(n))
+    {
+        if let Some(lines) = defaults.lines {
+            config.lines = lines;
+        }
+        config.bytes = config.bytes.or(defaults.bytes);
+        config.chars = config.chars.or(defaults.chars);
+    }
+    if !given("quiet") && !given("verbose") {
+        config.quiet = defaults.quiet.unwrap_or(config.quiet);
+        config.verbose = defaults.verbose.unwrap_or(config.verbose);
+    }
+    if !given("zero_terminated") && !given("delimiter") {
+        config.zero_terminated =
+            defaults.zero_terminated.unwrap_or(config.zero_terminated);
+    }
+    if !given("number") {
+        config.number = defaults.number.unwrap_or(config.number);
+    }
+    if !given("jobs") {
+        config.jobs = defaults.jobs.unwrap_or(config.jobs);
+    }
+    if !given("color") {
+        config.color = defaults.color.unwrap_or(config.color);
+    }
+}
+
+/* ファイルを指定せずに端末から起動したとき、端末の入力を待ち続けないようにする。
+"-"を明示したときと、ライブラリからConfigを組み立てたときは標準入力を読む */
+fn waits_on_terminal(config: &Config, stdin_is_terminal: bool) -> bool {
+    config.implicit_stdin && stdin_is_terminal && config.files == ["-"]
+}
+
+/* --repeatと--watch-dirで、Ctrl-Cが押されたかどうか */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/* 既にハンドラーがあれば(ライブラリとして2回目に呼んだときなど)そちらに任せる */
+fn catch_interrupt() {
+    let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed));
+}
+
+/* --watch-dirで、書き込み中のファイルの大きさがこの間変わらなければ書き終えたとみなす */
+#[cfg(feature = "watch")]
+const WATCH_SETTLE: std::time::Duration =
+    std::time::Duration::from_millis(500);
+
+/* ライブラリとして使うときの入り口。設定を持ち、stdoutや任意の書き出し先に実行する。
+run、run_with、run_collectはこれを呼ぶだけ */
+#[derive(Debug)]
+pub struct Head {
+    config: Config,
+}
+
+impl Head {
+    pub fn new(config: Config) -> Self {
+        Head { config }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /* stdout(-oがあればそのファイル)に書き出す。端末ならページャーを使う */
+    pub fn run(mut self) -> MyResult<()> {
+        #[cfg(feature = "serde")]
+        if self.config.dump_config {
+            let json = serde_json::to_string_pretty(&self.config)
+                .map_err(io::Error::from)?;
+            println!("{}", json);
+            return Ok(());
+        }
+        if self.config.dry_run {
+            return ignore_broken_pipe(print_dry_run(
+                &self.config,
+                &mut io::stdout().lock(),
+            ));
+        }
+
+        #[cfg(feature = "watch")]
+        let watching = self.config.watch_dir.is_some();
+        #[cfg(not(feature = "watch"))]
+        let watching = false;
+        if !watching
+            && waits_on_terminal(&self.config, io::stdin().is_terminal())
+        {
+            return Err(HeadError::InvalidArgument(
+                "headr: no files given and standard input is a terminal \
+                 (pass - to read it anyway)\n\
+                 Usage: headr [OPTIONS] [FILES]...\n\
+                 Try 'headr --help' for more information."
+                    .to_string(),
+            ));
+        }
+
+        resolve_color(&mut self.config);
+        #[cfg(feature = "watch")]
+        if let Some(dir) = self.config.watch_dir.clone() {
+            return self.watch_dir(&dir);
+        }
+        match self.config.repeat {
+            Some(interval) => self.run_repeatedly(interval),
+            None => ignore_broken_pipe(self.run_once()),
+        }
+    }
+
+    /* --repeat。Ctrl-Cで止めるまでintervalごとに繰り返す。stdoutが端末なら毎回画面を消し、
+    そうでなければ続けて書き足す。Ctrl-Cで止めたときも正常終了にする */
+    fn run_repeatedly(
+        &mut self,
+        interval: std::time::Duration,
+    ) -> MyResult<()> {
+        use std::time::{Duration, Instant};
+
+        catch_interrupt();
+        self.config.pager = PagerChoice::Never; // 毎回ページャーの終了を待たない
+        let clear = self.config.output.is_none() && io::stdout().is_terminal();
+
+        while !INTERRUPTED.load(Ordering::Relaxed) {
+            if clear {
+                print!("\x1b[H\x1b[2J");
+            }
+            match self.run_once() {
+                // 読めなかったファイルは報告済みなので、次の回も続ける
+                Ok(()) | Err(HeadError::FilesFailed(_)) => {}
+                Err(HeadError::Io(e))
+                    if e.kind() == io::ErrorKind::BrokenPipe =>
+                {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            }
+            let deadline = Instant::now() + interval;
+            while !INTERRUPTED.load(Ordering::Relaxed) {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    break;
+                }
+                thread::sleep(left.min(Duration::from_millis(100)));
+            }
+        }
+        Ok(())
+    }
+
+    /* --watch-dir。Ctrl-Cで止めるまで、dirに新しくできたファイルを1つずつ出力する。
+    書き込み中のファイルは、閉じられるか大きさがWATCH_SETTLEの間変わらなくなるまで待つ。
+    ファイル名を変えて置かれたものも新しいファイルとして扱い、同じ名前は2回出力しない */
+    #[cfg(feature = "watch")]
+    fn watch_dir(&mut self, dir: &Path) -> MyResult<()> {
+        use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+        use notify::{EventKind, RecursiveMode, Watcher};
+        use std::time::{Duration, Instant};
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)
+            .and_then(|mut watcher| {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                Ok(watcher)
+            })
+            .map_err(|e| {
+                HeadError::Io(io::Error::other(format!(
+                    "{}: cannot watch: {}",
+                    dir.display(),
+                    e
+                )))
+            })?;
+        catch_interrupt();
+        self.config.pager = PagerChoice::Never;
+        self.config.verbose = !self.config.quiet; // 1つずつ出力しても、どのファイルか分かるように
+
+        // 新しいファイルを見つけた順に、最後に変わった時刻と大きさを持つ
+        let mut pending: Vec<(PathBuf, Instant, Option<u64>)> = Vec::new();
+        let mut printed = HashSet::new();
+        let mut num_printed = 0;
+        while !INTERRUPTED.load(Ordering::Relaxed) {
+            let mut closed = Vec::new();
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Ok(event)) => {
+                    // 前からあったファイルへの書き込みは、新しいファイルとしては扱わない
+                    let is_new = match event.kind {
+                        EventKind::Create(_)
+                        | EventKind::Modify(ModifyKind::Name(
+                            RenameMode::To | RenameMode::Both,
+                        )) => Some(true),
+                        EventKind::Modify(ModifyKind::Data(_)) => Some(false),
+                        EventKind::Access(AccessKind::Close(
+                            AccessMode::Write,
+                        )) => {
+                            closed.extend(event.paths.last().cloned());
+                            Some(false)
+                        }
+                        _ => None,
+                    };
+                    if let (Some(is_new), Some(path)) =
+                        (is_new, event.paths.last().cloned())
+                    {
+                        match pending.iter_mut().find(|(p, ..)| *p == path) {
+                            Some((_, changed, _)) => *changed = Instant::now(),
+                            None if is_new && !printed.contains(&path) => {
+                                pending.push((path, Instant::now(), None))
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                Ok(Err(e)) => log::warn!("{}: {}", dir.display(), e),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            let mut ready = Vec::new();
+            pending.retain_mut(|(path, changed, size)| {
+                let Ok(meta) = fs::metadata(&*path) else {
+                    return false; // 出力する前に消されたファイル
+                };
+                if !meta.is_file() {
+                    return false;
+                }
+                if *size != Some(meta.len()) {
+                    *size = Some(meta.len());
+                    *changed = Instant::now();
+                }
+                if closed.contains(path) || changed.elapsed() >= WATCH_SETTLE {
+                    ready.push(path.clone());
+                    return false;
+                }
+                true
+            });
+            for path in ready {
+                if self.config.verbose && num_printed > 0 {
+                    print!("{}", "\n".repeat(self.config.separator_lines));
+                }
+                self.config.files = vec![path.to_string_lossy().into_owned()];
+                match self.run_once() {
+                    Ok(()) | Err(HeadError::FilesFailed(_)) => {}
+                    Err(HeadError::Io(e))
+                        if e.kind() == io::ErrorKind::BrokenPipe =>
+                    {
+                        return Ok(())
+                    }
+                    Err(e) => return Err(e),
+                }
+                io::stdout().flush()?;
+                printed.insert(path);
+                num_printed += 1;
+            }
+        }
+        watcher.unwatch(dir).ok();
+        Ok(())
+    }
+
+    /* stdout(-oがあればそのファイル、--splitなら連番のファイル)に1回書き出す */
+    fn run_once(&self) -> MyResult<()> {
+        let config = &self.config;
+        if let Some(dir) = &config.output_dir {
+            return write_output_dir(config, dir);
+        }
+        if config.build_index {
+            return write_indexes(config);
+        }
+
+        /* 1行ごとにロックとフラッシュが起きないよう、ロックしたstdout(または-oのファイル)にまとめて書き出す */
+        let tee = config.tee.as_deref().map(create_output).transpose()?;
+        if let Some(num_lines) = config.split {
+            let split = Split::new(config, num_lines);
+            return self.run_to(&mut Tee::new(split, tee, config));
+        }
+        match &config.output {
+            Some(path) => {
+                let file = create_output(path)?;
+                self.run_to(&mut BufWriter::new(Tee::new(file, tee, config)))
+            }
+            None => {
+                let mut pager = Pager::new(config.pager);
+                let result = self.run_to(&mut BufWriter::new(Tee::new(
+                    &mut pager, tee, config,
+                )));
+                // ページャーが終わるのを待つ。先に起きたエラーの方を優先する
+                result.and(pager.finish().map_err(HeadError::from))
+            }
+        }
+    }
+
+    /* 出力先を指定できる版。config.outputは無視してoutに書き出し、最後にflushする。
+    config.colorがAutoの場合は端末かどうか分からないので色を付けない */
+    pub fn run_to(&self, out: &mut impl Write) -> MyResult<()> {
+        print_heads(&self.config, out)
+    }
+
+    /* 何も表示せずに、ファイルごとの先頭のバイト列かエラーを引数の順番で返す。
+    ヘッダーは付けず、標準入力は"-"という名前で返す */
+    pub fn collect(&self) -> Vec<(String, MyResult<Vec<u8>>)> {
+        collect_heads(&self.config)
+    }
+}
+
+impl From<Config> for Head {
+    fn from(config: Config) -> Self {
+        Head::new(config)
+    }
+}
+
+pub fn run(config: Config) -> MyResult<()> {
+    Head::new(config).run()
+}
+
+/* runと同じだが、ファイルはopenerで開く。ConfigBuilder::openerで指定したのと同じ */
+pub fn run_with_opener<F>(config: Config, opener: F) -> MyResult<()>
+where
+    F: Fn(&str) -> MyResult<Box<dyn BufRead>> + Send + Sync + 'static,
+{
+    let config = Config {
+        opener: Some(Opener(Arc::new(opener))),
+        ..config
+    };
+    run(config)
+}
+
+/* runの非同期版。ファイルを同時にいくつも読み、引数の順番どおりに書き出す。
+URLはreqwestでtokio::io::AsyncBufReadとして取得し、それ以外はtokioのブロッキング用スレッドで開く。
+どの形式でも先頭を選ぶ処理はrunと同じものをブロッキング用スレッドで行い、書き出すものは
+sinkへの呼び出しとしてその都度受け取るので、順番が来たファイルは溜めずに書き出す。
+同時に読むのは-jで指定した数までで、指定がなければASYNC_JOBS個。
+--countや--splitなど、ファイルごとにsinkへ書き出すのでない使い方と--pager alwaysでは、
+同期版のrunをブロッキング用スレッドでそのまま実行する。そうでなければ--pager autoでもページャーは起動しない。
+URLを読むので、ランタイムはIOとタイマーを有効にして作ること */
+#[cfg(feature = "tokio")]
+pub async fn run_async(mut config: Config) -> MyResult<()> {
+    if runs_sync(&config) {
+        return tokio::task::spawn_blocking(move || run(config))
+            .await
+            .map_err(io::Error::from)?;
+    }
+    resolve_color(&mut config);
+    let config = Arc::new(config);
+
+    let tee = config.tee.as_deref().map(create_output).transpose()?;
+    let sink: Box<dyn Write + Send> = match &config.output {
+        Some(path) => Box::new(create_output(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut out = BufWriter::new(Tee::new(sink, tee, &config));
+    let result = match config.no_final_newline && !config.byte_mode() {
+        true => {
+            let mut out = HoldDelimiter::new(&mut out, config.delimiter());
+            write_async(&config, &mut out).await
+        }
+        false => write_async(&config, &mut out).await,
+    };
+    let result = result.and_then(|num_failed| {
+        out.flush()?;
+        Ok(num_failed)
+    });
+    match result {
+        Ok(num_failed) => check_failed(&config, num_failed),
+        Err(e) => ignore_broken_pipe(Err(e)),
+    }
+}
+
+/* run_asyncで-jの指定がないときに、同時に読むファイルの数 */
+#[cfg(feature = "tokio")]
+const ASYNC_JOBS: usize = 8;
+
+/* run_asyncで、同期版のrunにそのまま任せる設定か。run_sinkで1ファイルずつ書き出すもの以外と、
+前のファイルの結果を使うもの */
+#[cfg(feature = "tokio")]
+fn runs_sync(config: &Config) -> bool {
+    #[cfg(feature = "serde")]
+    let dump_config = config.dump_config;
+    #[cfg(not(feature = "serde"))]
+    let dump_config = false;
+    #[cfg(feature = "watch")]
+    let watching = config.watch_dir.is_some();
+    #[cfg(not(feature = "watch"))]
+    let watching = false;
+    #[cfg(feature = "regex")]
+    let matching = config.files_with_head_matching.is_some();
+    #[cfg(not(feature = "regex"))]
+    let matching = false;
+    [
+        dump_config,
+        watching,
+        matching,
+        config.dry_run,
+        config.repeat.is_some(),
+        config.pager == PagerChoice::Always,
+        config.output_dir.is_some(),
+        config.build_index,
+        config.split.is_some(),
+        config.format == OutputFormat::Text
+            && (config.count || config.interleave || config.diff),
+        config.total_lines,
+        config.with_total,
+        config.timing,
+        config.dedup_heads,
+    ]
+    .into_iter()
+    .any(|set| set)
+}
+
+/* 出力先に合わせたsinkを作り、run_asyncのファイルを書き出す。失敗したファイルの数を返す */
+#[cfg(feature = "tokio")]
+async fn write_async(
+    config: &Arc<Config>,
+    out: &mut impl Write,
+) -> MyResult<usize> {
+    let num_walk_failed = report_walk_errors(config);
+    let num_failed = match config.format {
+        #[cfg(feature = "json")]
+        OutputFormat::Json => {
+            stream_async(config, &mut JsonSink::new(config, out)).await?
+        }
+        #[cfg(feature = "csv")]
+        OutputFormat::Csv => {
+            stream_async(config, &mut CsvSink::new(config, out)?).await?
+        }
+        #[cfg(feature = "json")]
+        OutputFormat::Ndjson => {
+            let mut sink = NdjsonSink {
+                out,
+                file: String::new(),
+            };
+            stream_async(config, &mut sink).await?
+        }
+        OutputFormat::Text => {
+            let mut out = AtomicWriter::new(&mut *out, config.atomic_headers);
+            stream_async(config, &mut TextSink::new(config, &mut out)).await?
+        }
+    };
+    Ok(num_walk_failed + num_failed)
+}
+
+/* run_asyncのファイルごとのタスクからsinkへの呼び出し。Doneで終わり、Abortは書き込みのエラーなどで
+残りのファイルも止めるもの */
+#[cfg(feature = "tokio")]
+enum SinkEvent {
+    Begin,
+    Line(usize, Vec<u8>),
+    Bytes(Vec<u8>),
+    End(Option<usize>),
+    Fail(HeadError),
+    Done(usize),
+    Abort(HeadError),
+}
+
+/* ファイルごとのタスクを同時にいくつも動かし、順番が来たファイルから受け取ったものをsinkに書く。
+書き終えたかsinkがいっぱいになったら、まだのファイルのタスクはチャンネルが閉じたことで止まる */
+#[cfg(feature = "tokio")]
+async fn stream_async(
+    config: &Arc<Config>,
+    sink: &mut impl HeadSink,
+) -> MyResult<usize> {
+    let jobs = match config.jobs {
+        1 => ASYNC_JOBS,
+        jobs => jobs,
+    };
+    let permits = Arc::new(tokio::sync::Semaphore::new(jobs));
+    let client = reqwest::Client::new();
+    let by_line = sink.by_line();
+    let receivers: Vec<_> = (0..config.files.len())
+        .map(|file_num| {
+            // 溜めておくのは、順番を待つ間のこのくらいの呼び出しまで
+            let (tx, rx) = tokio::sync::mpsc::channel(64);
+            let config = Arc::clone(config);
+            let permits = Arc::clone(&permits);
+            let client = client.clone();
+            tokio::spawn(async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return;
+                };
+                if tx.is_closed() {
+                    return;
+                }
+                let filename = &config.files[file_num];
+                let body = (is_url(filename)
+                    && config.opener.is_none()
+                    && config.cache.is_none())
+                .then(|| {
+                    let (body_tx, body_rx) = tokio::sync::mpsc::channel(4);
+                    tokio::spawn(fetch_async(
+                        client,
+                        filename.clone(),
+                        body_tx,
+                    ));
+                    body_rx
+                });
+                let _ = tokio::task::spawn_blocking(move || {
+                    let sink = &mut EventSink { tx, by_line };
+                    let filename = &config.files[file_num];
+                    let event =
+                        match print_async(&config, filename, body, sink) {
+                            Ok(num_failed) => SinkEvent::Done(num_failed),
+                            Err(e) => SinkEvent::Abort(e),
+                        };
+                    let _ = sink.tx.blocking_send(event);
+                })
+                .await;
+            });
+            rx
+        })
+        .collect();
+
+    let mut num_failed = 0;
+    for (filename, mut rx) in config.files.iter().zip(receivers) {
+        if sink.is_full() || stop_early(config, num_failed) {
+            break;
+        }
+        loop {
+            match rx.recv().await {
+                Some(SinkEvent::Begin) => sink.begin_file(filename)?,
+                Some(SinkEvent::Line(n, line)) => sink.write_line(n, &line)?,
+                Some(SinkEvent::Bytes(bytes)) => sink.write_bytes(&bytes)?,
+                Some(SinkEvent::End(total)) => {
+                    sink.end_file(filename, total)?
+                }
+                Some(SinkEvent::Fail(e)) => sink.fail_file(filename, &e)?,
+                Some(SinkEvent::Done(num)) => {
+                    num_failed += num;
+                    break;
+                }
+                Some(SinkEvent::Abort(e)) => return Err(e),
+                // タスクがパニックで終わったら、そのファイルは読めなかったものとする
+                None => {
+                    let e = HeadError::Io(io::Error::other(
+                        "worker task exited before reading the file",
+                    ));
+                    sink.fail_file(filename, &e)?;
+                    num_failed += 1;
+                    break;
+                }
+            }
+        }
+    }
+    sink.finish()?;
+    Ok(num_failed)
+}
+
+/* run_asyncのブロッキング用スレッドで1ファイルを読む。bodyがあればURLを非同期に取得している本文で、
+開いた後はopen_textと同じものを重ねる。--exactと--min-linesではrun_serialと同じくいったんバッファに読む */
+#[cfg(feature = "tokio")]
+fn print_async(
+    config: &Config,
+    filename: &str,
+    body: Option<tokio::sync::mpsc::Receiver<io::Result<Vec<u8>>>>,
+    sink: &mut EventSink,
+) -> MyResult<usize> {
+    let opened = match body {
+        Some(body) => {
+            let input = Input {
+                reader: Box::new(BodyReader {
+                    rx: body,
+                    chunk: Vec::new(),
+                    pos: 0,
+                }),
+                size: None,
+            };
+            decompress(filename, input)
+                .and_then(|input| text_input(config, filename, input))
+        }
+        None => open_text(config, filename),
+    };
+    match config.exact || config.min_lines.is_some() {
+        true => {
+            let result = read_head_from(config, filename, opened);
+            print_buffered(config, filename, result, sink)
+        }
+        false => print_streamed_from(config, filename, opened, sink),
+    }
+}
+
+/* URLの本文をAsyncBufReadとして読み、そのまま渡していく。受け取る側が必要な分を読み終えて
+チャンネルを閉じたら、残りはダウンロードしない */
+#[cfg(feature = "tokio")]
+async fn fetch_async(
+    client: reqwest::Client,
+    url: String,
+    tx: tokio::sync::mpsc::Sender<io::Result<Vec<u8>>>,
+) {
+    use futures_util::TryStreamExt;
+    use tokio::io::AsyncBufReadExt;
+
+    let response = match client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+    {
+        Ok(response) => response,
+        Err(e) => {
+            let _ = tx.send(Err(io::Error::other(e))).await;
+            return;
+        }
+    };
+    let mut reader = tokio_util::io::StreamReader::new(
+        response.bytes_stream().map_err(io::Error::other),
+    );
+    loop {
+        let chunk = match reader.fill_buf().await {
+            Ok([]) => return,
+            Ok(chunk) => chunk.to_vec(),
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        reader.consume(chunk.len());
+        if tx.send(Ok(chunk)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/* fetch_asyncから届いた本文を、ブロッキング用スレッドでBufReadとして読む */
+#[cfg(feature = "tokio")]
+struct BodyReader {
+    rx: tokio::sync::mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self.fill_buf()?;
+        let len = chunk.len().min(buf.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl BufRead for BodyReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.chunk.len() {
+            match self.rx.blocking_recv() {
+                None => break,
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+            }
+        }
+        Ok(&self.chunk[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.chunk.len());
+    }
+}
+
+/* run_asyncのタスク側のsink。呼び出しをSinkEventにして、順番を待つstream_asyncに送る。
+受け取る側がもう要らなくなってチャンネルを閉じていたら、BrokenPipeにして読むのをやめさせる */
+#[cfg(feature = "tokio")]
+struct EventSink {
+    tx: tokio::sync::mpsc::Sender<SinkEvent>,
+    by_line: bool, // 書き出す先のsinkのby_lineに合わせる
+}
+
+#[cfg(feature = "tokio")]
+impl EventSink {
+    fn send(&mut self, event: SinkEvent) -> MyResult<()> {
+        self.tx
+            .blocking_send(event)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe).into())
+    }
+}
+
+/* io::Errorは複製できないので、表示とkindが同じになるように作り直す */
+#[cfg(feature = "tokio")]
+fn copy_error(e: &HeadError) -> HeadError {
+    match e {
+        HeadError::Io(e) => {
+            HeadError::Io(io::Error::new(e.kind(), e.to_string()))
+        }
+        HeadError::InvalidLineCount(val) => {
+            HeadError::InvalidLineCount(val.clone())
+        }
+        HeadError::InvalidByteCount(val) => {
+            HeadError::InvalidByteCount(val.clone())
+        }
+        HeadError::FileNotFound(name) => HeadError::FileNotFound(name.clone()),
+        HeadError::FilesFailed(num) => HeadError::FilesFailed(*num),
+        HeadError::InvalidArgument(msg) => {
+            HeadError::InvalidArgument(msg.clone())
+        }
+        HeadError::IsADirectory(name) => HeadError::IsADirectory(name.clone()),
+        HeadError::BinaryFile(name) => HeadError::BinaryFile(name.clone()),
+        HeadError::ShortInput(name, found, requested, unit) => {
+            HeadError::ShortInput(name.clone(), *found, *requested, unit)
+        }
+        HeadError::BufferLimit(name, max) => {
+            HeadError::BufferLimit(name.clone(), *max)
+        }
+        HeadError::InvalidUtf8(name, offset) => {
+            HeadError::InvalidUtf8(name.clone(), *offset)
+        }
+        HeadError::DirectoryLoop(name) => {
+            HeadError::DirectoryLoop(name.clone())
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl HeadSink for EventSink {
+    fn begin_file(&mut self, _name: &str) -> MyResult<()> {
+        self.send(SinkEvent::Begin)
+    }
+
+    fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()> {
+        self.send(SinkEvent::Line(n, line.to_vec()))
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+        self.send(SinkEvent::Bytes(bytes.to_vec()))
+    }
+
+    fn end_file(&mut self, _name: &str, total: Option<usize>) -> MyResult<()> {
+        self.send(SinkEvent::End(total))
+    }
+
+    fn fail_file(&mut self, _name: &str, e: &HeadError) -> MyResult<()> {
+        self.send(SinkEvent::Fail(copy_error(e)))
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        Ok(())
+    }
+
+    fn by_line(&self) -> bool {
+        self.by_line
+    }
+}
+
+/* --dry-run。展開と重複の除去を済ませたファイル名と、どこまで出力するかを表示する。
+ファイルは開かない */
+fn print_dry_run(config: &Config, out: &mut impl Write) -> MyResult<()> {
+    for filename in &config.files {
+        writeln!(out, "file: {}", filename)?;
+    }
+    writeln!(out, "limit: {}", describe_limit(config))?;
+    if config.skip > 0 {
+        let unit = if config.byte_mode() { "bytes" } else { "lines" };
+        writeln!(out, "skip: {} {}", config.skip, unit)?;
+    }
+    if let Some(num) = config.max_total_lines {
+        writeln!(out, "max total: {} lines", num)?;
+    }
+    if let Some(num) = config.max_total_bytes {
+        writeln!(out, "max total: {} bytes", num)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/* 各ファイルのどこまでを出力するか。print_headと同じ順に設定を見る */
+fn describe_limit(config: &Config) -> String {
+    let count = |count: Count, unit: &str| match count {
+        Count::First(num) => format!("first {} {}", num, unit),
+        Count::AllButLast(num) => format!("all but the last {} {}", num, unit),
+    };
+    if let Some(percent) = config.percent {
+        return format!("first {}% of lines", percent);
+    }
+    if let Some(percent) = config.bytes_percent {
+        return format!("first {}% of bytes", percent);
+    }
+    if let Some(num) = config.chars {
+        return format!("first {} characters", num);
+    }
+    if let Some(num) = config.graphemes {
+        return format!("first {} graphemes", num);
+    }
+    if let Some(num) = config.words {
+        return format!("first {} words", num);
+    }
+    match config.bytes {
+        Some(ByteSpec::Count(Count::First(num))) if config.first_limit => {
+            return format!(
+                "{}, at most {} bytes",
+                count(config.lines, "lines"),
+                num
+            )
+        }
+        Some(ByteSpec::Count(bytes)) => return count(bytes, "bytes"),
+        Some(ByteSpec::Range(first, last)) => {
+            return format!("bytes {}-{}", first, last)
+        }
+        None => {}
+    }
+    if let Some(ranges) = &config.line_ranges {
+        let ranges: Vec<_> = ranges
+            .iter()
+            .map(|(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            })
+            .collect();
+        return format!("lines {}", ranges.join(","));
+    }
+    #[cfg(feature = "regex")]
+    if let Some(pattern) = &config.until {
+        return format!("lines until /{}/", pattern);
+    }
+    if config.paragraph {
+        return count(config.lines, "paragraphs");
+    }
+    match config.context {
+        Some(num) => {
+            format!(
+                "{} and the last {} lines",
+                count(config.lines, "lines"),
+                num
+            )
+        }
+        None => count(config.lines, "lines"),
+    }
+}
+
+/* --color autoを、端末に書き出すかどうかでalwaysかneverに決める */
+fn resolve_color(config: &mut Config) {
+    if config.color == ColorChoice::Auto {
+        let is_tty = config.output.is_none() && io::stdout().is_terminal();
+        config.color = if is_tty {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        };
+    }
+}
+
+/* パイプの先(head -5など)が先に終了したら、GNU headと同じく正常終了扱いにする */
+fn ignore_broken_pipe(result: MyResult<()>) -> MyResult<()> {
+    match result {
+        Err(HeadError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/* -oや--teeのファイルを作る。エラーにはファイル名を付ける */
+fn create_output(path: &str) -> MyResult<File> {
+    File::create(path).map_err(|e| {
+        HeadError::Io(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    })
+}
+
+/* --output-dir用。ファイルごとの先頭を、DIRの下の同じ相対パスのファイルに書き出す。
+・絶対パスはルート(Windowsならドライブも)を取り除いてDIRの下に置く
+・..を含むパスはDIRの外に書いてしまうので、そのファイルだけエラーにする
+・標準入力には書き出す先の名前が無いので、エラーにする
+・別の入力が同じ書き出し先になったら(a/xと/a/xなど)、先に書いた方を残して後のをエラーにする
+前からDIRにあるファイルは-oと同じく上書きする。失敗したファイルの数でエラーを返す */
+fn write_output_dir(config: &Config, dir: &str) -> MyResult<()> {
+    let mut written: HashMap<PathBuf, &str> = HashMap::new();
+    let mut num_failed = 0;
+    for filename in &config.files {
+        if stop_early(config, num_failed) {
+            break;
+        }
+        let result = output_dir_path(dir, filename).and_then(|path| {
+            if let Some(other) = written.get(&path) {
+                return Err(HeadError::Io(io::Error::other(format!(
+                    "{} was already written for {}",
+                    path.display(),
+                    other
+                ))));
+            }
+            check_not_input(&path, filename)?;
+            let head =
+                read_head(config, filename).and_then(|result| result)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, head).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+            })?;
+            written.insert(path, filename);
+            Ok(())
+        });
+        if let Err(e) = result {
+            report_error(config, filename, &e);
+            num_failed += usize::from(is_failure(&e));
+        }
+    }
+    check_failed(config, num_failed)
+}
+
+/* 書き出し先が読むファイルそのものか、読むディレクトリの中なら書かない。
+まだ無い書き出し先は、あるところまで遡って正規化して比べる */
+fn check_not_input(path: &Path, filename: &str) -> MyResult<()> {
+    let Ok(input) = fs::canonicalize(filename) else {
+        return Ok(());
+    };
+    let mut existing = path;
+    let mut rest = Vec::new();
+    let dest = loop {
+        match fs::canonicalize(existing) {
+            Ok(dest) => break dest,
+            Err(_) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    rest.push(name);
+                    existing = parent;
+                }
+                _ => return Ok(()),
+            },
+        }
+    };
+    let dest = rest.iter().rev().fold(dest, |dest, name| dest.join(name));
+    if dest.starts_with(&input) {
+        return Err(HeadError::Io(io::Error::other(format!(
+            "refusing to write {} over the input",
+            path.display()
+        ))));
+    }
+    Ok(())
+}
+
+fn output_dir_path(dir: &str, filename: &str) -> MyResult<PathBuf> {
+    use std::path::Component;
+
+    if filename == "-" {
+        return Err(HeadError::Io(io::Error::other(
+            "standard input has no path to mirror under --output-dir",
+        )));
+    }
+    let mut path = PathBuf::from(dir);
+    for component in Path::new(filename).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::ParentDir => {
+                return Err(HeadError::Io(io::Error::other(
+                    "paths with .. cannot be mirrored under --output-dir",
+                )))
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    Ok(path)
+}
+
+/* --tee用。innerと同じ内容をファイルにも書き出す。ファイルへの書き込みに失敗したら
+報告だけして、それ以降はinnerにだけ書き出す */
+struct Tee<'a, W> {
+    inner: W,
+    file: Option<File>,
+    path: &'a str,
+}
+
+impl<'a, W: Write> Tee<'a, W> {
+    fn new(inner: W, file: Option<File>, config: &'a Config) -> Self {
+        Tee {
+            inner,
+            file,
+            path: config.tee.as_deref().unwrap_or_default(),
+        }
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            eprintln!("headr: {}: {}", self.path, e);
+            self.file = None;
+        }
+    }
+}
+
+impl<W: Write> Write for Tee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num = self.inner.write(buf)?;
+        if let Some(file) = self.file.as_mut() {
+            let result = file.write_all(&buf[..num]);
+            self.check(result);
+        }
+        Ok(num)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let result = file.flush();
+            self.check(result);
+        }
+        self.inner.flush()
+    }
+}
+
+/* --split用。num_lines行ごとに書き出すファイルをPREFIX000, PREFIX001, ...と切り替える。
+ファイルは最初に書き込むときに作るので、何も出力しなければ作らない */
+struct Split<'a> {
+    file: Option<BufWriter<File>>,
+    prefix: &'a str,
+    width: usize,
+    num_lines: usize,
+    lines_left: usize,
+    next_num: usize,
+    delimiter: u8,
+}
+
+impl<'a> Split<'a> {
+    fn new(config: &'a Config, num_lines: usize) -> Self {
+        Split {
+            file: None,
+            prefix: &config.split_prefix,
+            width: config.split_width,
+            num_lines,
+            lines_left: num_lines,
+            next_num: 0,
+            delimiter: config.delimiter(),
+        }
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            let path = format!(
+                "{}{:0width$}",
+                self.prefix,
+                self.next_num,
+                width = self.width
+            );
+            let file = File::create(&path).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", path, e))
+            })?;
+            self.file = Some(BufWriter::new(file));
+            self.next_num += 1;
+            self.lines_left = self.num_lines;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Split<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            self.open_next()?;
+            /* このファイルの最後の行の区切り文字までを書き、続きは次のファイルに書く */
+            let delimiters = rest
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == self.delimiter);
+            let (end, done) = match delimiters.clone().nth(self.lines_left - 1)
+            {
+                Some((pos, _)) => (pos + 1, true),
+                None => (rest.len(), false),
+            };
+            if !done {
+                self.lines_left -= delimiters.count();
+            }
+            if let Some(file) = self.file.as_mut() {
+                file.write_all(&rest[..end])?;
+                if done {
+                    file.flush()?;
+                    self.file = None;
+                }
+            }
+            rest = &rest[end..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/* 標準出力への書き込み口。--pagerの指定に従ってページャーに渡すか、直接書き出す */
+struct Pager {
+    stdout: io::StdoutLock<'static>,
+    mode: PagerMode,
+}
+
+enum PagerMode {
+    Direct,
+    /* 端末の高さを超えるまでは出力を貯めておく */
+    Buffering {
+        height: usize,
+        lines: usize,
+        buffer: Vec<u8>,
+    },
+    Paging(Child),
+}
+
+impl Pager {
+    fn new(choice: PagerChoice) -> Self {
+        let stdout = io::stdout();
+        let height = if stdout.is_terminal() {
+            terminal_size::terminal_size().map(|(_, height)| height.0 as usize)
+        } else {
+            None // パイプやファイルへの出力ではページャーを使わない
+        };
+        let mut pager = Pager {
+            stdout: stdout.lock(),
+            mode: PagerMode::Direct,
+        };
+        match (choice, height) {
+            (PagerChoice::Never, _) | (_, None) => {}
+            (PagerChoice::Always, Some(_)) => pager.start(Vec::new()),
+            (PagerChoice::Auto, Some(height)) => {
+                pager.mode = PagerMode::Buffering {
+                    height,
+                    lines: 0,
+                    buffer: Vec::new(),
+                }
+            }
+        }
+        pager
+    }
+
+    /* $PAGER(無ければless)を起動し、貯めていた出力を渡す。
+    起動できなければ警告だけ出して標準出力に書き出す */
+    fn start(&mut self, buffer: Vec<u8>) {
+        let command = std::env::var("PAGER")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+            .unwrap_or_else(|| "less".to_string());
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or("less");
+        let spawned = Command::new(program)
+            .args(words)
+            .env("LESS", std::env::var("LESS").unwrap_or("FRX".to_string())) // 色を通し、1画面に収まれば終了する
+            .stdin(Stdio::piped())
+            .spawn();
+        self.mode = match spawned {
+            Ok(child) => PagerMode::Paging(child),
+            Err(e) => {
+                eprintln!("headr: {}: {}", program, e);
+                PagerMode::Direct
+            }
+        };
+        if !buffer.is_empty() {
+            // 書き込みのエラーはこの後の書き込みでも起きるので、ここでは無視してよい
+            let _ = self.write_all(&buffer);
+        }
+    }
+
+    /* 端末に収まった出力を書き出すか、ページャーの終了を待つ */
+    fn finish(mut self) -> io::Result<()> {
+        match std::mem::replace(&mut self.mode, PagerMode::Direct) {
+            PagerMode::Direct => self.stdout.flush(),
+            PagerMode::Buffering { buffer, .. } => {
+                self.stdout.write_all(&buffer)?;
+                self.stdout.flush()
+            }
+            PagerMode::Paging(mut child) => {
+                drop(child.stdin.take()); // EOFを伝える
+                child.wait()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.mode {
+            PagerMode::Direct => self.stdout.write(buf),
+            PagerMode::Paging(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.write(buf),
+                None => Err(io::ErrorKind::BrokenPipe.into()),
+            },
+            PagerMode::Buffering {
+                height,
+                lines,
+                buffer,
+            } => {
+                buffer.extend_from_slice(buf);
+                *lines += buf.iter().filter(|&&byte| byte == b'\n').count();
+                if *lines >= *height {
+                    let buffer = std::mem::take(buffer);
+                    self.start(buffer);
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.mode {
+            PagerMode::Direct => self.stdout.flush(),
+            PagerMode::Paging(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.flush(),
+                None => Ok(()),
+            },
+            PagerMode::Buffering { .. } => Ok(()), // finishでまとめて書き出す
+        }
+    }
+}
+
+/* Head::run_toと同じ。Configを持ち替えずに使える */
+pub fn run_with(config: &Config, out: &mut impl Write) -> MyResult<()> {
+    print_heads(config, out)
+}
+
+/* Head::collectと同じ */
+pub fn run_collect(config: &Config) -> Vec<(String, MyResult<Vec<u8>>)> {
+    collect_heads(config)
+}
+
+fn print_heads(config: &Config, out: &mut impl Write) -> MyResult<()> {
+    if config.no_final_newline && !config.byte_mode() {
+        let mut out = HoldDelimiter::new(out, config.delimiter());
+        return print_all_heads(config, &mut out);
+    }
+    print_all_heads(config, out)
+}
+
+fn print_all_heads(config: &Config, out: &mut impl Write) -> MyResult<()> {
+    log::debug!(
+        "reading {} file(s), {} job(s)",
+        config.files.len(),
+        config.jobs
+    );
+    let num_walk_failed = report_walk_errors(config);
+    #[cfg(feature = "regex")]
+    if let Some(pattern) = &config.files_with_head_matching {
+        let num_failed = print_matching_files(config, pattern, out)?;
+        out.flush()?;
+        return check_failed(config, num_walk_failed + num_failed);
+    }
+    let num_failed = match config.format {
+        #[cfg(feature = "json")]
+        OutputFormat::Json => {
+            run_sink(config, &mut JsonSink::new(config, out))?
+        }
+        #[cfg(feature = "csv")]
+        OutputFormat::Csv => {
+            run_sink(config, &mut CsvSink::new(config, out)?)?
+        }
+        #[cfg(feature = "json")]
+        OutputFormat::Ndjson => {
+            let mut sink = NdjsonSink {
+                out,
+                file: String::new(),
+            };
+            run_sink(config, &mut sink)?
+        }
+        OutputFormat::Text if config.count => print_counts(config, out)?,
+        OutputFormat::Text if config.interleave => {
+            print_interleaved(config, out)?
+        }
+        OutputFormat::Text if config.diff => print_diff(config, out)?,
+        OutputFormat::Text => {
+            let mut out = AtomicWriter::new(&mut *out, config.atomic_headers);
+            run_sink(config, &mut TextSink::new(config, &mut out))?
+        }
+    };
+    out.flush()?;
+
+    check_failed(config, num_walk_failed + num_failed)
+}
+
+/* -rでディレクトリを読めなかったものを、ファイルを読む前に他のファイルと同じく報告する。
+失敗に数えるものの数を返す */
+fn report_walk_errors(config: &Config) -> usize {
+    config
+        .walk_errors
+        .iter()
+        .map(|(dirname, e)| {
+            report_error(config, dirname, e);
+            is_failure(e) as usize
+        })
+        .sum()
+}
+
+/* --no-final-newline用。末尾の区切り文字を次の書き込みがあるまで書かずに持っておく。
+最後まで続きが来なければ、出力全体の最後の区切り文字だけが書かれずに終わる */
+struct HoldDelimiter<'a, W> {
+    inner: &'a mut W,
+    delimiter: u8,
+    held: bool,
+}
+
+impl<'a, W: Write> HoldDelimiter<'a, W> {
+    fn new(inner: &'a mut W, delimiter: u8) -> Self {
+        HoldDelimiter {
+            inner,
+            delimiter,
+            held: false,
+        }
+    }
+}
+
+impl<W: Write> Write for HoldDelimiter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.held {
+            self.inner.write_all(&[self.delimiter])?;
+            self.held = false;
+        }
+        match buf.split_last() {
+            Some((last, rest)) if *last == self.delimiter => {
+                self.inner.write_all(rest)?;
+                self.held = true;
+            }
+            _ => self.inner.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/* --diff。2つのファイルを1行ずつ比べ、違う行を-nの数までa:とb:を付けて出力する。
+片方が先に終わったら、残った方の行だけを出力する。最後の行に区切り文字が
+あるかどうかは比べない。同じなら何も出力しない */
+fn print_diff(config: &Config, out: &mut impl Write) -> MyResult<usize> {
+    let [first, second] = config.files.as_slice() else {
+        return Err(HeadError::InvalidArgument(format!(
+            "--diff needs exactly two files, got {}",
+            config.files.len()
+        )));
+    };
+    let delimiter = config.delimiter();
+    let mut num_failed = 0;
+    let mut readers = Vec::new();
+    for filename in [first, second] {
+        let input = open_text(config, filename).and_then(|input| {
+            let input = if config.strip_bom {
+                strip_bom(input)?
+            } else {
+                input
+            };
+            skip_input(config, input, delimiter)
+        });
+        match input {
+            Ok(input) => readers.push((filename, input.reader)),
+            Err(e) => {
+                num_failed += is_failure(&e) as usize;
+                report_error(config, filename, &e);
+            }
+        }
+    }
+    if num_failed > 0 || readers.len() < 2 {
+        return Ok(num_failed);
+    }
+
+    let num_lines = match config.lines {
+        Count::First(num_lines) => num_lines,
+        Count::AllButLast(_) => 0,
+    };
+    let mut content = ContentWriter::new(config, out);
+    let chains = [FilterChain::new(config)?, FilterChain::new(config)?];
+    let mut found = 0;
+    let mut line_num = 0;
+    while found < num_lines && !content.is_exhausted() {
+        line_num += 1;
+        let mut lines = [Vec::new(), Vec::new()];
+        for ((filename, reader), line) in readers.iter_mut().zip(&mut lines) {
+            if let Err(e) = reader.read_until(delimiter, line) {
+                report_error(config, filename, &HeadError::Io(e));
+                return Ok(1);
+            }
+        }
+        if lines.iter().all(Vec::is_empty) {
+            break;
+        }
+        let [a, b] = lines.map(|mut line| {
+            if !line.is_empty() && line.last() != Some(&delimiter) {
+                line.push(delimiter);
+            }
+            line
+        });
+        if a == b {
+            continue;
+        }
+        found += 1;
+        for ((prefix, line), chain) in
+            [("a", a), ("b", b)].into_iter().zip(&chains)
+        {
+            if !line.is_empty() {
+                let line = String::from_utf8_lossy(&line);
+                print_labeled_line(
+                    config,
+                    chain,
+                    prefix,
+                    line_num,
+                    &line,
+                    &mut content,
+                )?;
+            }
+        }
+    }
+    Ok(num_failed)
+}
+
+/* --diffと--interleave。"label: "を付けて1行を出力する。--skip-emptyなどで落とした行には
+ラベルも書かないように、いったんバッファに書いてから出力する */
+fn print_labeled_line(
+    config: &Config,
+    chain: &FilterChain,
+    label: &str,
+    line_num: usize,
+    line: &str,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut buf = Vec::new();
+    if print_line(config, chain, line_num, None, line, &mut buf)? {
+        write!(out, "{}: ", label)?;
+        out.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+/* --fail-fastなら、失敗したファイルがあった時点で残りのファイルは読まない */
+fn stop_early(config: &Config, num_failed: usize) -> bool {
+    config.fail_fast && num_failed > 0
+}
+
+/* 失敗したファイルがあっても残りは処理し、最後にまとめてエラーを返す。
+--force-successなら失敗があっても成功にする */
+fn check_failed(config: &Config, num_failed: usize) -> MyResult<()> {
+    match num_failed {
+        0 => Ok(()),
+        _ if config.force_success => Ok(()),
+        num => Err(HeadError::FilesFailed(num)),
+    }
+}
+
+fn collect_heads(config: &Config) -> Vec<(String, MyResult<Vec<u8>>)> {
+    config
+        .files
+        .iter()
+        .map(|filename| {
+            let result = read_head(config, filename).and_then(|result| result);
+            (filename.clone(), result)
+        })
+        .collect()
+}
+
+/* --count。いつもと同じくprint_inputで先頭を選び、書き出す代わりに数えるだけにする。
+数えるのは-cならバイト数、--charsや--graphemesならその数、それ以外は行数。
+ヘッダーを付けるときと同じ条件で、数の後ろにファイル名を付ける。失敗したファイルの数を返す */
+fn print_counts(config: &Config, out: &mut impl Write) -> MyResult<usize> {
+    let show_names =
+        config.verbose || (config.files.len() > 1 && !config.quiet);
+    let mut num_failed = 0;
+    for filename in &config.files {
+        if stop_early(config, num_failed) {
+            break;
+        }
+        let mut counter = StatsWriter {
+            stats: Stats::default(),
+            delimiter: config.delimiter(),
+            #[cfg(feature = "graphemes")]
+            count_graphemes: config.graphemes.is_some(),
+        };
+        let result = open_text(config, filename).and_then(|input| {
+            print_input(config, filename, input, &mut counter)?
+        });
+        if let Err(e) = result {
+            num_failed += usize::from(is_failure(&e));
+            report_error(config, filename, &e);
+            continue;
+        }
+        let stats = counter.stats;
+        let count = if config.byte_mode() {
+            stats.bytes
+        } else if config.chars.is_some() {
+            stats.chars
+        } else if config.graphemes.is_some() {
+            stats.num_graphemes()
+        } else {
+            stats.num_lines()
+        };
+        if show_names {
+            writeln!(out, "{} {}", count, filename)?;
+        } else {
+            writeln!(out, "{}", count)?;
+        }
+    }
+    Ok(num_failed)
+}
+
+/* --files-with-head-matching。各ファイルの先頭を出力する代わりにバッファに書き、
+patternに一致する行があればファイル名だけを出力する */
+#[cfg(feature = "regex")]
+fn print_matching_files(
+    config: &Config,
+    pattern: &regex::Regex,
+    out: &mut impl Write,
+) -> MyResult<usize> {
+    let delimiter = config.delimiter();
+    let mut num_failed = 0;
+    for filename in &config.files {
+        if stop_early(config, num_failed) {
+            break;
+        }
+        let mut head = Vec::new();
+        let result = open_text(config, filename).and_then(|input| {
+            print_input(config, filename, input, &mut head)?
+        });
+        if let Err(e) = result {
+            num_failed += usize::from(is_failure(&e));
+            report_error(config, filename, &e);
+            continue;
+        }
+        let found = head
+            .split(|byte| *byte == delimiter)
+            .any(|line| pattern.is_match(&String::from_utf8_lossy(line)));
+        if found {
+            writeln!(out, "{}", filename)?;
+        }
+    }
+    Ok(num_failed)
+}
+
+/* --count用に、書かれた内容を捨てて数だけを数える */
+struct StatsWriter {
+    stats: Stats,
+    delimiter: u8,
+    #[cfg(feature = "graphemes")]
+    count_graphemes: bool,
+}
+
+impl Write for StatsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stats.add(buf, self.delimiter);
+        #[cfg(feature = "graphemes")]
+        if self.count_graphemes {
+            self.stats.add_graphemes(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/* 出力の形式ごとの書き出し方。run_serialとrun_parallelが各ファイルを読み、どの形式にも同じ順に渡す。
+開けたファイルはbegin_fileの後、by_lineならprint_inputが書いたものを区切り文字を除いた1行ずつwrite_lineに、
+そうでなければ書かれたままwrite_bytesに、ファイル全体を溜めずに少しずつ渡す。
+最後まで読めたらend_fileを、開けなかったか途中で読めなくなったらfail_fileを呼ぶ */
+trait HeadSink {
+    fn begin_file(&mut self, name: &str) -> MyResult<()>;
+    fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()>;
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()>;
+    /* total_linesは--total-linesなどで数えた入力全体の行数 */
+    fn end_file(
+        &mut self,
+        name: &str,
+        total_lines: Option<usize>,
+    ) -> MyResult<()>;
+    fn fail_file(&mut self, name: &str, e: &HeadError) -> MyResult<()>;
+    fn finish(&mut self) -> MyResult<()>;
+
+    /* 行ごとにwrite_lineで受け取るか */
+    fn by_line(&self) -> bool {
+        true
+    }
+
+    /* --max-total-linesなどで、もう何も書けないか */
+    fn is_full(&self) -> bool {
+        false
+    }
+
+    /* --dedup-heads。priorと同じ先頭だったファイルを注記だけで済ませたらtrueを返す。
+    falseならいつもどおりに書く */
+    fn same_file(&mut self, _name: &str, _prior: &str) -> MyResult<bool> {
+        Ok(false)
+    }
+}
+
+/* 行単位で出力するか。-c、--chars、--graphemes、--wordsでは行に分けない */
+fn is_line_mode(config: &Config) -> bool {
+    !config.byte_mode()
+        && config.chars.is_none()
+        && config.graphemes.is_none()
+        && config.words.is_none()
+}
+
+/* print_inputが書いたものをsinkに渡す。行ごとに受け取るsinkには、区切り文字までを溜めてから渡す */
+struct SinkWriter<'a, S> {
+    sink: &'a mut S,
+    delimiter: Option<u8>, // 行ごとに渡すときの区切り文字
+    line: Vec<u8>,         // まだ区切り文字が来ていない行
+    line_num: usize,
+}
+
+impl<'a, S: HeadSink> SinkWriter<'a, S> {
+    fn new(config: &Config, sink: &'a mut S) -> Self {
+        let by_line = sink.by_line() && is_line_mode(config);
+        SinkWriter {
+            sink,
+            delimiter: by_line.then(|| config.delimiter()),
+            line: Vec::new(),
+            line_num: 0,
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> MyResult<()> {
+        self.line_num += 1;
+        self.sink.write_line(self.line_num, line)
+    }
+
+    /* 区切り文字で終わっていない最後の行も1行として渡す */
+    fn finish(mut self) -> MyResult<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.write_line(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/* sinkのエラーをWriteのエラーにする。書き込みのエラーはそのまま返し、BrokenPipeなどを見分けられるようにする */
+fn into_io_error(e: HeadError) -> io::Error {
+    match e {
+        HeadError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl<S: HeadSink> Write for SinkWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(delimiter) = self.delimiter else {
+            self.sink.write_bytes(buf).map_err(into_io_error)?;
+            return Ok(buf.len());
+        };
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|&byte| byte == delimiter) {
+            let result = if self.line.is_empty() {
+                self.write_line(&rest[..pos])
+            } else {
+                let mut line = std::mem::take(&mut self.line);
+                line.extend_from_slice(&rest[..pos]);
+                self.write_line(&line)
+            };
+            result.map_err(into_io_error)?;
+            rest = &rest[pos + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/* sinkに合わせて、-jが2以上ならrun_parallelで、そうでなければrun_serialで書き出す。
+失敗したファイルの数を返す */
+fn run_sink(config: &Config, sink: &mut impl HeadSink) -> MyResult<usize> {
+    if config.jobs > 1
+        && !config.total_lines
+        && !config.with_total
+        && !config.timing
+        && !config.dedup_heads
+    {
+        run_parallel(config, sink)
+    } else {
+        run_serial(config, sink)
+    }
+}
+
+/* 通常の出力用。print_headが書式を整えたもの(-cの16進ダンプや--rawも)を、ヘッダーを付けてそのまま書き出す。
+--max-total-linesなどの上限と--statsはcontentが扱う */
+struct TextSink<'a, W> {
+    config: &'a Config,
+    content: ContentWriter<'a, AtomicWriter<W>>,
+    num_printed: usize, // 書き出したファイルの数。開けなかったファイルは数えない
+    in_file: bool,      // begin_fileの後で、まだ書き終えていない
+}
+
+impl<'a, W: Write> TextSink<'a, W> {
+    fn new(config: &'a Config, out: &'a mut AtomicWriter<W>) -> Self {
+        TextSink {
+            config,
+            content: ContentWriter::new(config, out),
+            num_printed: 0,
+            in_file: false,
+        }
+    }
+
+    /* 書き終えたファイルのヘッダーと中身を--atomic-headersなら1回で書き出す */
+    fn close(&mut self, name: &str) -> MyResult<()> {
+        self.in_file = false;
+        if finish_file(self.config, name, &mut self.content)? {
+            self.num_printed += 1;
+        }
+        self.content.inner.commit()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> HeadSink for TextSink<'_, W> {
+    fn begin_file(&mut self, name: &str) -> MyResult<()> {
+        start_file(self.config, self.num_printed, name, &mut self.content)?;
+        self.in_file = true;
+        Ok(())
+    }
+
+    fn write_line(&mut self, _n: usize, line: &[u8]) -> MyResult<()> {
+        self.content.write_all(line)?;
+        self.content.write_all(&[self.config.delimiter()])?;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+        self.content.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn end_file(
+        &mut self,
+        name: &str,
+        total_lines: Option<usize>,
+    ) -> MyResult<()> {
+        self.content.stats.total_lines = total_lines;
+        self.close(name)
+    }
+
+    /* 読み込みに失敗しても、そこまでの出力は残してから報告する */
+    fn fail_file(&mut self, name: &str, e: &HeadError) -> MyResult<()> {
+        if self.in_file {
+            self.content.stats.total_lines = None;
+            self.close(name)?;
+        }
+        report_error(self.config, name, e);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.content.inner.commit()?;
+        Ok(())
+    }
+
+    fn by_line(&self) -> bool {
+        false
+    }
+
+    fn is_full(&self) -> bool {
+        self.content.is_exhausted()
+    }
+
+    fn same_file(&mut self, name: &str, prior: &str) -> MyResult<bool> {
+        print_same_header(
+            self.config,
+            self.num_printed,
+            name,
+            prior,
+            self.content.inner,
+        )?;
+        self.num_printed += 1;
+        self.content.inner.commit()?;
+        Ok(true)
+    }
+}
+
+/* --format json用(json機能)。エラーもstderrではなく{"file": ..., "error": ...}として出力する。
+バイト単位ならbase64にした"bytes"、行単位なら区切り文字を除いた"lines"、
+文字数などの指定なら"text"を持つ。全てのファイルを読んでから1つの配列として書く */
+#[cfg(feature = "json")]
+struct JsonSink<'a, W> {
+    out: &'a mut W,
+    entries: Vec<serde_json::Value>,
+    byte_mode: bool,
+    line_mode: bool,
+    in_file: bool, // 最後のentryがまだ書き終えていないファイルのもの
+    bytes: Vec<u8>, // 行単位でないときに、end_fileまで溜めておく中身
+}
+
+#[cfg(feature = "json")]
+impl<'a, W: Write> JsonSink<'a, W> {
+    fn new(config: &Config, out: &'a mut W) -> Self {
+        JsonSink {
+            out,
+            entries: Vec::new(),
+            byte_mode: config.byte_mode(),
+            line_mode: is_line_mode(config),
+            in_file: false,
+            bytes: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> HeadSink for JsonSink<'_, W> {
+    fn begin_file(&mut self, name: &str) -> MyResult<()> {
+        let mut entry = serde_json::json!({ "file": name });
+        if self.line_mode {
+            entry["lines"] = serde_json::json!([]);
+        }
+        self.entries.push(entry);
+        self.in_file = true;
+        Ok(())
+    }
+
+    fn write_line(&mut self, _n: usize, line: &[u8]) -> MyResult<()> {
+        if let Some(serde_json::Value::Array(lines)) =
+            self.entries.last_mut().map(|entry| &mut entry["lines"])
+        {
+            lines.push(String::from_utf8_lossy(line).into());
+        }
+        Ok(())
+    }
+
+    // base64は3バイトごとに区切るので、少しずつ来る中身は最後にまとめて変換する
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+        self.bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn end_file(
+        &mut self,
+        _name: &str,
+        _total: Option<usize>,
+    ) -> MyResult<()> {
+        use base64::Engine;
+
+        self.in_file = false;
+        let bytes = std::mem::take(&mut self.bytes);
+        match self.entries.last_mut() {
+            Some(entry) if self.byte_mode => {
+                entry["bytes"] = base64::engine::general_purpose::STANDARD
+                    .encode(bytes)
+                    .into();
+            }
+            Some(entry) if !self.line_mode => {
+                entry["text"] = String::from_utf8_lossy(&bytes).into();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /* 途中で読めなくなったファイルは、読めた分を捨ててエラーだけにする */
+    fn fail_file(&mut self, name: &str, e: &HeadError) -> MyResult<()> {
+        if std::mem::take(&mut self.in_file) {
+            self.entries.pop();
+            self.bytes.clear();
+        }
+        self.entries
+            .push(serde_json::json!({ "file": name, "error": e.to_string() }));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        serde_json::to_writer_pretty(&mut *self.out, &self.entries)
+            .map_err(io::Error::from)?;
+        writeln!(self.out)?;
+        Ok(())
+    }
+}
+
+/* --format csv用(csv機能)。file,line_number,contentの見出しの後に、出力する行を1行ずつ書く。
+line_numberはそのファイルで出力した行の中での番号。開けなかったファイルと途中で読めなくなったファイルは
+いつもどおりstderrに報告する */
+#[cfg(feature = "csv")]
+struct CsvSink<'a, W: Write> {
+    config: &'a Config,
+    writer: csv::Writer<&'a mut W>,
+    file: String,
+}
+
+#[cfg(feature = "csv")]
+impl<'a, W: Write> CsvSink<'a, W> {
+    fn new(config: &'a Config, out: &'a mut W) -> MyResult<Self> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer
+            .write_record(["file", "line_number", "content"])
+            .map_err(io::Error::from)?;
+        Ok(CsvSink {
+            config,
+            writer,
+            file: String::new(),
+        })
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<W: Write> HeadSink for CsvSink<'_, W> {
+    fn begin_file(&mut self, name: &str) -> MyResult<()> {
+        self.file = name.to_string();
+        Ok(())
+    }
+
+    fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()> {
+        let line = String::from_utf8_lossy(line);
+        self.writer
+            .write_record([&self.file, &n.to_string(), &*line])
+            .map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    // csvは行単位でしか使えないので、ここには来ない。来ても1行として書く
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+        self.write_line(1, bytes)
+    }
+
+    fn end_file(
+        &mut self,
+        _name: &str,
+        _total: Option<usize>,
+    ) -> MyResult<()> {
+        Ok(())
+    }
+
+    fn fail_file(&mut self, name: &str, e: &HeadError) -> MyResult<()> {
+        report_error(self.config, name, e);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/* --format ndjson用(json機能)。出力する行ごとに{"file": ..., "n": 行番号, "text": 区切り文字を除いた行}を1行で書く。
+全体を配列に溜めず、1行受け取るごとに書き出す。途中で読めなくなったファイルは、書き出した行の後にエラーを書く。
+開けなかったファイルは--format jsonと同じく{"file": ..., "error": ...}にする */
+#[cfg(feature = "json")]
+struct NdjsonSink<'a, W> {
+    out: &'a mut W,
+    file: String,
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> NdjsonSink<'_, W> {
+    fn write_entry(&mut self, entry: &serde_json::Value) -> MyResult<()> {
+        serde_json::to_writer(&mut *self.out, entry)
+            .map_err(io::Error::from)?;
+        writeln!(self.out)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> HeadSink for NdjsonSink<'_, W> {
+    fn begin_file(&mut self, name: &str) -> MyResult<()> {
+        self.file = name.to_string();
+        Ok(())
+    }
+
+    fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()> {
+        let entry = serde_json::json!({
+            "file": self.file,
+            "n": n,
+            "text": String::from_utf8_lossy(line),
+        });
+        self.write_entry(&entry)
+    }
+
+    // ndjsonも行単位でしか使えないので、ここには来ない。来ても1行として書く
+    fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+        self.write_line(1, bytes)
+    }
+
+    fn end_file(
+        &mut self,
+        _name: &str,
+        _total: Option<usize>,
+    ) -> MyResult<()> {
+        Ok(())
+    }
+
+    fn fail_file(&mut self, name: &str, e: &HeadError) -> MyResult<()> {
+        self.write_entry(
+            &serde_json::json!({ "file": name, "error": e.to_string() }),
+        )
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        Ok(())
+    }
+}
+
+/* ファイルを順番に読み、sinkに渡す。開けなかったファイルの数を返す */
+fn run_serial(config: &Config, sink: &mut impl HeadSink) -> MyResult<usize> {
+    let mut num_failed = 0;
+    let mut timings = Vec::new();
+    let mut seen_heads = HashMap::new(); // --dedup-heads: 先頭から最初のファイル名
+    for filename in &config.files {
+        if sink.is_full() || stop_early(config, num_failed) {
+            break;
+        }
+        let started = std::time::Instant::now();
+        /* --exactと--min-linesでは足りないファイルを何も出力せずにエラーにするので、いったんバッファに読む。
+        --dedup-headsでも前のファイルと比べるために読む */
+        if config.exact || config.min_lines.is_some() || config.dedup_heads {
+            let result = read_head(config, filename);
+            let prior = match &result {
+                Ok(Ok(head)) if config.dedup_heads => {
+                    // ハッシュだけでなく中身まで比べる
+                    let prior =
+                        seen_heads.entry(head.clone()).or_insert(filename);
+                    Some(*prior).filter(|prior| *prior != filename)
+                }
+                _ => None,
+            };
+            let noted = match prior {
+                Some(prior) => sink.same_file(filename, prior)?,
+                None => false,
+            };
+            if !noted {
+                num_failed += print_buffered(config, filename, result, sink)?;
+            }
+        } else {
+            num_failed += print_streamed(config, filename, sink)?;
+        }
+        if config.timing {
+            let elapsed = started.elapsed();
+            eprintln!("{}: {}", filename, format_elapsed(elapsed));
+            timings.push((filename, elapsed));
+        }
+    }
+    sink.finish()?;
+    if timings.len() > 1 {
+        report_timings(&timings);
+    }
+    Ok(num_failed)
+}
+
+/* 開いたファイルを溜めずにsinkへ書き出す。開けなかったか、途中で読めなくなったら1を返す
+(開けなかった理由によっては0) */
+fn print_streamed(
+    config: &Config,
+    filename: &str,
+    sink: &mut impl HeadSink,
+) -> MyResult<usize> {
+    print_streamed_from(config, filename, open_text(config, filename), sink)
+}
+
+/* 開いた結果から書き出す版。read_head_fromと同じくrun_asyncが使う */
+fn print_streamed_from(
+    config: &Config,
+    filename: &str,
+    opened: MyResult<Input>,
+    sink: &mut impl HeadSink,
+) -> MyResult<usize> {
+    let input = match opened {
+        Err(e) => {
+            sink.fail_file(filename, &e)?;
+            return Ok(is_failure(&e) as usize);
+        }
+        Ok(input) => input,
+    };
+    sink.begin_file(filename)?;
+    let mut writer = SinkWriter::new(config, sink);
+    let result = print_input(config, filename, input, &mut writer)?;
+    writer.finish()?;
+    match result {
+        Ok(total_lines) => {
+            sink.end_file(filename, total_lines)?;
+            Ok(0)
+        }
+        // 読み込みに失敗しても、そこまでの出力は残して次のファイルに進む
+        Err(e) => {
+            sink.fail_file(filename, &e)?;
+            Ok(1)
+        }
+    }
+}
+
+/* read_headで読み込んだ1ファイル分をsinkに書き出す。開けなかったファイルなら1を返す */
+fn print_buffered(
+    config: &Config,
+    filename: &str,
+    result: MyResult<MyResult<Vec<u8>>>,
+    sink: &mut impl HeadSink,
+) -> MyResult<usize> {
+    match result {
+        Err(e) => {
+            sink.fail_file(filename, &e)?;
+            Ok(is_failure(&e) as usize)
+        }
+        /* 読み込み中に失敗したファイルは、開けなかったときと同じくヘッダーも出さない */
+        Ok(Err(e)) => {
+            sink.fail_file(filename, &e)?;
+            Ok(1)
+        }
+        Ok(Ok(buffer)) => {
+            sink.begin_file(filename)?;
+            let mut writer = SinkWriter::new(config, sink);
+            writer.write_all(&buffer)?;
+            writer.finish()?;
+            sink.end_file(filename, None)?;
+            Ok(0)
+        }
+    }
+}
+
+/* --dedup-heads。中身を出力する代わりに、ヘッダーの後ろに同じ先頭を出力したファイルの名前を付ける。
+-qなどでヘッダーを出さない場合も、黙って落とさずに注記だけ書く */
+fn print_same_header(
+    config: &Config,
+    num_printed: usize,
+    filename: &str,
+    prior: &str,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut header = Vec::new();
+    print_header(config, num_printed, filename, &mut header)?;
+    match header.strip_suffix(b"\n") {
+        Some(header) => {
+            out.write_all(header)?;
+            writeln!(out, " (same as {})", prior)?;
+        }
+        None => writeln!(out, "(same as {})", prior)?,
+    }
+    Ok(())
+}
+
+/* --timing。最後に全体の時間と、一番時間のかかったファイルを報告する */
+fn report_timings(timings: &[(&String, std::time::Duration)]) {
+    let total: std::time::Duration =
+        timings.iter().map(|(_, elapsed)| *elapsed).sum();
+    if let Some((filename, slowest)) =
+        timings.iter().max_by_key(|(_, elapsed)| *elapsed)
+    {
+        eprintln!(
+            "total: {}, slowest: {} ({})",
+            format_elapsed(total),
+            filename,
+            format_elapsed(*slowest)
+        );
+    }
+}
+
+/* --timingの表示。1ms未満はマイクロ秒で示す */
+fn format_elapsed(elapsed: std::time::Duration) -> String {
+    match elapsed.as_millis() {
+        0 => format!("{}us", elapsed.as_micros()),
+        millis => format!("{}ms", millis),
+    }
+}
+
+/* --atomic-headers。1ファイル分の出力(ヘッダーと中身)をためておき、commitで1回のwrite_allにまとめる。
+ためている間のflushは何もしない */
+struct AtomicWriter<W> {
+    inner: W,
+    buffer: Option<Vec<u8>>,
+}
+
+impl<W: Write> AtomicWriter<W> {
+    fn new(inner: W, atomic: bool) -> Self {
+        AtomicWriter {
+            inner,
+            buffer: atomic.then(Vec::new),
+        }
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        match &mut self.buffer {
+            Some(buffer) if !buffer.is_empty() => {
+                self.inner.write_all(buffer)?;
+                buffer.clear();
+                self.inner.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for AtomicWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.buffer {
+            Some(buffer) => {
+                buffer.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.buffer {
+            Some(_) => Ok(()),
+            None => self.inner.flush(),
+        }
+    }
+}
+
+/* --interleave。全てのファイルを先に開いておき、1行目を順に、次に2行目を順に、と-nの行数まで書き出す。
+各行の前にはファイル名を付ける。読み終えたファイルは次の回から飛ばす */
+fn print_interleaved(
+    config: &Config,
+    out: &mut impl Write,
+) -> MyResult<usize> {
+    let delimiter = config.delimiter();
+    let mut num_failed = 0;
+    let mut readers = Vec::new();
+    for filename in &config.files {
+        if stop_early(config, num_failed) {
+            return Ok(num_failed);
+        }
+        let input = open_text(config, filename).and_then(|input| {
+            let input = if config.strip_bom {
+                strip_bom(input)?
+            } else {
+                input
+            };
+            skip_input(config, input, delimiter)
+        });
+        match input {
+            Ok(input) => {
+                let label = match filename.as_str() {
+                    "-" => "standard input",
+                    _ => filename,
+                };
+                readers.push((
+                    filename,
+                    label,
+                    input.reader,
+                    FilterChain::new(config)?,
+                ));
+            }
+            Err(e) => {
+                num_failed += is_failure(&e) as usize;
+                report_error(config, filename, &e);
+            }
+        }
+    }
+
+    let num_lines = match config.lines {
+        Count::First(num_lines) => num_lines,
+        Count::AllButLast(_) => 0,
+    };
+    let mut content = ContentWriter::new(config, out);
+    for line_num in 1..=num_lines {
+        if readers.is_empty() || content.is_exhausted() {
+            break;
+        }
+        let mut reader_num = 0;
+        while reader_num < readers.len() {
+            if stop_early(config, num_failed) {
+                return Ok(num_failed);
+            }
+            let (filename, label, reader, chain) = &mut readers[reader_num];
+            let mut line = Vec::new();
+            match reader.read_until(delimiter, &mut line) {
+                Ok(0) => {
+                    readers.remove(reader_num);
+                    continue;
+                }
+                Ok(_) => {}
+                /* 読めなくなったファイルは報告して、次の回から飛ばす */
+                Err(e) => {
+                    num_failed += 1;
+                    report_error(config, filename, &HeadError::Io(e));
+                    readers.remove(reader_num);
+                    continue;
+                }
+            }
+            // 最後の行が区切り文字で終わっていなくても、次のファイルの行とつなげない
+            if line.last() != Some(&delimiter) {
+                line.push(delimiter);
+            }
+            let line = String::from_utf8_lossy(&line);
+            print_labeled_line(
+                config,
+                chain,
+                label,
+                line_num,
+                &line,
+                &mut content,
+            )?;
+            reader_num += 1;
+        }
+    }
+    Ok(num_failed)
+}
+
+/* config.jobs個のスレッドで各ファイルの先頭をバッファに読み込み、引数の順番どおりにsinkへ渡す。
+標準入力("-")はスレッドで読まず、順番が来たときにこのスレッドで読む */
+fn run_parallel(config: &Config, sink: &mut impl HeadSink) -> MyResult<usize> {
+    let files = &config.files;
+    let next_file = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..config.jobs {
+            let tx = tx.clone();
+            let next_file = &next_file;
+            scope.spawn(move || loop {
+                let file_num = next_file.fetch_add(1, Ordering::Relaxed);
+                match files.get(file_num) {
+                    None => break,
+                    Some(filename) if filename == "-" => continue,
+                    Some(filename) => {
+                        let result = read_head(config, filename);
+                        if tx.send((file_num, result)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut finished = HashMap::new();
+        let mut num_failed = 0;
+        for (file_num, filename) in files.iter().enumerate() {
+            if sink.is_full() || stop_early(config, num_failed) {
+                // まだ読み始めていないファイルはスレッドにも読ませない
+                next_file.store(files.len(), Ordering::Relaxed);
+                break;
+            }
+            let result = if filename == "-" {
+                read_head(config, filename)
+            } else {
+                loop {
+                    if let Some(result) = finished.remove(&file_num) {
+                        break result;
+                    }
+                    match rx.recv() {
+                        Ok((num, result)) => {
+                            finished.insert(num, result);
+                        }
+                        /* スレッドがパニックで全て終わっていたら、そのファイルは読めなかったものとする。
+                        パニック自体はthread::scopeを抜けるときに伝わる */
+                        Err(_) => {
+                            break Err(HeadError::Io(io::Error::other(
+                                "worker thread exited before reading the file",
+                            )))
+                        }
+                    }
+                }
+            };
+
+            num_failed += print_buffered(config, filename, result, sink)?;
+        }
+        sink.finish()?;
+        Ok(num_failed)
+    })
+}
+
+/* ファイルの中身だけを書き出す(ヘッダーはinnerに直接書く)。
+--max-total-lines/--max-total-bytesの上限に達したら以降の書き込みは捨て、
+--stats用に実際に書き出した量を数える */
+struct ContentWriter<'a, W> {
+    inner: &'a mut W,
+    lines_left: Option<usize>,
+    bytes_left: Option<usize>,
+    delimiter: u8,
+    flush_each: bool,                // --line-buffered
+    pending_header: Option<Vec<u8>>, // --skip-empty: 最初に書くときまで出さないヘッダー
+    stats: Stats,
+    #[cfg(feature = "graphemes")]
+    count_graphemes: bool, // 書記素の区切りを調べるのは重いので--statsのときだけ数える
+    #[cfg(feature = "checksum")]
+    hasher: Option<Box<dyn sha2::digest::DynDigest + Send>>,
+}
+
+/* 1つのファイルについて実際に出力した量 */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Stats {
+    lines: usize,
+    chars: usize,
+    graphemes: usize,
+    bytes: usize,
+    in_line: bool, // 最後の行が区切り文字で終わっていない
+    total_lines: Option<usize>, // --total-lines: 出力しなかった分も含めた入力全体の行数
+    /* 次の書き込みで続くかもしれない最後の書記素(と途中で切れた文字)のバイト列 */
+    pending: Vec<u8>,
+}
+
+impl Stats {
+    fn add(&mut self, buf: &[u8], delimiter: u8) {
+        if let Some(last) = buf.last() {
+            self.in_line = *last != delimiter;
+        }
+        self.lines += buf.iter().filter(|byte| **byte == delimiter).count();
+        // UTF-8の継続バイト(0b10xxxxxx)以外を1文字として数える
+        self.chars += buf.iter().filter(|byte| **byte & 0xc0 != 0x80).count();
+        self.bytes += buf.len();
+    }
+
+    /* 書記素は書き込みの境目をまたぐことがあるので、最後の1つは次の書き込みまで数えずに残す。
+    UTF-8として不正なバイトは1バイトを1つと数える */
+    #[cfg(feature = "graphemes")]
+    fn add_graphemes(&mut self, buf: &[u8]) {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        self.pending.extend_from_slice(buf);
+        let mut rest = &self.pending[..];
+        loop {
+            let (valid, error_len) = match std::str::from_utf8(rest) {
+                Ok(text) => (text, None),
+                Err(e) => (
+                    std::str::from_utf8(&rest[..e.valid_up_to()])
+                        .unwrap_or_default(),
+                    e.error_len(),
+                ),
+            };
+            match error_len {
+                Some(len) => {
+                    self.graphemes += valid.graphemes(true).count() + 1;
+                    rest = &rest[valid.len() + len..];
+                }
+                None => {
+                    let last = valid
+                        .grapheme_indices(true)
+                        .next_back()
+                        .map_or(valid.len(), |(pos, _)| pos);
+                    self.graphemes += valid[..last].graphemes(true).count();
+                    rest = &rest[last..];
+                    break;
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+    }
+
+    /* 区切り文字で終わっていない最後の行も1行と数える */
+    fn num_lines(&self) -> usize {
+        self.lines + usize::from(self.in_line)
+    }
+
+    fn num_graphemes(&self) -> usize {
+        self.graphemes + usize::from(!self.pending.is_empty())
+    }
+}
+
+impl<'a, W: Write> ContentWriter<'a, W> {
+    fn new(config: &Config, inner: &'a mut W) -> Self {
+        ContentWriter {
+            inner,
+            lines_left: config.max_total_lines,
+            bytes_left: config.max_total_bytes,
+            delimiter: config.delimiter(),
+            flush_each: config.line_buffered && !config.byte_mode(),
+            pending_header: None,
+            stats: Stats::default(),
+            #[cfg(feature = "graphemes")]
+            count_graphemes: config.stats && !config.raw,
+            #[cfg(feature = "checksum")]
+            hasher: config.checksum.map(Checksum::hasher),
+        }
+    }
+
+    fn take_stats(&mut self) -> Stats {
+        std::mem::take(&mut self.stats)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.lines_left == Some(0) || self.bytes_left == Some(0)
+    }
+}
+
+impl<W: Write> Write for ContentWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut end = buf.len();
+        if let Some(bytes_left) = self.bytes_left {
+            end = end.min(bytes_left);
+        }
+        if let Some(lines_left) = self.lines_left {
+            /* lines_left個目の区切り文字の直後までに切り詰める */
+            if lines_left == 0 {
+                end = 0;
+            } else if let Some(pos) = buf[..end]
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == self.delimiter)
+                .nth(lines_left - 1)
+                .map(|(pos, _)| pos + 1)
+            {
+                end = pos;
+            }
+        }
+
+        if end > 0 {
+            if let Some(header) = self.pending_header.take() {
+                self.inner.write_all(&header)?;
+            }
+        }
+        self.inner.write_all(&buf[..end])?;
+        self.stats.add(&buf[..end], self.delimiter);
+        #[cfg(feature = "graphemes")]
+        if self.count_graphemes {
+            self.stats.add_graphemes(&buf[..end]);
+        }
+        #[cfg(feature = "checksum")]
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..end]);
+        }
+        if let Some(bytes_left) = self.bytes_left.as_mut() {
+            *bytes_left -= end;
+        }
+        if let Some(lines_left) = self.lines_left.as_mut() {
+            let written = buf[..end]
+                .iter()
+                .filter(|byte| **byte == self.delimiter)
+                .count();
+            *lines_left -= written;
+        }
+        if self.flush_each && buf[..end].contains(&self.delimiter) {
+            self.inner.flush()?;
+        }
+        Ok(buf.len()) // 捨てた分も書いたことにする
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/* print_headで出力し、入力から読めなかったエラーは内側のErrとして返す。
+外側のErrは書き込みのエラー(と--exact、--min-linesで足りなかったこと)で、
+書き込みのエラーなら残りのファイルも書けないものとして中断する。
+--total-linesでは、内側のOkで入力全体の行数を返す */
+fn print_input(
+    config: &Config,
+    filename: &str,
+    input: Input,
+    out: &mut impl Write,
+) -> MyResult<MyResult<Option<usize>>> {
+    /* --min-lines、--total-linesと--with-totalでは、print_headが読み終えた後も同じ入力の続きを数える */
+    let total_lines =
+        config.total_lines || (config.with_total && !config.byte_mode());
+    let counted = total_lines || config.min_lines.is_some();
+    let (reader, counter): (Box<dyn BufRead>, _) = match counted {
+        true => {
+            let counter = Rc::new(RefCell::new(LineCounter::new(
+                input.reader,
+                config.delimiter(),
+            )));
+            let shared = SharedReader(Rc::clone(&counter));
+            (Box::new(BufReader::new(shared)), Some(counter))
+        }
+        false => (input.reader, None),
+    };
+    let failed = Rc::new(Cell::new(false));
+    let input = Input {
+        reader: Box::new(WatchRead {
+            inner: reader,
+            failed: Rc::clone(&failed),
+        }),
+        size: input.size,
+    };
+    match print_head(config, filename, input, out) {
+        Err(e) if failed.get() => return Ok(Err(e)),
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+
+    if let (Some(min_lines), Some(counter)) = (config.min_lines, &counter) {
+        match counter.borrow_mut().count_until(min_lines) {
+            Err(e) => return Ok(Err(HeadError::Io(e))),
+            Ok(total) if total < min_lines => {
+                return Err(HeadError::ShortInput(
+                    filename.to_string(),
+                    total,
+                    min_lines,
+                    "lines",
+                ))
+            }
+            Ok(_) => {}
+        }
+    }
+    if let (true, Some(counter)) = (total_lines, counter) {
+        let total = counter.borrow_mut().count_until(usize::MAX);
+        return Ok(total.map(Some).map_err(HeadError::Io));
+    }
+    Ok(Ok(None))
+}
+
+/* --min-lines、--total-lines、--with-totalとhead_with_total用。元の入力から読んだ分の行を数える */
+struct LineCounter<R = Box<dyn BufRead>> {
+    inner: R,
+    delimiter: u8,
+    lines: usize,
+    last: Option<u8>, // 最後に読んだバイト
+}
+
+impl<R: Read> LineCounter<R> {
+    fn new(inner: R, delimiter: u8) -> Self {
+        LineCounter {
+            inner,
+            delimiter,
+            lines: 0,
+            last: None,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        let delimiter = self.delimiter;
+        self.lines +=
+            buf[..len].iter().filter(|byte| **byte == delimiter).count();
+        if len > 0 {
+            self.last = Some(buf[len - 1]);
+        }
+        Ok(len)
+    }
+
+    /* min_lines行に届くか入力が終わるまで読んで、数えた行数を返す。
+    区切り文字で終わっていない最後の行も1行と数える */
+    fn count_until(&mut self, min_lines: usize) -> io::Result<usize> {
+        let mut buf = [0; 8192];
+        while self.lines < min_lines {
+            if self.read(&mut buf)? == 0 {
+                break;
+            }
+        }
+        let in_line = self.last.is_some_and(|last| last != self.delimiter);
+        Ok(self.lines + usize::from(in_line && self.lines < min_lines))
+    }
+}
+
+struct SharedReader<R = Box<dyn BufRead>>(Rc<RefCell<LineCounter<R>>>);
+
+impl<R: Read> Read for SharedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/* 読み込みのエラーが起きたらfailedに記録する */
+struct WatchRead<R> {
+    inner: R,
+    failed: Rc<Cell<bool>>,
+}
+
+impl<R> WatchRead<R> {
+    fn watch<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if result.is_err() {
+            self.failed.set(true);
+        }
+        result
+    }
+}
+
+impl<R: Read> Read for WatchRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.watch(result)
+    }
+}
+
+impl<R: BufRead> BufRead for WatchRead<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // 借用の都合で、成功したときはもう一度呼んで(バッファ済みの)中身を返す
+        if let Err(e) = self.inner.fill_buf() {
+            self.failed.set(true);
+            return Err(e);
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+/* シグナルで中断された(Interruptedの)読み込みはやり直す。read_untilなどは自分でやり直すが、
+fill_bufやreadを直接呼ぶ所のために、開いた入力をこれで包む */
+struct RetryInterrupted<R>(R);
+
+/* 入力を開いたらすぐ包み、展開などはその上で行う */
+fn retry_interrupted(input: Input) -> Input {
+    Input {
+        reader: Box::new(RetryInterrupted(input.reader)),
+        size: input.size,
+    }
+}
+
+impl<R: Read> Read for RetryInterrupted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for RetryInterrupted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // 借用の都合で、中断されなくなるまで呼んでから、もう一度呼んで中身を返す
+        while let Err(e) = self.0.fill_buf() {
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+/* 1つのファイルの先頭をバッファに読み込む。外側のErrは開けなかった場合、内側は読み込み中のエラー */
+/* --exactや--min-linesで足りなかったのも、開けなかったのと同じく外側のErrにする */
+fn read_head(config: &Config, filename: &str) -> MyResult<MyResult<Vec<u8>>> {
+    read_head_from(config, filename, open_text(config, filename))
+}
+
+/* 開いた結果から読む版。run_asyncではURLを非同期に取得したものを渡す */
+fn read_head_from(
+    config: &Config,
+    filename: &str,
+    opened: MyResult<Input>,
+) -> MyResult<MyResult<Vec<u8>>> {
+    opened.and_then(|input| {
+        let mut buffer = Vec::new();
+        match print_input(config, filename, input, &mut buffer) {
+            Err(e @ HeadError::ShortInput(..)) => Err(e),
+            // バッファへの書き込みは失敗しないので、これも読み込み中のエラー
+            Err(e) => Ok(Err(e)),
+            Ok(result) => Ok(result.map(|_| buffer)),
+        }
+    })
+}
+
+/* ヘッダーを書いてからファイルの中身を書き始める。
+--skip-emptyではヘッダーを保留し、中身を最初に書くときに出す */
+fn start_file<W: Write>(
+    config: &Config,
+    num_printed: usize,
+    filename: &str,
+    content: &mut ContentWriter<W>,
+) -> MyResult<()> {
+    if config.skip_empty {
+        let mut header = Vec::new();
+        print_header(config, num_printed, filename, &mut header)?;
+        content.pending_header = Some(header);
+        Ok(())
+    } else {
+        print_header(config, num_printed, filename, content.inner)
+    }
+}
+
+/* 1つのファイルを書き終えたら、--stats、--with-totalと--checksumの結果を報告する。
+--skip-emptyで何も出力しなかったファイルは報告せずにfalseを返す */
+fn finish_file<W: Write>(
+    config: &Config,
+    filename: &str,
+    content: &mut ContentWriter<W>,
+) -> MyResult<bool> {
+    if content.pending_header.take().is_some() {
+        return Ok(false);
+    }
+    let stats = content.take_stats();
+    log::debug!(
+        "{}: printed {} lines, {} bytes",
+        filename,
+        stats.num_lines(),
+        stats.bytes
+    );
+    report_stats(config, filename, &stats);
+    /* --with-total。-cでは行数を数えないので付けない */
+    if let (true, Some(total)) =
+        (config.with_total && !config.byte_mode(), stats.total_lines)
+    {
+        let newline = if stats.in_line { "\n" } else { "" };
+        writeln!(content.inner, "{}({} lines total)", newline, total)?;
+    }
+    #[cfg(feature = "checksum")]
+    if let (Some(checksum), Some(hasher)) =
+        (config.checksum, content.hasher.as_mut())
+    {
+        let digest: String = hasher
+            .finalize_reset()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let line = format!("{}: {}={}", filename, checksum.name(), digest);
+        if config.checksum_stdout {
+            // 中身が改行で終わっていなければ、行を改めてから書く
+            let newline = if stats.in_line { "\n" } else { "" };
+            writeln!(content.inner, "{}{}", newline, line)?;
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+    Ok(true)
+}
+
+/* どの指定で止めたのかも、--dry-runと同じ書き方で括弧に入れて示す */
+fn report_stats(config: &Config, filename: &str, stats: &Stats) {
+    let total = match stats.total_lines {
+        Some(total) => format!(", {} lines in total", total),
+        None => String::new(),
+    };
+    // --rawでは中身を文字として読まないので、バイト数だけを報告する
+    if config.stats && config.raw {
+        eprintln!(
+            "{}: {}{} (limit: {})",
+            filename,
+            format_bytes(stats.bytes, config.pretty_bytes),
+            total,
+            describe_limit(config)
+        );
+    } else if config.stats {
+        // 書記素はgraphemes機能を有効にしたときだけ数える
+        let graphemes = if cfg!(feature = "graphemes") {
+            format!("{} graphemes, ", stats.num_graphemes())
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "{}: {} lines, {} chars, {}{}{} (limit: {})",
+            filename,
+            stats.num_lines(),
+            stats.chars,
+            graphemes,
+            format_bytes(stats.bytes, config.pretty_bytes),
+            total,
+            describe_limit(config)
+        );
+    }
+}
+
+/* --statsのバイト数。単位を付けるときは-cの接尾辞の逆で、小数1桁まで示す。
+丸めると基数に届く値(1023.96 KiBなど)は1つ上の単位にする */
+fn format_bytes(bytes: usize, units: Option<ByteUnits>) -> String {
+    let (base, suffixes) = match units {
+        None => return format!("{} bytes", bytes),
+        Some(ByteUnits::Iec) => {
+            (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+        }
+        Some(ByteUnits::Si) => (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"]),
+    };
+    let mut value = bytes as f64;
+    let mut suffix = None;
+    for next in suffixes {
+        if value < base - 0.05 {
+            break;
+        }
+        value /= base;
+        suffix = Some(next);
+    }
+    match suffix {
+        Some(suffix) => format!("{:.1} {}", value, suffix),
+        None => format!("{} B", bytes),
+    }
+}
+
+/* バイナリファイルとディレクトリのループは読み飛ばすだけで、失敗には数えない */
+fn is_failure(e: &HeadError) -> bool {
+    !matches!(e, HeadError::BinaryFile(_) | HeadError::DirectoryLoop(_))
+}
+
+/* --ignore-errorsなら何も表示しない */
+fn report_error(config: &Config, filename: &str, e: &HeadError) {
+    let msg = match e {
+        HeadError::Io(e) => format!("{}: {}", filename, e),
+        e => e.message(),
+    };
+    log::debug!("{}", msg);
+    if config.ignore_errors {
+        return;
+    }
+    match config.error_format {
+        ErrorFormat::Plain => eprintln!("{}", msg),
+        #[cfg(feature = "json")]
+        ErrorFormat::Json => eprintln!("{}", error_json(filename, e)),
+    }
+}
+
+/* --error-format json。errorにはファイル名を付けない */
+#[cfg(feature = "json")]
+fn error_json(filename: &str, e: &HeadError) -> serde_json::Value {
+    let msg = e.to_string();
+    let msg = msg
+        .strip_prefix(filename)
+        .and_then(|msg| msg.strip_prefix(": "))
+        .unwrap_or(&msg);
+    serde_json::json!({ "file": filename, "error": msg, "kind": e.kind() })
+}
+
+/* num_printedはこれまでに出力したファイルの数。2つ目以降のヘッダーの前には空行を入れる。
+前のファイルが改行で終わっていなくても、この改行でヘッダーは必ず行頭から始まる。
+空行の数は--separator-linesで変えられ、0なら前のファイルの直後にヘッダーを書く。
+開けなかったファイルは数えないので、最初のファイルが無くても先頭に空行は出ない */
+fn print_header(
+    config: &Config,
+    num_printed: usize,
+    filename: &str,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    /* --print0ではヘッダーの代わりに、2つ目以降のファイルの前にNULを1つ書く */
+    if config.print0 {
+        if num_printed > 0 {
+            out.write_all(b"\0")?;
+        }
+        return Ok(());
+    }
+    if config.verbose || (config.files.len() > 1 && !config.quiet) {
+        // GNU headと同じく、標準入力は"-"ではなく"standard input"と表示する
+        let label = match filename {
+            "-" => "standard input".into(),
+            _ if config.absolute_paths => absolute_path(filename),
+            _ => Cow::from(filename),
+        };
+        let (before, after) = config
+            .header_format
+            .split_once("{}")
+            .unwrap_or(("==> ", " <=="));
+        let header = format!("{}{}{}", before, label, after);
+        writeln!(
+            out,
+            "{}{}",
+            if num_printed > 0 {
+                "\n".repeat(config.separator_lines)
+            } else {
+                String::new()
+            },
+            match config.color {
+                ColorChoice::Always => format!("\x1b[1;36m{}\x1b[0m", header), // 太字のシアン
+                _ => header,
+            }
+        )?;
+    }
+    Ok(())
+}
+
+/* --absolute-paths。ヘッダーに出す名前だけを絶対パスにし、ファイルは元の名前で開く。
+URLなどのファイルでないものや、開いた後に消されたファイルは元の名前のままにする */
+fn absolute_path(filename: &str) -> Cow<'_, str> {
+    match fs::canonicalize(filename) {
+        Ok(path) => path.to_string_lossy().into_owned().into(),
+        Err(_) => filename.into(),
+    }
+}
+
+/* 設定に従って1つの入力の先頭(または末尾を除いた部分)を出力する */
+fn print_head(
+    config: &Config,
+    filename: &str,
+    input: Input,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    // --validate-utf8のエラーは入力の中での位置だけを持って返ってくるので、ファイル名を付ける
+    print_head_to(config, filename, input, out).map_err(|e| match e {
+        HeadError::InvalidUtf8(_, offset) => {
+            HeadError::InvalidUtf8(filename.to_string(), offset)
+        }
+        e => e,
+    })
+}
+
+fn print_head_to(
+    config: &Config,
+    filename: &str,
+    input: Input,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let delimiter = config.delimiter();
+    /* --byte-offsetと--validate-utf8では、BOMや--skipで読み飛ばした分も含めて入力の中の位置を数える */
+    let consumed = Rc::new(Cell::new(0));
+    let track_offsets = config.byte_offset || config.validate_utf8;
+    let input = match track_offsets {
+        true => Input {
+            reader: Box::new(Consumed {
+                inner: input.reader,
+                count: Rc::clone(&consumed),
+            }),
+            size: input.size,
+        },
+        false => input,
+    };
+    let input = if config.strip_bom {
+        strip_bom(input)?
+    } else {
+        input
+    };
+    let input = match config.keep_header {
+        true => print_first_line(
+            config,
+            input,
+            track_offsets.then(|| consumed.get()),
+            delimiter,
+            out,
+        )?,
+        false => input,
+    };
+    let input = match config.use_index {
+        true => skip_with_index(config, filename, input, delimiter)?,
+        false => skip_input(config, input, delimiter)?,
+    };
+    let Input {
+        reader: mut file,
+        size,
+    } = input;
+
+    /* --first-limitでは-cのバイト数までしか読まずに、行単位で出力する */
+    let bytes = match config.bytes {
+        Some(ByteSpec::Count(Count::First(max_bytes)))
+            if config.first_limit =>
+        {
+            file = Box::new(file.take(max_bytes as u64));
+            None
+        }
+        bytes => bytes,
+    };
+
+    let offset = track_offsets.then(|| consumed.get());
+    let line_mode = config.percent.is_some()
+        || bytes.is_none()
+            && config.bytes_percent.is_none()
+            && config.chars.is_none()
+            && config.graphemes.is_none()
+            && config.words.is_none();
+    if line_mode {
+        if config.reverse_lines {
+            /* --reverse-lines。選んだ行を整形せずにバッファに記録してから、逆順に整形して出力する。
+            --exactで足りなかったときも、読めた分は出力してからエラーにする */
+            let mut buffer = Vec::new();
+            let result = print_selected_lines(
+                config,
+                filename,
+                file,
+                size,
+                offset,
+                delimiter,
+                &mut buffer,
+            );
+            write_reversed(config, &buffer, out)?;
+            return result;
+        }
+        return print_selected_lines(
+            config, filename, file, size, offset, delimiter, out,
+        );
+    }
+
+    /* 行に分けないものは、書き出すバイト列をそのまま調べる。
+    行頭の表示は無いので、書き出した位置に読み飛ばした分を足せば入力の中の位置になる */
+    if !config.validate_utf8 {
+        return print_head_units(config, filename, file, size, bytes, out);
+    }
+    let mut checked = Utf8Check::new(out);
+    let result =
+        print_head_units(config, filename, file, size, bytes, &mut checked)
+            .and_then(|()| Ok(checked.finish()?));
+    match checked.invalid_at {
+        Some(at) => Err(HeadError::InvalidUtf8(
+            String::new(),
+            offset.unwrap_or(0) + at,
+        )),
+        None => result,
+    }
+}
+
+/* -nか--percentで選んだ行を出力する */
+fn print_selected_lines(
+    config: &Config,
+    filename: &str,
+    mut file: Box<dyn BufRead>,
+    size: Option<u64>,
+    offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    match config.percent {
+        Some(percent) => print_percent_lines(
+            config, filename, file, size, percent, offset, out,
+        ),
+        None => {
+            print_lines(config, filename, &mut file, offset, delimiter, out)
+        }
+    }
+}
+
+/* -c、--bytes-percent、--chars、--graphemes、--words。行に分けずに出力する */
+fn print_head_units(
+    config: &Config,
+    filename: &str,
+    mut file: Box<dyn BufRead>,
+    size: Option<u64>,
+    bytes: Option<ByteSpec>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    if let Some(percent) = config.bytes_percent {
+        return print_percent_bytes(
+            config, filename, file, size, percent, out,
+        );
+    }
+
+    if let Some(num_chars) = config.chars {
+        let text = head_chars(&mut file, num_chars)?;
+        out.write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "graphemes")]
+    if let Some(num_graphemes) = config.graphemes {
+        let text = head_graphemes(&mut file, num_graphemes)?;
+        out.write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    if let Some(num_words) = config.words {
+        let buffer = head_words(&mut file, num_words)?;
+        out.write_all(&buffer)?;
+        return Ok(());
+    }
+
+    match bytes {
+        Some(spec) if config.hex => {
+            let mut dump = HexDump::new(out);
+            print_byte_spec(
+                config, filename, spec, &mut file, size, &mut dump,
+            )?;
+            dump.finish()?;
+        }
+        Some(spec) => {
+            print_byte_spec(config, filename, spec, &mut file, size, out)?
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/* 行単位の指定に従って出力する。offsetは--byte-offsetと--validate-utf8のときの、最初の行の位置 */
+fn print_lines(
+    config: &Config,
+    filename: &str,
+    file: &mut impl BufRead,
+    mut offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    if let Some(ranges) = &config.line_ranges {
+        return print_line_ranges(
+            config, filename, file, ranges, offset, delimiter, out,
+        );
+    }
+    #[cfg(feature = "regex")]
+    if let Some(pattern) = &config.until {
+        return print_until(config, file, pattern, delimiter, out);
+    }
+    if config.paragraph {
+        return print_paragraphs(
+            config, filename, file, offset, delimiter, out,
+        );
+    }
+    match config.lines {
+        Count::First(num_lines) => {
+            /* --squeeze-blankなどのフィルターでは、まとめたり落としたりした後の行数で-nまで出力する。
+            --contextでは続きも読むので、どちらも読む行数は決めない */
+            let chain = FilterChain::new(config)?;
+            let limit = match config.context.is_some() || chain.drops_lines {
+                true => usize::MAX,
+                false => num_lines,
+            };
+            let mut lines = head_iter_bytes(file, limit, delimiter);
+            let mut found = 0;
+            let mut num_read = 0;
+            while found < num_lines {
+                let Some(line) = lines.next() else { break };
+                let line = line?;
+                num_read += 1;
+                let line_offset = offset;
+                offset = offset.map(|offset| offset + line.len() as u64);
+                // フィルターで落とした行は-nに数えない
+                if write_line(
+                    config,
+                    &chain,
+                    found + 1,
+                    line_offset,
+                    &line,
+                    out,
+                )? {
+                    found += 1;
+                }
+            }
+            if config.exact && found < num_lines {
+                return Err(HeadError::ShortInput(
+                    filename.to_string(),
+                    found,
+                    num_lines,
+                    "lines",
+                ));
+            }
+            if let Some(num_tail) = config.context {
+                print_context(
+                    config, &chain, num_read, lines, num_tail, offset, out,
+                )?;
+            }
+        }
+        Count::AllButLast(num_lines) => print_all_but_last_lines(
+            config, filename, file, num_lines, offset, delimiter, out,
+        )?,
+    }
+    Ok(())
+}
+
+/* --validate-utf8で行に分けずに出力するとき用。書き出すバイト列をUTF-8として調べ、
+不正なバイトの手前までを書いてエラーにする。invalid_atは書き出した中でのバイト位置 */
+struct Utf8Check<'a, W> {
+    inner: &'a mut W,
+    written: u64,
+    pending: Vec<u8>, // 書き込みの境目で途中まで来ている文字
+    invalid_at: Option<u64>,
+}
+
+impl<'a, W: Write> Utf8Check<'a, W> {
+    fn new(inner: &'a mut W) -> Self {
+        Utf8Check {
+            inner,
+            written: 0,
+            pending: Vec::new(),
+            invalid_at: None,
+        }
+    }
+
+    fn invalid(&mut self) -> io::Error {
+        self.invalid_at = Some(self.written);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8")
+    }
+
+    /* 最後の文字が途中で終わっていたらエラーにする */
+    fn finish(&mut self) -> io::Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(self.invalid()),
+        }
+    }
+}
+
+impl<W: Write> Write for Utf8Check<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let (valid, error_len) = match std::str::from_utf8(&self.pending) {
+            Ok(_) => (self.pending.len(), None),
+            Err(e) => (e.valid_up_to(), e.error_len()),
+        };
+        self.inner.write_all(&self.pending[..valid])?;
+        self.pending.drain(..valid);
+        self.written += valid as u64;
+        if error_len.is_some() {
+            return Err(self.invalid());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/* --byte-offset用。BufReadとして使われた(consumeされた)バイト数を数える */
+struct Consumed<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Consumed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.set(self.count.get() + len as u64);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Consumed<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count.set(self.count.get() + amt as u64);
+        self.inner.consume(amt)
+    }
+}
+
+/* --reverse-lines用。フィルターを通した1行を、行番号と位置と一緒にそのままバッファに記録する。
+-Nや--wrap、-Eなどの整形はwrite_reversedで逆順にしてから行う */
+fn defer_line(
+    line_num: usize,
+    offset: Option<u64>,
+    line: &[u8],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    out.write_all(&(line_num as u64).to_le_bytes())?;
+    out.write_all(&offset.unwrap_or(u64::MAX).to_le_bytes())?;
+    out.write_all(&(line.len() as u64).to_le_bytes())?;
+    out.write_all(line)
+}
+
+/* defer_lineで記録した行を後ろから整形して書き出す。最後の行に区切り文字がなければ付けて、
+逆順にしたときに次の行とつながらないようにする */
+fn write_reversed(
+    config: &Config,
+    buffer: &[u8],
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let delimiter = config.delimiter();
+    let mut records = Vec::new();
+    let mut rest = buffer;
+    while let Some((head, tail)) = rest.split_first_chunk::<24>() {
+        let field = |i: usize| {
+            u64::from_le_bytes(head[i * 8..i * 8 + 8].try_into().unwrap())
+        };
+        let Some((line, tail)) = usize::try_from(field(2))
+            .ok()
+            .and_then(|len| (len <= tail.len()).then(|| tail.split_at(len)))
+        else {
+            break;
+        };
+        let offset = Some(field(1)).filter(|&offset| offset != u64::MAX);
+        records.push((field(0) as usize, offset, line));
+        rest = tail;
+    }
+    for (line_num, offset, line) in records.into_iter().rev() {
+        let mut line = Cow::Borrowed(line);
+        if !line.ends_with(&[delimiter]) {
+            line.to_mut().push(delimiter);
+        }
+        match config.raw {
+            true => {
+                write_line_prefix(config, line_num, offset, out)?;
+                write_body(config, &line, out)?;
+            }
+            false => format_line(
+                config,
+                line_num,
+                offset,
+                &String::from_utf8_lossy(&line),
+                out,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/* バイト単位の指定に従って、バイト列をそのまま出力する */
+fn print_bytes(
+    config: &Config,
+    filename: &str,
+    spec: ByteSpec,
+    file: &mut impl BufRead,
+    size: Option<u64>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    match spec {
+        ByteSpec::Count(Count::First(num_bytes)) => match size {
+            Some(size) => {
+                print_file_prefix(filename, file, size, num_bytes as u64, out)?
+            }
+            // 読んだ分ずつ書き出すので、-c 10Gでもメモリは一定量しか使わない。
+            // マルチバイト文字の途中で切れてもバイト列をそのまま出力する
+            None => print_prefix_bytes(file, num_bytes as u64, out)?,
+        },
+        ByteSpec::Count(Count::AllButLast(num_bytes)) => match size {
+            Some(size) => print_file_prefix(
+                filename,
+                file,
+                size,
+                size.saturating_sub(num_bytes as u64),
+                out,
+            )?,
+            None => print_all_but_last_bytes(
+                config, filename, file, num_bytes, out,
+            )?,
+        },
+        ByteSpec::Range(start, end) => {
+            // START-1バイトを読み捨ててから範囲の幅だけ出力する
+            io::copy(&mut file.take(start as u64 - 1), &mut io::sink())?;
+            print_prefix_bytes(file, (end - start + 1) as u64, out)?;
+        }
+    }
+    Ok(())
+}
+
+/* -cの指定どおりに出力してから、--exactと--utf8-cleanを処理する */
+fn print_byte_spec(
+    config: &Config,
+    filename: &str,
+    spec: ByteSpec,
+    file: &mut impl BufRead,
+    size: Option<u64>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    // --utf8-cleanでは続きをfileから読むので、ファイル名で開き直す読み方はしない
+    let size = if config.utf8_clean { None } else { size };
+    let mut counted = CountingWriter::new(out);
+    #[cfg(feature = "progress")]
+    if config.progress && io::stderr().is_terminal() {
+        counted.progress = Some(progress_bar(spec, size));
+    }
+    print_bytes(config, filename, spec, file, size, &mut counted)?;
+    #[cfg(feature = "progress")]
+    if let Some(bar) = counted.progress.take() {
+        bar.finish_and_clear();
+    }
+    check_exact(config, filename, spec, counted.count)?;
+    if config.utf8_clean {
+        let missing = utf8_missing(&counted.last);
+        let rest = read_continuation_bytes(file, missing)?;
+        counted.write_all(&rest)?;
+    }
+    Ok(())
+}
+
+/* --progress用。出力するバイト数が分かれば全体に対する割合も表示する */
+#[cfg(feature = "progress")]
+fn progress_bar(spec: ByteSpec, size: Option<u64>) -> indicatif::ProgressBar {
+    let total = match spec {
+        ByteSpec::Count(Count::First(num_bytes)) => Some(
+            size.map_or(num_bytes as u64, |size| size.min(num_bytes as u64)),
+        ),
+        ByteSpec::Count(Count::AllButLast(num_bytes)) => {
+            size.map(|size| size.saturating_sub(num_bytes as u64))
+        }
+        ByteSpec::Range(start, end) => Some((end - start + 1) as u64),
+    };
+    let template = match total {
+        Some(_) => {
+            "{bar:30} {bytes}/{total_bytes} {binary_bytes_per_sec} {elapsed}"
+        }
+        None => "{spinner} {bytes} {binary_bytes_per_sec} {elapsed}",
+    };
+    let bar = indicatif::ProgressBar::with_draw_target(
+        total,
+        indicatif::ProgressDrawTarget::stderr(),
+    );
+    if let Ok(style) = indicatif::ProgressStyle::with_template(template) {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/* bytesの最後がUTF-8の文字の途中で切れていたら、その文字に足りないバイト数を返す */
+fn utf8_missing(bytes: &[u8]) -> usize {
+    let is_continuation = |byte: &u8| byte & 0xc0 == 0x80;
+    let num_continuation = bytes
+        .iter()
+        .rev()
+        .take(3)
+        .take_while(|b| is_continuation(b))
+        .count();
+    let Some(lead) = bytes.len().checked_sub(num_continuation + 1) else {
+        return 0;
+    };
+    let len: usize = match bytes[lead] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return 0,
+    };
+    len.saturating_sub(num_continuation + 1)
+}
+
+/* 継続バイト(0b10xxxxxx)をmax個まで読む。それ以外のバイトやEOFが来たらそこで止める */
+fn read_continuation_bytes(
+    file: &mut impl BufRead,
+    max: usize,
+) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    while bytes.len() < max {
+        match file.fill_buf()?.first() {
+            Some(&byte) if byte & 0xc0 == 0x80 => {
+                bytes.push(byte);
+                file.consume(1);
+            }
+            _ => break,
+        }
+    }
+    Ok(bytes)
+}
+
+/* --exactのとき、-cで指定したバイト数を全て出力できたか確かめる。
+末尾を除く指定(-c -N)はいつも満たせるので調べない */
+fn check_exact(
+    config: &Config,
+    filename: &str,
+    spec: ByteSpec,
+    found: usize,
+) -> MyResult<()> {
+    let requested = match spec {
+        ByteSpec::Count(Count::First(num_bytes)) => num_bytes,
+        ByteSpec::Range(start, end) => end - start + 1,
+        ByteSpec::Count(Count::AllButLast(_)) => return Ok(()),
+    };
+    if config.exact && found < requested {
+        return Err(HeadError::ShortInput(
+            filename.to_string(),
+            found,
+            requested,
+            "bytes",
+        ));
+    }
+    Ok(())
+}
+
+/* 書き出したバイト数を数え、--utf8-clean用に最後の4バイトまでを覚えておく */
+struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+    last: Vec<u8>,
+    #[cfg(feature = "progress")]
+    progress: Option<indicatif::ProgressBar>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter {
+            inner,
+            count: 0,
+            last: Vec::with_capacity(8),
+            #[cfg(feature = "progress")]
+            progress: None,
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.progress {
+            bar.inc(written as u64);
+        }
+        let buf = &buf[written.saturating_sub(4)..written];
+        self.last.extend_from_slice(buf);
+        self.last.drain(..self.last.len().saturating_sub(4));
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/* --hex用。hexdump -Cと同じ形式(オフセット、16バイト分の16進数、ASCII表示)で書き出す。
+オフセットはファイルごとに0から数え直す */
+struct HexDump<'a, W> {
+    inner: &'a mut W,
+    offset: usize,
+    pending: Vec<u8>, // まだ16バイトに満たない最後の行
+}
+
+impl<'a, W: Write> HexDump<'a, W> {
+    const WIDTH: usize = 16;
+
+    fn new(inner: &'a mut W) -> Self {
+        HexDump {
+            inner,
+            offset: 0,
+            pending: Vec::with_capacity(Self::WIDTH),
+        }
+    }
+
+    fn write_row(&mut self) -> io::Result<()> {
+        write!(self.inner, "{:08x} ", self.offset)?;
+        for i in 0..Self::WIDTH {
+            if i % 8 == 0 {
+                write!(self.inner, " ")?;
+            }
+            match self.pending.get(i) {
+                Some(byte) => write!(self.inner, "{:02x} ", byte)?,
+                None => write!(self.inner, "   ")?,
+            }
+        }
+        let ascii: String = self
+            .pending
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(self.inner, " |{}|", ascii)?;
+        self.offset += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /* 残りの行と、最後に合計のバイト数を書き出す */
+    fn finish(mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.write_row()?;
+        }
+        if self.offset > 0 {
+            writeln!(self.inner, "{:08x}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HexDump<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.pending.push(byte);
+            if self.pending.len() == Self::WIDTH {
+                self.write_row()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/* patternに一致する行が現れるまで表示する。一致した行は--until-exclusiveが無ければ含める。
+一致の判定は末尾の区切り文字を除いた行に対して行う */
+#[cfg(feature = "regex")]
+fn print_until(
+    config: &Config,
+    file: &mut impl BufRead,
+    pattern: &regex::Regex,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let chain = FilterChain::new(config)?;
+    for (line_num, line) in
+        head_iter_delimited(file, usize::MAX, delimiter).enumerate()
+    {
+        let line = line?;
+        let content = line.strip_suffix(delimiter as char).unwrap_or(&line);
+        let found = pattern.is_match(content);
+        if found && config.until_exclusive {
+            break;
+        }
+        print_line(config, &chain, line_num + 1, None, &line, out)?;
+        if found {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/* 先頭からn行を読み出して返す。各要素は末尾の改行を含む */
+pub fn head_lines(reader: impl BufRead, n: usize) -> MyResult<Vec<String>> {
+    head_lines_delimited(reader, n, b'\n')
+}
+
+/* head_linesの区切り文字を指定できる版。-zのときはdelimiterに0を渡す */
+pub fn head_lines_delimited(
+    reader: impl BufRead,
+    n: usize,
+    delimiter: u8,
+) -> MyResult<Vec<String>> {
+    head_iter_delimited(reader, n, delimiter).collect()
+}
+
+/* 先頭からn行を1行ずつ返すイテレータ。n行返した後やエラーの後はreaderに触らない */
+pub fn head_iter(
+    reader: impl BufRead,
+    n: usize,
+) -> impl Iterator<Item = MyResult<String>> {
+    head_iter_delimited(reader, n, b'\n')
+}
+
+pub fn head_iter_delimited(
+    reader: impl BufRead,
+    n: usize,
+    delimiter: u8,
+) -> impl Iterator<Item = MyResult<String>> {
+    head_iter_bytes(reader, n, delimiter).map(|line| {
+        line.map(|line| {
+            String::from_utf8(line).unwrap_or_else(|e| {
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            })
+        })
+    })
+}
+
+/* head_iter_delimitedの、UTF-8として読まずにバイト列のまま返す版 */
+fn head_iter_bytes<R: BufRead>(
+    reader: R,
+    n: usize,
+    delimiter: u8,
+) -> HeadIter<R> {
+    HeadIter {
+        reader,
+        remaining: n,
+        delimiter,
+    }
+}
+
+struct HeadIter<R> {
+    reader: R,
+    remaining: usize,
+    delimiter: u8,
+}
+
+impl<R: BufRead> Iterator for HeadIter<R> {
+    type Item = MyResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut line = Vec::new();
+        match self.reader.read_until(self.delimiter, &mut line) {
+            Ok(0) => {
+                self.remaining = 0;
+                None
+            }
+            Ok(_) => {
+                self.remaining -= 1;
+                Some(Ok(line))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/* 先頭からnバイトを読み出して返す。途中でEOFになればそこまで */
+pub fn head_bytes(reader: impl Read, n: usize) -> MyResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    reader.take(n as u64).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/* head_bytesと同じくnバイトを読み出し、読んだ分ごと最初から読み直せるreaderと一緒に返す。
+標準入力の先頭を調べてから、全体をそのまま別の処理に渡すときに使う */
+pub fn peek_head<R: Read>(
+    mut reader: R,
+    n: usize,
+) -> MyResult<(Vec<u8>, impl BufRead)> {
+    let head = head_bytes(&mut reader, n)?;
+    let rest = io::Cursor::new(head.clone()).chain(reader);
+    Ok((head, BufReader::new(rest)))
+}
+
+/// `delimiter`で区切られた行の数を数える。最後の行に区切り文字がなくても1行と数え、空の入力は0行。
+///
+/// -zと同じくNUL区切りのレコードを数えるときは`delimiter`に0を渡す
+pub fn count_lines(
+    mut reader: impl BufRead,
+    delimiter: u8,
+) -> MyResult<usize> {
+    let mut num_lines = 0;
+    let mut line = Vec::new();
+    while reader.read_until(delimiter, &mut line)? > 0 {
+        num_lines += 1;
+        line.clear();
+    }
+    Ok(num_lines)
+}
+
+/* 先頭からn行と、入力全体の行数を1回読むだけで返す。
+行数はcount_linesと同じく、区切り文字で終わっていない最後の行も1行と数える。
+--total-linesと--with-totalと同じLineCounterで、head_linesが読んだ分も続きも数える */
+pub fn head_with_total(
+    reader: impl BufRead,
+    n: usize,
+) -> MyResult<(Vec<String>, usize)> {
+    let counter = Rc::new(RefCell::new(LineCounter::new(reader, b'\n')));
+    let lines =
+        head_lines(BufReader::new(SharedReader(Rc::clone(&counter))), n)?;
+    let total = counter.borrow_mut().count_until(usize::MAX)?;
+    Ok((lines, total))
+}
+
+/* 先頭からn文字を読み出して返す。マルチバイト文字の途中では切らない。
+UTF-8の継続バイト(0b10xxxxxx)以外を文字の始まりとして数え、n+1文字目の始まりは読まずに残す */
+pub fn head_chars(mut reader: impl BufRead, n: usize) -> MyResult<String> {
+    let mut buffer = Vec::new();
+    let mut num_chars = 0;
+    'outer: loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let mut used = 0;
+        for &byte in chunk {
+            let is_start = byte & 0b1100_0000 != 0b1000_0000;
+            if is_start {
+                if num_chars == n {
+                    reader.consume(used);
+                    break 'outer;
+                }
+                num_chars += 1;
+            }
+            buffer.push(byte);
+            used += 1;
+        }
+        reader.consume(used);
+    }
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/* 先頭からn個の書記素クラスタ(結合文字や国旗の絵文字などを含めた見た目の1文字)を返す。
+クラスタは改行をまたがないので1行ずつ読み、クラスタの途中では切らない(graphemes機能) */
+#[cfg(feature = "graphemes")]
+pub fn head_graphemes(mut reader: impl BufRead, n: usize) -> MyResult<String> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let mut text = String::new();
+    let mut num_left = n;
+    let mut line = Vec::new();
+    while num_left > 0 {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        for grapheme in String::from_utf8_lossy(&line).graphemes(true) {
+            if num_left == 0 {
+                break;
+            }
+            text.push_str(grapheme);
+            num_left -= 1;
+        }
+    }
+    Ok(text)
+}
+
+/* 先頭からn語を読み出して返す。語の間の空白はそのまま残し、n語目の直後で止める。
+空白の判定はASCIIの空白文字だけで行い、n語目の後の空白は読まずに残す */
+pub fn head_words(mut reader: impl BufRead, n: usize) -> MyResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if n == 0 {
+        return Ok(buffer);
+    }
+    let mut num_words = 0;
+    let mut in_word = false;
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let mut used = 0;
+        for &byte in chunk {
+            let is_space = byte.is_ascii_whitespace();
+            if !in_word && !is_space {
+                num_words += 1;
+            } else if in_word && is_space && num_words == n {
+                reader.consume(used);
+                return Ok(buffer);
+            }
+            in_word = !is_space;
+            buffer.push(byte);
+            used += 1;
+        }
+        reader.consume(used);
+    }
+    Ok(buffer)
+}
+
+/* 全体の行数のpercent%(切り上げ)だけ表示する。通常ファイルはもう一度開いて行数を数え、
+標準入力など読み直せないものは全行をバッファに読み込んでから数える。
+--spill-to-diskでは--max-bufferを超えた分を一時ファイルに置いて読み直す */
+fn print_percent_lines(
+    config: &Config,
+    filename: &str,
+    mut file: Box<dyn BufRead>,
+    size: Option<u64>,
+    percent: f64,
+    mut offset: Option<u64>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let delimiter = config.delimiter();
+    let percent_of =
+        |total: usize| (total as f64 * percent / 100.0).ceil() as usize;
+
+    let lines: Box<dyn Iterator<Item = MyResult<Vec<u8>>>> = match size {
+        Some(_) => {
+            /* 開き直して数えるので、--skipで読み飛ばした行はここで除く。
+            BOMは行の数を変えないのでそのままでよい */
+            let total =
+                count_lines(open_input(config, filename)?.reader, delimiter)?
+                    .saturating_sub(config.skip);
+            Box::new(head_iter_bytes(file, percent_of(total), delimiter))
+        }
+        None if config.spill_to_disk => {
+            let mut spilled = SpillBuffer::new(config);
+            io::copy(&mut file, &mut spilled)?;
+            let total = count_lines(spilled.reader()?, delimiter)?;
+            Box::new(head_iter_bytes(
+                spilled.into_reader()?,
+                percent_of(total),
+                delimiter,
+            ))
+        }
+        None => {
+            let all = read_to_end_limited(config, filename, &mut file)?;
+            let total = count_lines(&all[..], delimiter)?;
+            Box::new(head_iter_bytes(
+                io::Cursor::new(all),
+                percent_of(total),
+                delimiter,
+            ))
+        }
+    };
+
+    let chain = FilterChain::new(config)?;
+    for (line_num, line) in lines.enumerate() {
+        let line = line?;
+        write_line(config, &chain, line_num + 1, offset, &line, out)?;
+        offset = offset.map(|offset| offset + line.len() as u64);
+    }
+    Ok(())
+}
+
+/* 全体のバイト数のpercent%(切り上げ)だけ表示する。切り上げるので、空でなければ1バイトは必ず出力する。
+通常ファイルはメタデータのサイズを使い、標準入力など大きさの分からないものは全体をバッファに読み込んでから数える */
+fn print_percent_bytes(
+    config: &Config,
+    filename: &str,
+    mut file: Box<dyn BufRead>,
+    size: Option<u64>,
+    percent: f64,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let percent_of =
+        |total: u64| (total as f64 * percent / 100.0).ceil() as u64;
+
+    match size {
+        Some(size) => {
+            print_file_prefix(filename, &mut file, size, percent_of(size), out)
+        }
+        None if config.spill_to_disk => {
+            let mut spilled = SpillBuffer::new(config);
+            io::copy(&mut file, &mut spilled)?;
+            let num_bytes = percent_of(spilled.len());
+            let mut reader = spilled.reader()?;
+            print_prefix_bytes(&mut reader, num_bytes, out)
+        }
+        None => {
+            let all = read_to_end_limited(config, filename, &mut file)?;
+            let num_bytes = percent_of(all.len() as u64) as usize;
+            out.write_all(&all[..num_bytes.min(all.len())])?;
+            Ok(())
+        }
+    }
+}
+
+/* 大きさの分からない入力を最後まで読む。負の-n/-cと同じく、--max-bufferを超えたらエラーにする */
+fn read_to_end_limited(
+    config: &Config,
+    filename: &str,
+    file: &mut impl Read,
+) -> MyResult<Vec<u8>> {
+    let mut all = Vec::new();
+    file.take(config.max_buffer as u64 + 1)
+        .read_to_end(&mut all)?;
+    if all.len() > config.max_buffer {
+        return Err(HeadError::BufferLimit(
+            filename.to_string(),
+            config.max_buffer,
+        ));
+    }
+    Ok(all)
+}
+
+/* --lines-rangesの範囲に入る行だけを出力する。rangesは並べ替えて重なりをまとめたもの。
+最後の範囲の終わりより後は読まない */
+fn print_line_ranges(
+    config: &Config,
+    filename: &str,
+    file: &mut impl BufRead,
+    ranges: &[(usize, usize)],
+    mut offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let chain = FilterChain::new(config)?;
+    let last_line = ranges.last().map_or(0, |(_, last)| *last);
+    let mut ranges = ranges.iter().peekable();
+    let mut found = 0;
+    for (line_num, line) in
+        (1..).zip(head_iter_bytes(file, last_line, delimiter))
+    {
+        let line = line?;
+        found = line_num;
+        let line_offset = offset;
+        offset = offset.map(|offset| offset + line.len() as u64);
+        while ranges.next_if(|(_, last)| *last < line_num).is_some() {}
+        if ranges.peek().is_some_and(|(first, _)| *first <= line_num) {
+            write_line(config, &chain, line_num, line_offset, &line, out)?;
+        }
+    }
+    // --exactでは最後の範囲の終わりまで行が無ければエラーにする
+    if config.exact && found < last_line {
+        return Err(HeadError::ShortInput(
+            filename.to_string(),
+            found,
+            last_line,
+            "lines",
+        ));
+    }
+    Ok(())
+}
+
+/* 読んだままのバイト列の1行を出力する。--rawならUTF-8として読まずにそのまま書き、
+それ以外はprint_lineに渡す(不正なバイトはU+FFFDになる)。
+--validate-utf8では不正な行を何も書かずにエラーにする。
+フィルターで落とした行は書かずにfalseを返す。--rawでは行を書き換えるフィルターは使えないので、
+--squeeze-blankで落とすかどうかだけを決める */
+fn write_line(
+    config: &Config,
+    chain: &FilterChain,
+    line_num: usize,
+    offset: Option<u64>,
+    line: &[u8],
+    out: &mut impl Write,
+) -> MyResult<bool> {
+    check_utf8(config, offset, line)?;
+    if !config.raw {
+        return print_line(
+            config,
+            chain,
+            line_num,
+            offset,
+            &String::from_utf8_lossy(line),
+            out,
+        );
+    }
+    if chain.apply(&String::from_utf8_lossy(line)).is_none() {
+        return Ok(false);
+    }
+    if config.reverse_lines {
+        defer_line(line_num, offset, line, out)?;
+        return Ok(true);
+    }
+    write_line_prefix(config, line_num, offset, out)?;
+    write_body(config, line, out)?;
+    Ok(true)
+}
+
+/* --validate-utf8。行を書く前に調べ、不正なバイトの入力の中での位置を返す。
+ファイル名はprint_headで付ける */
+fn check_utf8(
+    config: &Config,
+    offset: Option<u64>,
+    line: &[u8],
+) -> MyResult<()> {
+    if !config.validate_utf8 {
+        return Ok(());
+    }
+    match std::str::from_utf8(line) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(HeadError::InvalidUtf8(
+            String::new(),
+            offset.unwrap_or(0) + e.valid_up_to() as u64,
+        )),
+    }
+}
+
+/* 行モードで1行を出力する。line_numはファイルごとに1から数える。
+フィルターで落とした行は書かずにfalseを返す */
+fn print_line(
+    config: &Config,
+    chain: &FilterChain,
+    line_num: usize,
+    offset: Option<u64>,
+    line: &str,
+    out: &mut impl Write,
+) -> MyResult<bool> {
+    let Some(line) = chain.apply(line) else {
+        return Ok(false);
+    };
+    match config.reverse_lines {
+        true => defer_line(line_num, offset, line.as_bytes(), out)?,
+        false => format_line(config, line_num, offset, &line, out)?,
+    }
+    Ok(true)
+}
+
+/* 行番号などを前に付け、--wrapで折り返して1行を書く */
+fn format_line(
+    config: &Config,
+    line_num: usize,
+    offset: Option<u64>,
+    line: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    write_line_prefix(config, line_num, offset, out)?;
+    let wrapped;
+    let line = match config.wrap {
+        0 => line,
+        width => {
+            wrapped = wrap_line(line, width, config.delimiter());
+            &*wrapped
+        }
+    };
+    write_body(config, line.as_bytes(), out)
+}
+
+/* 1ファイル分のフィルターの並び。--trim(--skip-emptyなら空になった行を落とす)、--squeeze-blank、
+--columnsか--fields、ConfigBuilder::filterのフィルターの順に行の中身に適用する。
+--squeeze-blankは前の行を覚えているので、ファイルごとに作る。
+--wrap、-E、-T、--unix-linesは行末の区切り文字も見て出力を整えるものなので、ここには入れずに
+write_bodyとprint_lineで最後に行う */
+struct FilterChain<'a> {
+    filters: Vec<Box<dyn LineFilter + 'a>>,
+    delimiter: u8,
+    drops_lines: bool, // 行を落とすことがあるか
+}
+
+impl<'a> FilterChain<'a> {
+    fn new(config: &'a Config) -> MyResult<Self> {
+        // --rawの行はUTF-8とは限らないので、中身を書き換えるフィルターは渡せない
+        if config.raw && !config.filters.0.is_empty() {
+            return Err(HeadError::InvalidArgument(
+                "line filters cannot be used with --raw or --null-data"
+                    .to_string(),
+            ));
+        }
+        let delimiter = config.delimiter();
+        let mut filters: Vec<Box<dyn LineFilter + 'a>> = Vec::new();
+        if let Some(trim) = config.trim {
+            filters.push(Box::new(TrimFilter {
+                trim,
+                delimiter,
+                skip_empty: config.skip_empty,
+            }));
+        }
+        if config.squeeze_blank {
+            filters.push(Box::new(SqueezeFilter::default()));
+        }
+        match (&config.columns, config.fields) {
+            (Some(ranges), _) => {
+                filters.push(Box::new(ColumnsFilter { ranges, delimiter }))
+            }
+            (None, Some(num_fields)) => filters.push(Box::new(FieldsFilter {
+                num_fields,
+                sep: config.field_sep,
+            })),
+            (None, None) => {}
+        }
+        for filter in &config.filters.0 {
+            filters.push(Box::new(|line: &mut String| filter.apply(line)));
+        }
+        Ok(FilterChain {
+            filters,
+            delimiter,
+            drops_lines: (config.trim.is_some() && config.skip_empty)
+                || config.squeeze_blank
+                || !config.filters.0.is_empty(),
+        })
+    }
+
+    /* 落とした行はNone。行末の区切り文字は元のまま付け直す */
+    fn apply<'b>(&self, line: &'b str) -> Option<Cow<'b, str>> {
+        if self.filters.is_empty() {
+            return Some(Cow::Borrowed(line));
+        }
+        let body = line_body(line, self.delimiter);
+        let mut text = body.to_string();
+        for filter in &self.filters {
+            if !filter.apply(&mut text) {
+                return None;
+            }
+        }
+        text.push_str(&line[body.len()..]);
+        Some(Cow::Owned(text))
+    }
+}
+
+/* 設定から作る組み込みのフィルター。行の中身に対してtrim_lineなどを呼ぶ */
+struct TrimFilter {
+    trim: Trim,
+    delimiter: u8,
+    skip_empty: bool,
+}
+
+impl LineFilter for TrimFilter {
+    fn apply(&self, line: &mut String) -> bool {
+        if let Cow::Owned(trimmed) = trim_line(line, self.trim, self.delimiter)
+        {
+            *line = trimmed;
+        }
+        // --skip-emptyでは空白しかなかった行を出力しない
+        !(self.skip_empty && line.is_empty())
+    }
+}
+
+/* 空行が続いたら2行目から落とす */
+#[derive(Default)]
+struct SqueezeFilter {
+    prev_blank: AtomicBool,
+}
+
+impl LineFilter for SqueezeFilter {
+    fn apply(&self, line: &mut String) -> bool {
+        let blank = line.is_empty();
+        !(self.prev_blank.swap(blank, Ordering::Relaxed) && blank)
+    }
+}
+
+struct ColumnsFilter<'a> {
+    ranges: &'a [(usize, usize)],
+    delimiter: u8,
+}
+
+impl LineFilter for ColumnsFilter<'_> {
+    fn apply(&self, line: &mut String) -> bool {
+        *line = select_columns(line, self.ranges, self.delimiter).into_owned();
+        true
+    }
+}
+
+struct FieldsFilter {
+    num_fields: usize,
+    sep: char,
+}
+
+impl LineFilter for FieldsFilter {
+    fn apply(&self, line: &mut String) -> bool {
+        if let Cow::Owned(fields) =
+            head_fields(line, self.num_fields, self.sep)
+        {
+            *line = fields;
+        }
+        true
+    }
+}
+
+/* -Nの行番号と--byte-offsetの位置を、grep -nbと同じ順に行の前に書く */
+fn write_line_prefix(
+    config: &Config,
+    line_num: usize,
+    offset: Option<u64>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if config.number {
+        write!(out, "{:6}\t", line_num)?; // cat -nと同じく6桁右寄せ+タブ
+    }
+    // --validate-utf8のためだけに数えた位置は出さない
+    if let Some(offset) = offset.filter(|_| config.byte_offset) {
+        write!(out, "{}\t", offset)?;
+    }
+    Ok(())
+}
+
+/* 行末を整えて1行を書く。--unix-linesではCRLFをLFにし、-Tではタブを^Iに、
+-Eでは行末の区切り文字の前に$を付ける。区切り文字で終わらない最後の行には$を付けない */
+fn write_body(
+    config: &Config,
+    line: &[u8],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let delimiter = config.delimiter();
+    let (body, end) = match line.strip_suffix(b"\r\n") {
+        Some(body) if config.unix_lines => (body, &b"\n"[..]),
+        _ => line.split_at(line.len() - line.ends_with(&[delimiter]) as usize),
+    };
+    if config.show_tabs {
+        for (pos, part) in body.split(|byte| *byte == b'\t').enumerate() {
+            if pos > 0 {
+                out.write_all(b"^I")?;
+            }
+            out.write_all(part)?;
+        }
+    } else {
+        out.write_all(body)?;
+    }
+    if config.show_ends && !end.is_empty() {
+        out.write_all(b"$")?;
+    }
+    out.write_all(end)
+}
+
+/* 行の中身をwidth文字ごとに改行で区切る。行末の区切り文字はそのまま残す */
+fn wrap_line(line: &str, width: usize, delimiter: u8) -> Cow<'_, str> {
+    let body = line.trim_end_matches(|c| {
+        c == '\r' || c == '\n' || c == char::from(delimiter)
+    });
+    if body.chars().count() <= width {
+        return line.into();
+    }
+    let mut wrapped = String::with_capacity(line.len() + line.len() / width);
+    for (pos, c) in body.chars().enumerate() {
+        if pos > 0 && pos % width == 0 {
+            wrapped.push('\n');
+        }
+        wrapped.push(c);
+    }
+    wrapped.push_str(&line[body.len()..]);
+    wrapped.into()
+}
+
+/* --trim。行末の区切り文字(CRLFならCRも)は残して、その前の空白を除く */
+fn trim_line(line: &str, trim: Trim, delimiter: u8) -> Cow<'_, str> {
+    let body = line_body(line, delimiter);
+    let trimmed = match trim {
+        Trim::Both => body.trim(),
+        Trim::Start => body.trim_start(),
+        Trim::End => body.trim_end(),
+    };
+    if trimmed.len() == body.len() {
+        return line.into();
+    }
+    format!("{}{}", trimmed, &line[body.len()..]).into()
+}
+
+/* 行末の区切り文字を除いた行の中身。改行区切りならCRLFのCRも除く */
+fn line_body(line: &str, delimiter: u8) -> &str {
+    let delimiter = char::from(delimiter);
+    line.strip_suffix("\r\n")
+        .filter(|_| delimiter == '\n')
+        .or_else(|| line.strip_suffix(delimiter))
+        .unwrap_or(line)
+}
+
+/* 行の先頭num_fields個のフィールドだけを残す。区切り文字と行末の改行は元のまま。
+フィールドが足りない行はそのまま返す */
+fn head_fields(line: &str, num_fields: usize, sep: char) -> Cow<'_, str> {
+    let body = line
+        .strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix(['\n', '\0']))
+        .unwrap_or(line);
+    match body.match_indices(sep).nth(num_fields - 1) {
+        Some((pos, _)) => {
+            Cow::Owned(format!("{}{}", &body[..pos], &line[body.len()..]))
+        }
+        None => Cow::Borrowed(line),
+    }
+}
+
+/* --columns。行の中身から、1から数えた文字の位置がrangesに入る文字だけを残す。
+行より後ろにはみ出した範囲は無視し、行末の区切り文字は元のまま */
+fn select_columns<'a>(
+    line: &'a str,
+    ranges: &[(usize, usize)],
+    delimiter: u8,
+) -> Cow<'a, str> {
+    let body = line_body(line, delimiter);
+    let last_column = ranges.last().map_or(0, |(_, last)| *last);
+    let mut selected = String::with_capacity(body.len());
+    for (column, ch) in (1..=last_column).zip(body.chars()) {
+        if ranges
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&column))
+        {
+            selected.push(ch);
+        }
+    }
+    selected.push_str(&line[body.len()..]);
+    Cow::Owned(selected)
+}
+
+/* --context。先頭を出力した残りを読み進め、最後のnum_lines行だけをリングバッファに保持して出力する。
+先頭との間に飛ばした行があれば"..."の行を挟む。num_readは先頭で読んだ行数で、-Nの行番号に使う */
+fn print_context(
+    config: &Config,
+    chain: &FilterChain,
+    num_read: usize,
+    lines: impl Iterator<Item = MyResult<Vec<u8>>>,
+    num_lines: usize,
+    mut offset: Option<u64>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut tail = VecDeque::with_capacity(num_lines);
+    let mut num_rest = 0;
+    for line in lines {
+        if tail.len() == num_lines {
+            tail.pop_front();
+        }
+        let line = line?;
+        let line_offset = offset;
+        offset = offset.map(|offset| offset + line.len() as u64);
+        tail.push_back((line_offset, line));
+        num_rest += 1;
+    }
+    let mut line_num = num_read + num_rest - tail.len();
+    if line_num > num_read {
+        out.write_all(b"...")?;
+        out.write_all(&[chain.delimiter])?;
+    }
+    for (line_offset, line) in tail {
+        line_num += 1;
+        write_line(config, chain, line_num, line_offset, &line, out)?;
+    }
+    Ok(())
+}
+
+/* 末尾のnum_lines行は読み終えるまで手元に置く。その合計が--max-bufferを超えたらエラーにする。
+--spill-to-diskなら、そこから先は一時ファイルに移して続ける */
+fn print_all_but_last_lines(
+    config: &Config,
+    filename: &str,
+    file: &mut impl BufRead,
+    num_lines: usize,
+    mut offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let chain = FilterChain::new(config)?;
+    // -n -9999999999でも先に領域を確保しないよう、容量は指定しない
+    let mut pending: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut pending_bytes = 0;
+    let mut line_num = 0;
+    loop {
+        let mut line = Vec::new();
+        if file.read_until(delimiter, &mut line)? == 0 {
+            break;
+        }
+        pending_bytes += line.len();
+        pending.push_back(line);
+        if pending.len() > num_lines {
+            if let Some(oldest) = pending.pop_front() {
+                pending_bytes -= oldest.len();
+                line_num += 1;
+                write_line(config, &chain, line_num, offset, &oldest, out)?;
+                offset = offset.map(|offset| offset + oldest.len() as u64);
+            }
+        }
+        if pending_bytes > config.max_buffer {
+            if config.spill_to_disk {
+                for line in spill_lines(config, pending, file, num_lines)? {
+                    let line = line?;
+                    line_num += 1;
+                    write_line(config, &chain, line_num, offset, &line, out)?;
+                    offset = offset.map(|offset| offset + line.len() as u64);
+                }
+                return Ok(());
+            }
+            return Err(HeadError::BufferLimit(
+                filename.to_string(),
+                config.max_buffer,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* --spill-to-disk。手元の行と残りの入力を一時ファイルに書き出して全体の行数を数え、
+先頭から読み直して末尾のnum_lines行より前の行を返す */
+fn spill_lines(
+    config: &Config,
+    pending: VecDeque<Vec<u8>>,
+    file: &mut impl BufRead,
+    num_lines: usize,
+) -> MyResult<impl Iterator<Item = MyResult<Vec<u8>>>> {
+    let delimiter = config.delimiter();
+    let mut spilled = SpillBuffer::new(config);
+    for line in pending {
+        spilled.write_all(&line)?;
+    }
+    io::copy(file, &mut spilled)?;
+    let total = count_lines(spilled.reader()?, delimiter)?;
+    Ok(head_iter_bytes(
+        spilled.into_reader()?,
+        total.saturating_sub(num_lines),
+        delimiter,
+    ))
+}
+
+/* --paragraph。1行以上の空行で区切られた段落を1件と数えて-nの数だけ出力する。
+区切りの空行は何行あっても1行にまとめ、-Nは出力した行を通しで数える */
+fn print_paragraphs(
+    config: &Config,
+    filename: &str,
+    file: &mut impl BufRead,
+    mut offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let chain = FilterChain::new(config)?;
+    let mut line_num = 0;
+    let mut write_paragraph =
+        |skipped: u64, paragraph: Vec<Vec<u8>>| -> MyResult<()> {
+            if line_num > 0 {
+                line_num += 1;
+                write_line(config, &chain, line_num, None, &[delimiter], out)?;
+            }
+            offset = offset.map(|offset| offset + skipped);
+            for line in paragraph {
+                line_num += 1;
+                write_line(config, &chain, line_num, offset, &line, out)?;
+                offset = offset.map(|offset| offset + line.len() as u64);
+            }
+            Ok(())
+        };
+    let mut paragraphs = paragraphs(file, delimiter);
+    match config.lines {
+        Count::First(num) => {
+            for paragraph in paragraphs.by_ref().take(num) {
+                let (skipped, paragraph) = paragraph?;
+                write_paragraph(skipped, paragraph)?;
+            }
+        }
+        Count::AllButLast(num) => {
+            // 末尾のnum段落は読み終えるまで手元に置く
+            let mut pending = VecDeque::new();
+            let mut pending_bytes = 0;
+            for paragraph in paragraphs {
+                let paragraph = paragraph?;
+                pending_bytes +=
+                    paragraph.1.iter().map(Vec::len).sum::<usize>();
+                pending.push_back(paragraph);
+                if pending.len() > num {
+                    if let Some((skipped, oldest)) = pending.pop_front() {
+                        pending_bytes -=
+                            oldest.iter().map(Vec::len).sum::<usize>();
+                        write_paragraph(skipped, oldest)?;
+                    }
+                }
+                if pending_bytes > config.max_buffer {
+                    return Err(HeadError::BufferLimit(
+                        filename.to_string(),
+                        config.max_buffer,
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/* 段落ごとに、その前にあった空行のバイト数と、段落の行(区切りの空行は含まない)を返す。
+先頭や末尾の空行は段落にならないので、空の段落は返さない */
+fn paragraphs<R: BufRead>(
+    mut reader: R,
+    delimiter: u8,
+) -> impl Iterator<Item = MyResult<(u64, Vec<Vec<u8>>)>> {
+    let mut skipped = 0;
+    std::iter::from_fn(move || {
+        let mut paragraph = Vec::new();
+        let mut blank_before = std::mem::take(&mut skipped);
+        loop {
+            let mut line = Vec::new();
+            match reader.read_until(delimiter, &mut line) {
+                Ok(0) => break,
+                Ok(_) if line == [delimiter] => {
+                    if !paragraph.is_empty() {
+                        skipped = 1; // 段落を終えた空行は次の段落の前の空行として数える
+                        break;
+                    }
+                    blank_before += 1;
+                }
+                Ok(_) => paragraph.push(line),
+                Err(e) => return Some(Err(HeadError::Io(e))),
+            }
+        }
+        (!paragraph.is_empty()).then_some(Ok((blank_before, paragraph)))
+    })
+}
+
+/* 先頭からnum_bytesバイトをそのまま(UTF-8変換せずに)出力する */
+fn print_prefix_bytes(
+    file: &mut impl BufRead,
+    num_bytes: u64,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    // io::copyは8KiBずつなので、読み込みのバッファ(--chunk-size)をそのまま書き出す
+    let mut left = num_bytes;
+    while left > 0 {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let len = chunk.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        out.write_all(&chunk[..len])?;
+        file.consume(len);
+        left -= len as u64;
+    }
+    Ok(())
+}
+
+/* サイズの分かる通常ファイル用。mmap機能が有効ならファイルをメモリにマップして、
+コピーせずにそのまま書き出す */
+#[cfg(feature = "mmap")]
+fn print_file_prefix(
+    filename: &str,
+    _file: &mut impl BufRead,
+    size: u64,
+    num_bytes: u64,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    if size == 0 || num_bytes == 0 {
+        return Ok(());
+    }
+    let file = File::open(filename)?;
+    // SAFETY: 読んでいる途中で他のプロセスにファイルを切り詰められると壊れるが、
+    // 書き出す間だけ使うのでGNU headなどと同程度の前提とする
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    // sizeは現在位置から後ろのバイト数なので、読み飛ばした分(BOMなど)はここで除く
+    let start = map.len().saturating_sub(size as usize);
+    let end = start.saturating_add(num_bytes as usize).min(map.len());
+    out.write_all(&map[start..end])?;
+    Ok(())
+}
+
+#[cfg(not(feature = "mmap"))]
+fn print_file_prefix(
+    _filename: &str,
+    file: &mut impl BufRead,
+    _size: u64,
+    num_bytes: u64,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    print_prefix_bytes(file, num_bytes, out)
+}
+
+/* サイズの分からないストリーム用。末尾num_bytesバイトだけをバッファに残しながら出力する */
+fn print_all_but_last_bytes(
+    config: &Config,
+    filename: &str,
+    file: &mut impl BufRead,
+    num_bytes: usize,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut pending: Vec<u8> = Vec::new();
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let len = chunk.len();
+        pending.extend_from_slice(chunk);
+        file.consume(len);
+        if pending.len() > num_bytes {
+            let excess = pending.len() - num_bytes;
+            out.write_all(&pending[..excess])?;
+            pending.drain(..excess);
+        }
+        if pending.len() > config.max_buffer {
+            if config.spill_to_disk {
+                /* 残りを一時ファイルに移せば全体の長さが分かる */
+                let mut spilled = SpillBuffer::new(config);
+                spilled.write_all(&pending)?;
+                io::copy(file, &mut spilled)?;
+                let num_bytes = spilled.len().saturating_sub(num_bytes as u64);
+                return print_prefix_bytes(
+                    &mut spilled.reader()?,
+                    num_bytes,
+                    out,
+                );
+            }
+            return Err(HeadError::BufferLimit(
+                filename.to_string(),
+                config.max_buffer,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* --spill-to-disk用。--max-bufferまではメモリに置き、超えたら一時ファイルに移して続きもそこに書く。
+一時ファイルは名前を持たないので、エラーで途中で終わっても閉じれば消える */
+struct SpillBuffer {
+    memory: Vec<u8>,
+    file: Option<BufWriter<File>>,
+    len: u64,
+    threshold: usize,
+    chunk_size: usize,
+}
+
+impl SpillBuffer {
+    fn new(config: &Config) -> Self {
+        SpillBuffer {
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+            threshold: config.max_buffer,
+            chunk_size: config.chunk_size,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    /* 書き込んだ内容を先頭から読む。一時ファイルの場合は呼ぶたびに先頭に戻る */
+    fn reader(&mut self) -> io::Result<Box<dyn BufRead + '_>> {
+        use std::io::{Seek, SeekFrom};
+
+        match self.file.as_mut() {
+            Some(writer) => {
+                writer.flush()?;
+                let mut file = writer.get_ref().try_clone()?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::with_capacity(self.chunk_size, file)))
+            }
+            None => Ok(Box::new(&self.memory[..])),
+        }
+    }
+
+    fn into_reader(self) -> io::Result<Box<dyn BufRead>> {
+        use std::io::{Seek, SeekFrom};
+
+        match self.file {
+            Some(writer) => {
+                let mut file =
+                    writer.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::with_capacity(self.chunk_size, file)))
+            }
+            None => Ok(Box::new(io::Cursor::new(self.memory))),
+        }
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none()
+            && self.memory.len() + buf.len() > self.threshold
+        {
+            log::debug!(
+                "spilling more than {} bytes to a temporary file",
+                self.threshold
+            );
+            let mut file = BufWriter::with_capacity(
+                self.chunk_size,
+                tempfile::tempfile()?,
+            );
+            file.write_all(&std::mem::take(&mut self.memory))?;
+            self.file = Some(file);
+        }
+        match self.file.as_mut() {
+            Some(file) => file.write_all(buf)?,
+            None => self.memory.extend_from_slice(buf),
+        }
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/* 開いた入力と、分かる場合はそのバイト数(通常ファイルをそのまま読むときのみ) */
+struct Input {
+    reader: Box<dyn BufRead>,
+    size: Option<u64>,
+}
+
+/* openerを指定していればそれで開く。通常ファイルとして開き直せないので、サイズは分からないものとして扱う */
+fn open_input(config: &Config, filename: &str) -> MyResult<Input> {
+    match &config.opener {
+        Some(opener) => {
+            log::debug!("opening {} with the custom opener", filename);
+            let reader = (opener.0)(filename)?;
+            let input = retry_interrupted(Input { reader, size: None });
+            decompress(filename, input)
+        }
+        #[cfg(feature = "http")]
+        None if config.cache.is_some() && is_url(filename) => {
+            open_cached_url(config, filename)
+        }
+        None => open(filename, config.chunk_size),
+    }
+}
+
+/// [`open_any`]で開いた入力と、開いたときに分かったこと。
+///
+/// `size`と`seekable`は通常ファイルをそのまま読むときだけ分かる(展開したものや標準入力、URLなどは分からない)
+pub struct Source {
+    /// 中身を読むreader。圧縮されていれば展開した後の内容を返す
+    pub reader: Box<dyn BufRead>,
+    /// ファイルの大きさ。分からなければNone
+    pub size: Option<u64>,
+    /// `reader`の元のファイルをシークし直せるか
+    pub seekable: bool,
+    /// 展開しながら読んでいるか
+    pub decompressed: bool,
+}
+
+/* readerはDebugを持たないので、それ以外を出す */
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Source")
+            .field("size", &self.size)
+            .field("seekable", &self.seekable)
+            .field("decompressed", &self.decompressed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// headrと同じようにファイル名を開く。
+///
+/// `"-"`は標準入力、URLや`tar://`なども機能が有効なら開け、圧縮されていれば展開して読む
+pub fn open_any(filename: &str) -> MyResult<Source> {
+    open_source(filename, DEFAULT_CHUNK_SIZE)
+}
+
+/* chunk_sizeは--chunk-size。ファイルと標準入力はこの大きさのバッファで読む */
+fn open(filename: &str, chunk_size: usize) -> MyResult<Input> {
+    open_source(filename, chunk_size).map(|source| Input {
+        reader: source.reader,
+        size: source.size,
+    })
+}
+
+fn open_source(filename: &str, chunk_size: usize) -> MyResult<Source> {
+    log::debug!("opening {}", filename);
+    let input = match filename {
+        "-" => Input {
+            reader: Box::new(BufReader::with_capacity(
+                chunk_size,
+                io::stdin(),
+            )),
+            size: None,
+        },
+        #[cfg(feature = "http")]
+        _ if is_url(filename) => open_url(filename)?,
+        #[cfg(feature = "tar")]
+        _ if filename.starts_with("tar://") => {
+            let input = retry_interrupted(open_tar(filename, chunk_size)?);
+            return Ok(Source {
+                reader: input.reader,
+                size: input.size,
+                seekable: false,
+                decompressed: false,
+            });
+        }
+        #[cfg(unix)]
+        _ if filename.starts_with("fd:") => open_fd(filename)?,
+        #[cfg(feature = "cmd")]
+        _ if is_command(filename) => {
+            open_command(&filename["cmd:".len()..], chunk_size)?
+        }
+        #[cfg(feature = "clipboard")]
+        "clip:" => clipboard_input(
+            arboard::Clipboard::new().and_then(|mut clip| clip.get_text()),
+        )?,
+        _ => match File::open(filename) {
+            Ok(file) if file.metadata().is_ok_and(|meta| meta.is_dir()) => {
+                return Err(HeadError::IsADirectory(filename.to_string()))
+            }
+            Ok(file) => Input {
+                size: file
+                    .metadata()
+                    .ok()
+                    .filter(|meta| meta.is_file())
+                    .map(|meta| meta.len()),
+                reader: Box::new(BufReader::with_capacity(chunk_size, file)),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(HeadError::FileNotFound(filename.to_string()))
+            }
+            Err(e) => return Err(HeadError::Io(e)),
+        },
+    };
+    let input = retry_interrupted(input);
+    let (input, decompressed) = decompress_detected(filename, input)?;
+    Ok(Source {
+        seekable: input.size.is_some(),
+        reader: input.reader,
+        size: input.size,
+        decompressed,
+    })
+}
+
+/* "clip:"として、クリップボードのテキストを読む。空のクリップボードは空の入力として扱う。
+ファイル名で開き直せないので、サイズは分からないものとして扱う */
+#[cfg(feature = "clipboard")]
+fn clipboard_input(text: Result<String, arboard::Error>) -> MyResult<Input> {
+    let text = match text {
+        Ok(text) => text,
+        Err(arboard::Error::ContentNotAvailable) => String::new(),
+        Err(e) => return Err(HeadError::Io(io::Error::other(e.to_string()))),
+    };
+    Ok(Input {
+        reader: Box::new(io::Cursor::new(text.into_bytes())),
+        size: None,
+    })
+}
+
+/* "fd:N"を、既に開いているファイルディスクリプタNから読む。
+Nはfcntlで複製してから読むので、Nを閉じるのは呼び出し側の責任のまま。
+ファイル名で開き直せないので、サイズは分からないものとして扱う */
+#[cfg(unix)]
+fn open_fd(filename: &str) -> MyResult<Input> {
+    use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+    let fd: RawFd = filename["fd:".len()..]
+        .parse()
+        .ok()
+        .filter(|fd| *fd >= 0)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid file descriptor",
+            )
+        })?;
+    // 開いていないfdを借りることはできないので、fcntlで複製して確かめる。
+    // 閉じていればEBADFで失敗するだけで、元のfdには触らない
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // SAFETY: dupはこの関数が複製したばかりのfdで、他に持ち主はいない
+    let file = File::from(unsafe { OwnedFd::from_raw_fd(dup) });
+    if file.metadata()?.is_dir() {
+        return Err(HeadError::IsADirectory(filename.to_string()));
+    }
+    Ok(Input {
+        reader: Box::new(BufReader::new(file)),
+        size: None,
+    })
+}
+
+/* --retry。一時的と思われるエラーで開けなかったときだけ、待ってから開き直す */
+fn open_with_retry(config: &Config, filename: &str) -> MyResult<Input> {
+    let mut attempt = 0;
+    loop {
+        match open_with_timeout(config, filename) {
+            Err(HeadError::Io(e))
+                if attempt < config.retry && is_transient(&e) =>
+            {
+                attempt += 1;
+                eprintln!(
+                    "headr: {}: {}; retrying ({}/{})",
+                    filename, e, attempt, config.retry
+                );
+                thread::sleep(config.retry_delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/* --timeout用。標準入力とFIFOは別のスレッドで開いて読み、timeoutの間データが来なければエラーにする。
+最初のデータもここで待つので、書き手の現れないFIFOはファイルを開けなかったのと同じく報告される。
+通常のファイルはそのまま開く */
+fn open_with_timeout(config: &Config, filename: &str) -> MyResult<Input> {
+    let timeout = match config.timeout {
+        Some(timeout)
+            if config.opener.is_none()
+                && (filename == "-" || is_fifo(filename)) =>
+        {
+            timeout
+        }
+        _ => return open_input(config, filename),
+    };
+
+    let (tx, rx) = mpsc::sync_channel(1);
+    let name = filename.to_string();
+    let chunk_size = config.chunk_size;
+    // 待ちきれなかったときは、このスレッドは読みかけのまま残る
+    thread::spawn(move || {
+        let mut input = match open(&name, chunk_size) {
+            Ok(input) => input,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+        loop {
+            let chunk = match input.reader.fill_buf() {
+                Ok(buf) => buf.to_vec(),
+                Err(e) => {
+                    let _ = tx.send(Err(HeadError::Io(e)));
+                    return;
+                }
+            };
+            input.reader.consume(chunk.len());
+            let eof = chunk.is_empty();
+            if tx.send(Ok(chunk)).is_err() || eof {
+                return;
+            }
+        }
+    });
+
+    let chunk = match rx.recv_timeout(timeout) {
+        Ok(result) => result?,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            return Err(HeadError::Io(timed_out(timeout)))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Vec::new(),
+    };
+    let reader = TimeoutReader {
+        rx,
+        timeout,
+        chunk,
+        pos: 0,
+    };
+    Ok(Input {
+        reader: Box::new(BufReader::new(reader)),
+        size: None,
+    })
+}
+
+#[cfg(unix)]
+fn is_fifo(filename: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(filename).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_filename: &str) -> bool {
+    false
+}
+
+/* 読み込み用のスレッドから受け取ったデータを順に返す */
+struct TimeoutReader {
+    rx: mpsc::Receiver<MyResult<Vec<u8>>>,
+    timeout: std::time::Duration,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl TimeoutReader {
+    /* 次のデータを待つ。空ならEOF */
+    fn recv(&self) -> io::Result<Vec<u8>> {
+        match self.rx.recv_timeout(self.timeout) {
+            Ok(Ok(chunk)) => Ok(chunk),
+            Ok(Err(HeadError::Io(e))) => Err(e),
+            Ok(Err(e)) => Err(io::Error::other(e)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(timed_out(self.timeout))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Ok(Vec::new()),
+        }
+    }
+}
+
+fn timed_out(timeout: std::time::Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no data within {} ms (--timeout)", timeout.as_millis()),
+    )
+}
+
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            self.chunk = self.recv()?;
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/* --decode base64。改行などの空白は読み飛ばし、不正な文字があれば読み込みエラーにする */
+#[cfg(feature = "decode")]
+fn decode_base64(input: Input) -> Input {
+    let reader = base64::read::DecoderReader::new(
+        SkipWhitespace(input.reader),
+        &base64::engine::general_purpose::STANDARD,
+    );
+    Input {
+        reader: Box::new(BufReader::new(Base64Reader(reader))),
+        size: None, // 復号後の大きさは分からない
+    }
+}
+
+#[cfg(feature = "decode")]
+struct SkipWhitespace<R>(R);
+
+#[cfg(feature = "decode")]
+impl<R: Read> Read for SkipWhitespace<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.0.read(buf)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            let mut kept = 0;
+            for pos in 0..len {
+                if !buf[pos].is_ascii_whitespace() {
+                    buf[kept] = buf[pos];
+                    kept += 1;
+                }
+            }
+            // 空白だけだったら続きを読む(0を返すとEOFになってしまう)
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+/* 復号のエラーに、base64が不正だったことを書き添える */
+#[cfg(feature = "decode")]
+struct Base64Reader<R>(R);
+
+#[cfg(feature = "decode")]
+impl<R: Read> Read for Base64Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid base64: {}", e),
+            ),
+            _ => e,
+        })
+    }
+}
+
+/* --decrypt。ageのファイル(ASCII armorでも)かgpgのASCII armorなら、全体を復号してから読む。
+途中まで復号した中身を出力しないよう、最後まで復号して認証できてから渡す。
+暗号化されていなければそのまま読む。復号した中身が圧縮されていれば展開する */
+#[cfg(feature = "decrypt")]
+fn decrypt(config: &Config, filename: &str, input: Input) -> MyResult<Input> {
+    const AGE: &[u8] = b"age-encryption.org/v1\n";
+    const AGE_ARMOR: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+    const GPG_ARMOR: &[u8] = b"-----BEGIN PGP MESSAGE-----";
+
+    let magics = [AGE, AGE_ARMOR, GPG_ARMOR];
+    let len = magics.iter().map(|magic| magic.len()).max().unwrap_or(0);
+    let mut input = peek(input, len, |head| {
+        magics
+            .iter()
+            .any(|magic| head.len() < magic.len() && magic.starts_with(head))
+    })?;
+    let head = input.reader.fill_buf()?;
+    let plain = if head.starts_with(AGE) || head.starts_with(AGE_ARMOR) {
+        decrypt_age(config, filename, input.reader)
+    } else if head.starts_with(GPG_ARMOR) {
+        decrypt_gpg(input.reader)
+    } else {
+        return Ok(input);
+    };
+    let plain = plain.map_err(|e| {
+        HeadError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("cannot decrypt: {}", e),
+        ))
+    })?;
+    decompress(
+        filename,
+        Input {
+            reader: Box::new(io::Cursor::new(plain)),
+            size: None, // 復号後の大きさは読んでみないと分からない
+        },
+    )
+}
+
+/* パスフレーズで暗号化されていれば端末で尋ね、それ以外は--decrypt-keyの鍵で復号する */
+#[cfg(feature = "decrypt")]
+fn decrypt_age(
+    config: &Config,
+    filename: &str,
+    reader: Box<dyn BufRead>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decryptor =
+        age::Decryptor::new(age::armor::ArmoredReader::new(reader))?;
+    let identities: Vec<Box<dyn age::Identity>> = if decryptor.is_scrypt() {
+        let passphrase = rpassword::prompt_password(format!(
+            "headr: passphrase for {}: ",
+            filename
+        ))?;
+        vec![Box::new(age::scrypt::Identity::new(passphrase.into()))]
+    } else {
+        match &config.decrypt_key {
+            Some(path) => age::IdentityFile::from_file(
+                path.to_string_lossy().into_owned(),
+            )?
+            .into_identities()?,
+            None => {
+                return Err("no identity given (use --decrypt-key FILE)".into())
+            }
+        }
+    };
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))?;
+    let mut plain = Vec::new();
+    reader.read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+/* gpgに渡して復号する。鍵やパスフレーズはgpg自身(gpg-agent)に任せる */
+#[cfg(feature = "decrypt")]
+fn decrypt_gpg(
+    mut reader: Box<dyn BufRead>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut armored = Vec::new();
+    reader.read_to_end(&mut armored)?;
+    let mut child = Command::new("gpg")
+        .args(["--quiet", "--decrypt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run gpg: {}", e))?;
+    // 出力を読みながら書かないと、大きな入力ではパイプが詰まる
+    let mut stdin = child.stdin.take().ok_or("cannot write to gpg")?;
+    let writer = thread::spawn(move || stdin.write_all(&armored));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr);
+        return Err(match msg.lines().last() {
+            Some(line) => format!("gpg: {}", line.trim_start_matches("gpg: ")),
+            None => format!("gpg exited with {}", output.status),
+        }
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+/* openしてから、-aが無ければバイナリファイルでないか確かめる */
+fn open_text(config: &Config, filename: &str) -> MyResult<Input> {
+    text_input(config, filename, open_with_retry(config, filename)?)
+}
+
+/* 開いた入力に--limit-rateや--decryptなどを重ね、バイナリでないか調べる */
+fn text_input(
+    config: &Config,
+    filename: &str,
+    input: Input,
+) -> MyResult<Input> {
+    let input = match config.limit_rate {
+        Some(rate) => throttle(input, rate),
+        None => input,
+    };
+    #[cfg(feature = "decrypt")]
+    let input = if config.decrypt {
+        decrypt(config, filename, input)?
+    } else {
+        input
+    };
+    #[cfg(feature = "decode")]
+    let input = if config.decode_base64 {
+        decode_base64(input)
+    } else {
+        input
+    };
+    #[cfg(feature = "encoding")]
+    let input = decode(input, config.encoding);
+    let mut input = input;
+    if !config.text
+        && looks_binary(input.reader.fill_buf()?, config.delimiter())
+    {
+        return Err(HeadError::BinaryFile(filename.to_string()));
+    }
+    Ok(input)
+}
+
+/* --limit-rate。mmapで読まれないよう、サイズは分からないものとして扱う */
+fn throttle(input: Input, rate: u64) -> Input {
+    Input {
+        reader: Box::new(BufReader::new(Throttle {
+            inner: input.reader,
+            rate,
+            start: std::time::Instant::now(),
+            total: 0,
+        })),
+        size: None,
+    }
+}
+
+/* 読み始めてからの平均が毎秒rateバイトを超えないよう、読むたびに進みすぎた分だけ待つ */
+struct Throttle<R> {
+    inner: R,
+    rate: u64,
+    start: std::time::Instant,
+    total: u64,
+}
+
+impl<R: Read> Read for Throttle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // 一度に読むのは1秒分までにして、待ちを細かく刻む
+        let len = buf.len().min(self.rate.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        self.total += n as u64;
+        let due = std::time::Duration::from_secs_f64(
+            self.total as f64 / self.rate as f64,
+        );
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(n)
+    }
+}
+
+/* --encodingが指定されていれば、UTF-8に変換しながら読む。UTF-8ならそのまま読む */
+#[cfg(feature = "encoding")]
+fn decode(
+    input: Input,
+    encoding: Option<&'static encoding_rs::Encoding>,
+) -> Input {
+    match encoding {
+        Some(encoding) if encoding != encoding_rs::UTF_8 => Input {
+            reader: Box::new(BufReader::new(DecodeReader {
+                inner: input.reader,
+                decoder: encoding.new_decoder_without_bom_handling(),
+                buffer: Vec::new(),
+                pos: 0,
+                done: false,
+            })),
+            size: None, // 変換後のバイト数は読んでみないと分からない
+        },
+        _ => input,
+    }
+}
+
+#[cfg(feature = "encoding")]
+struct DecodeReader<R> {
+    inner: R,
+    decoder: encoding_rs::Decoder,
+    buffer: Vec<u8>, // 変換済みでまだ読まれていない分
+    pos: usize,
+    done: bool,
+}
+
+#[cfg(feature = "encoding")]
+impl<R: BufRead> Read for DecodeReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let chunk = self.inner.fill_buf()?;
+            let last = chunk.is_empty();
+            let capacity = self
+                .decoder
+                .max_utf8_buffer_length(chunk.len())
+                .ok_or_else(|| io::Error::other("input too large"))?;
+            self.buffer.resize(capacity, 0);
+            // 出力先は十分な大きさなので、入力は全て消費される
+            let (_, read, written, _) =
+                self.decoder.decode_to_utf8(chunk, &mut self.buffer, last);
+            self.inner.consume(read);
+            self.buffer.truncate(written);
+            self.pos = 0;
+            self.done = last;
+        }
+        let num = out.len().min(self.buffer.len() - self.pos);
+        out[..num].copy_from_slice(&self.buffer[self.pos..self.pos + num]);
+        self.pos += num;
+        Ok(num)
+    }
+}
+
+/* grepと同じように先頭のバッファだけを見て判定する。NULを含むか、
+制御文字が3割を超えればバイナリとみなす(-zなどで区切り文字にしている文字は除く) */
+fn looks_binary(buf: &[u8], delimiter: u8) -> bool {
+    let is_control = |byte: u8| {
+        byte != delimiter
+            && (byte < 0x20 && !b"\t\n\r\x0b\x0c\x08\x1b".contains(&byte)
+                || byte == 0x7f)
+    };
+    buf.iter().any(|&byte| byte == 0 && delimiter != 0)
+        || buf.iter().filter(|&&byte| is_control(byte)).count() * 10
+            > buf.len() * 3
+}
+
+/* "cmd:COMMAND"。cmd機能を有効にしたときだけ、明示したコマンドを実行する */
+fn is_command(filename: &str) -> bool {
+    cfg!(feature = "cmd") && filename.starts_with("cmd:")
+}
+
+/* "cmd:COMMAND"として、シェルで実行したコマンドの標準出力を読む。
+必要な分を読み終えて入力を閉じたら、まだ動いているコマンドは止める。
+最後まで読んで、コマンドが失敗していたら読み込みのエラーにする */
+#[cfg(feature = "cmd")]
+fn open_command(command: &str, chunk_size: usize) -> MyResult<Input> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot run command: {}", e))
+        })?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Input {
+        reader: Box::new(BufReader::with_capacity(
+            chunk_size,
+            CommandReader { child, stdout },
+        )),
+        size: None,
+    })
+}
+
+#[cfg(feature = "cmd")]
+struct CommandReader {
+    child: Child,
+    stdout: std::process::ChildStdout,
+}
+
+#[cfg(feature = "cmd")]
+impl Read for CommandReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stdout.read(buf)?;
+        if len == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "command failed: {}",
+                    status
+                )));
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "cmd")]
+impl Drop for CommandReader {
+    fn drop(&mut self) {
+        // 終わっていれば何もしない。killの失敗(既に終わっていた)は気にしない
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(feature = "http")]
+fn is_url(filename: &str) -> bool {
+    filename.starts_with("http://") || filename.starts_with("https://")
+}
+
+/* レスポンスの本文を少しずつ読むので、必要な分を読んだ後は残りをダウンロードしない */
+#[cfg(feature = "http")]
+fn open_url(url: &str) -> MyResult<Input> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| HeadError::Io(io::Error::other(e)))?;
+    Ok(Input {
+        reader: Box::new(BufReader::new(response.into_reader())),
+        size: None,
+    })
+}
+
+/* --cacheのファイルの先頭行。続けて読んだ分の本文をlenバイト置く */
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    url: String,
+    fetched: u64, // 取得したUNIX時刻(秒)
+    max_age: u64, // fetchedから何秒使えるか
+    #[serde(default)]
+    from_header: bool, // max_ageがCache-Controlで決まったか。違えば今の--cache-ttlでも縮める
+    complete: bool, // 本文の最後まで読んだか
+    len: usize,
+}
+
+/* URLごとのファイル名。Rustの版が変わっても同じ名前になるよう、FNV-1aで求める */
+#[cfg(feature = "http")]
+fn cache_path(dir: &Path, url: &str) -> PathBuf {
+    let hash = url.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    dir.join(format!("{:016x}.cache", hash))
+}
+
+#[cfg(feature = "http")]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/* まだ使えるキャッシュを読む。無い、古い、別のURLのもの、壊れているものはNoneにして取り直させる */
+#[cfg(feature = "http")]
+fn read_cache(
+    path: &Path,
+    url: &str,
+    now: u64,
+    ttl: u64,
+) -> Option<(CacheEntry, Vec<u8>)> {
+    let data = fs::read(path).ok()?;
+    let newline = data.iter().position(|byte| *byte == b'\n')?;
+    let entry: CacheEntry = serde_json::from_slice(&data[..newline]).ok()?;
+    let body = &data[newline + 1..];
+    let max_age = match entry.from_header {
+        true => entry.max_age,
+        false => entry.max_age.min(ttl),
+    };
+    let fresh = now < entry.fetched.saturating_add(max_age);
+    (entry.url == url && entry.len == body.len() && fresh)
+        .then(|| (entry, body.to_vec()))
+}
+
+/* 書いている途中のファイルを読まれないよう、同じディレクトリに書いてから置き換える */
+#[cfg(feature = "http")]
+fn write_cache(
+    path: &Path,
+    entry: &CacheEntry,
+    body: &[u8],
+) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&mut file, entry)?;
+    file.write_all(b"\n")?;
+    file.write_all(body)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/* Cache-Controlのmax-ageを秒数にする。no-storeならNone(保存しない)、no-cacheは0秒。
+どちらも無ければttlで、2つ目はfalseになる */
+#[cfg(feature = "http")]
+fn cache_max_age(
+    cache_control: Option<&str>,
+    ttl: u64,
+) -> Option<(u64, bool)> {
+    let mut max_age = (ttl, false);
+    for directive in cache_control.unwrap_or_default().split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.as_str() {
+            "no-store" => return None,
+            "no-cache" => max_age = (0, true),
+            _ => {
+                if let Some(secs) = directive
+                    .strip_prefix("max-age=")
+                    .and_then(|secs| secs.parse().ok())
+                {
+                    max_age = (secs, true);
+                }
+            }
+        }
+    }
+    Some(max_age)
+}
+
+#[cfg(feature = "http")]
+fn fetch_url(url: &str) -> MyResult<ureq::Response> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| HeadError::Io(io::Error::other(e)))
+}
+
+/* --cache。まだ使えるキャッシュがあればその本文から読み、なければ取得しながら読んだ分を覚える */
+#[cfg(feature = "http")]
+fn open_cached_url(config: &Config, url: &str) -> MyResult<Input> {
+    let dir = config.cache.as_deref().unwrap_or(Path::new("."));
+    let path = cache_path(dir, url);
+    let now = unix_now();
+    let cached = match read_cache(&path, url, now, config.cache_ttl.as_secs())
+    {
+        Some((entry, body)) => {
+            log::debug!("{}: using {}", url, path.display());
+            CachedUrl {
+                url: url.to_string(),
+                path,
+                entry,
+                body,
+                offset: 0,
+                response: None,
+                store: true,
+                changed: false,
+                limit: config.max_buffer,
+            }
+        }
+        None => {
+            let response = fetch_url(url)?;
+            let max_age = cache_max_age(
+                response.header("cache-control"),
+                config.cache_ttl.as_secs(),
+            );
+            CachedUrl {
+                url: url.to_string(),
+                path,
+                entry: CacheEntry {
+                    url: url.to_string(),
+                    fetched: now,
+                    max_age: max_age.map_or(0, |(secs, _)| secs),
+                    from_header: max_age
+                        .is_some_and(|(_, from_header)| from_header),
+                    complete: false,
+                    len: 0,
+                },
+                body: Vec::new(),
+                offset: 0,
+                response: Some(response.into_reader()),
+                store: max_age.is_some(),
+                changed: true,
+                limit: config.max_buffer,
+            }
+        }
+    };
+    Ok(Input {
+        reader: Box::new(BufReader::with_capacity(config.chunk_size, cached)),
+        size: None,
+    })
+}
+
+/* open_cached_urlの入力。先にbodyを返し、足りなければURLを取り直してbodyの続きから読む。
+読んだ分は--max-bufferまでbodyに足し、終わったときにキャッシュに書き戻す */
+#[cfg(feature = "http")]
+struct CachedUrl {
+    url: String,
+    path: PathBuf,
+    entry: CacheEntry,
+    body: Vec<u8>,
+    offset: usize, // 読み出したバイト数
+    response: Option<Box<dyn Read + Send + Sync>>,
+    store: bool,
+    changed: bool,
+    limit: usize,
+}
+
+#[cfg(feature = "http")]
+impl Read for CachedUrl {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset < self.body.len() {
+            let num = buf.len().min(self.body.len() - self.offset);
+            buf[..num]
+                .copy_from_slice(&self.body[self.offset..self.offset + num]);
+            self.offset += num;
+            return Ok(num);
+        }
+        if self.entry.complete {
+            return Ok(0);
+        }
+        let response = match &mut self.response {
+            Some(response) => response,
+            None => {
+                log::debug!(
+                    "{}: reading past the cached {} bytes",
+                    self.url,
+                    self.offset
+                );
+                let mut response = fetch_url(&self.url)
+                    .map_err(|e| io::Error::other(e.to_string()))?
+                    .into_reader();
+                io::copy(
+                    &mut (&mut response).take(self.offset as u64),
+                    &mut io::sink(),
+                )?;
+                self.response.insert(response)
+            }
+        };
+        let num = response.read(buf)?;
+        if num == 0 {
+            self.entry.complete = self.offset == self.body.len();
+        } else if self.offset == self.body.len()
+            && self.body.len() + num <= self.limit
+        {
+            self.body.extend_from_slice(&buf[..num]);
+        }
+        self.offset += num;
+        self.changed = true;
+        Ok(num)
+    }
+}
+
+#[cfg(feature = "http")]
+impl Drop for CachedUrl {
+    fn drop(&mut self) {
+        if !self.store || !self.changed {
+            return;
+        }
+        self.entry.len = self.body.len();
+        if let Err(e) = write_cache(&self.path, &self.entry, &self.body) {
+            log::debug!(
+                "{}: cannot write {}: {}",
+                self.url,
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/* tar://archive.tar!path/to/member の形で、アーカイブの中の1つのファイルを読む。
+通常のtarファイルならメンバーの位置までシークしてそのまま読み、
+gzipで圧縮されたものなどシークできない場合はメンバーの中身をメモリに読み込む */
+#[cfg(feature = "tar")]
+fn open_tar(spec: &str, chunk_size: usize) -> MyResult<Input> {
+    use std::io::{Seek, SeekFrom};
+
+    let (archive_path, member) = spec
+        .strip_prefix("tar://")
+        .and_then(|rest| rest.split_once('!'))
+        .ok_or_else(|| {
+            HeadError::InvalidArgument(format!(
+                "expected tar://ARCHIVE!MEMBER -- {}",
+                spec
+            ))
+        })?;
+    let is_member = |path: &Path| {
+        let trim =
+            |path: &Path| path.strip_prefix("./").unwrap_or(path).to_owned();
+        trim(path) == trim(Path::new(member))
+    };
+    let not_found = || {
+        HeadError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such member in {}", archive_path),
+        ))
+    };
+
+    let archive = open(archive_path, chunk_size)?;
+    if archive.size.is_some() {
+        let mut file = File::open(archive_path)?;
+        let mut found = None;
+        for entry in tar::Archive::new(&file).entries()? {
+            let entry = entry?;
+            if is_member(&entry.path()?) {
+                found = Some((entry.raw_file_position(), entry.size()));
+                break;
+            }
+        }
+        let (position, size) = found.ok_or_else(not_found)?;
+        file.seek(SeekFrom::Start(position))?;
+        Ok(Input {
+            reader: Box::new(BufReader::with_capacity(
+                chunk_size,
+                file.take(size),
+            )),
+            size: None, // 名前で開き直せないので、通常ファイルとしては扱わない
+        })
+    } else {
+        let mut entries = tar::Archive::new(archive.reader);
+        for entry in entries.entries()? {
+            let mut entry = entry?;
+            if is_member(&entry.path()?) {
+                let mut buffer = Vec::new();
+                entry.read_to_end(&mut buffer)?;
+                return Ok(Input {
+                    reader: Box::new(io::Cursor::new(buffer)),
+                    size: None,
+                });
+            }
+        }
+        Err(not_found())
+    }
+}
+
+/* 展開しながら読める圧縮形式。それぞれ同じ名前の機能を有効にしたものだけを扱う */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    fn enabled() -> impl Iterator<Item = Compression> {
+        [
+            (Compression::Gzip, cfg!(feature = "gzip")),
+            (Compression::Xz, cfg!(feature = "xz")),
+            (Compression::Zstd, cfg!(feature = "zstd")),
+            (Compression::Bzip2, cfg!(feature = "bzip2")),
+        ]
+        .into_iter()
+        .filter_map(|(format, enabled)| enabled.then_some(format))
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Xz => ".xz",
+            Compression::Zstd => ".zst",
+            Compression::Bzip2 => ".bz2",
+        }
+    }
+
+    /* 中身から形式を決めるときに見る先頭のバイト数 */
+    fn header_len(self) -> usize {
+        match self {
+            Compression::Gzip => 4,
+            Compression::Xz => 8,
+            Compression::Zstd => 5,
+            Compression::Bzip2 => 10,
+        }
+    }
+
+    /* 先頭i番目のバイトとしてありうるか。マジックナンバーだけでは"BZh"で始まる
+    テキストなども拾ってしまうので、その後ろのヘッダーの決まった値まで確かめる。
+    gzipは圧縮方式がdeflateで予約ビットが0、xzはストリームフラグの予約ビットが0、
+    zstdはフレームヘッダーの予約ビットが0、bzip2はブロックサイズの数字と最初の
+    ブロックのマジックナンバーが続くこと。中身の無いbzip2は拡張子でしか決まらない */
+    fn header_byte_ok(self, i: usize, byte: u8) -> bool {
+        const BZIP2_BLOCK: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+        match (self, i) {
+            (Compression::Gzip, 0) => byte == 0x1f,
+            (Compression::Gzip, 1) => byte == 0x8b,
+            (Compression::Gzip, 2) => byte == 0x08,
+            (Compression::Gzip, 3) => byte & 0xe0 == 0,
+            (Compression::Xz, 0..=5) => {
+                byte == [0xfd, b'7', b'z', b'X', b'Z', 0x00][i]
+            }
+            (Compression::Xz, 6) => byte == 0,
+            (Compression::Xz, 7) => byte & 0xf0 == 0,
+            (Compression::Zstd, 0..=3) => byte == [0x28, 0xb5, 0x2f, 0xfd][i],
+            (Compression::Zstd, 4) => byte & 0x08 == 0,
+            (Compression::Bzip2, 0..=2) => byte == b"BZh"[i],
+            (Compression::Bzip2, 3) => (b'1'..=b'9').contains(&byte),
+            (Compression::Bzip2, 4..=9) => byte == BZIP2_BLOCK[i - 4],
+            _ => true,
+        }
+    }
+
+    /* headがこの形式のヘッダーの途中までと食い違っていないか */
+    fn header_prefix_ok(self, head: &[u8]) -> bool {
+        head.iter()
+            .take(self.header_len())
+            .enumerate()
+            .all(|(i, &byte)| self.header_byte_ok(i, byte))
+    }
+
+    fn header_matches(self, head: &[u8]) -> bool {
+        head.len() >= self.header_len() && self.header_prefix_ok(head)
+    }
+
+    /* どれも複数のフレーム(gzipならメンバー)を続けて読む */
+    fn decoder(self, reader: Box<dyn BufRead>) -> io::Result<Box<dyn Read>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+            }
+            #[cfg(feature = "xz")]
+            Compression::Xz => Ok(Box::new(
+                xz2::bufread::XzDecoder::new_multi_decoder(reader),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+            }
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => {
+                Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(reader)))
+            }
+            // enabledが返さない形式なので、ここには来ない
+            #[allow(unreachable_patterns)]
+            _ => Ok(Box::new(reader)),
+        }
+    }
+}
+
+/* 圧縮されていれば展開しながら読む。形式は拡張子(.gz、.xz、.zst、.bz2)で決め、
+どれでもなければ先頭のマジックナンバーとヘッダーで決める。拡張子で決めたものは中身が違えばエラーになる。
+先頭はfill_bufで覗くだけなので消費しない */
+fn decompress(filename: &str, input: Input) -> MyResult<Input> {
+    decompress_detected(filename, input).map(|(input, _)| input)
+}
+
+/* decompressと同じだが、展開したかどうかも返す */
+fn decompress_detected(
+    filename: &str,
+    input: Input,
+) -> MyResult<(Input, bool)> {
+    let mut input = input;
+    let format = match Compression::enabled()
+        .find(|format| filename.ends_with(format.extension()))
+    {
+        Some(format) => Some(format),
+        None => {
+            let len = Compression::enabled()
+                .map(|format| format.header_len())
+                .max();
+            let Some(len) = len else {
+                return Ok((input, false));
+            };
+            // まだどれかのヘッダーの途中かもしれない間だけ読み足す
+            input = peek(input, len, |head| {
+                Compression::enabled().any(|format| {
+                    head.len() < format.header_len()
+                        && format.header_prefix_ok(head)
+                })
+            })?;
+            let head = input.reader.fill_buf()?;
+            Compression::enabled().find(|format| format.header_matches(head))
+        }
+    };
+    match format {
+        Some(format) => Ok((
+            Input {
+                reader: Box::new(BufReader::new(
+                    format.decoder(input.reader)?,
+                )),
+                size: None,
+            },
+            true,
+        )),
+        None => Ok((input, false)),
+    }
+}
+
+/* 先頭のlenバイトをバッファに揃える。パイプからは1回のreadで1バイトしか来ないことがあるので、
+足りなければ読み足し、読んだ分は先頭に戻して何も読んでいないのと同じ状態にする。
+moreがfalseを返したらlenバイトに満たなくてもそこで止め、1行ずつ書かれるパイプを待たせない */
+fn peek(
+    mut input: Input,
+    len: usize,
+    more: impl Fn(&[u8]) -> bool,
+) -> io::Result<Input> {
+    let buffered = input.reader.fill_buf()?;
+    if buffered.len() >= len || !more(buffered) {
+        return Ok(input);
+    }
+    let mut head = Vec::with_capacity(len);
+    let mut chunk = vec![0; len];
+    while head.len() < len && more(&head) {
+        let num_read = input.reader.read(&mut chunk[..len - head.len()])?;
+        if num_read == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..num_read]);
+    }
+    Ok(Input {
+        reader: Box::new(BufReader::new(
+            io::Cursor::new(head).chain(input.reader),
+        )),
+        size: input.size,
+    })
+}
+
+/* 先頭のUTF-8のBOMを読み飛ばす。-cなどのバイト数はBOMの後から数える */
+fn strip_bom(mut input: Input) -> MyResult<Input> {
+    const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+    if input.reader.fill_buf()?.starts_with(&UTF8_BOM) {
+        input.reader.consume(UTF8_BOM.len());
+        input.size = input.size.map(|size| size - UTF8_BOM.len() as u64);
+    }
+    Ok(input)
+}
+
+/* --keep-header。先頭の1行は-nに数えずにそのまま出力し、残りを続きとして返す */
+fn print_first_line(
+    config: &Config,
+    mut input: Input,
+    offset: Option<u64>,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<Input> {
+    let mut line = Vec::new();
+    input.reader.read_until(delimiter, &mut line)?;
+    check_utf8(config, offset, &line)?;
+    write_body(config, &line, out)?;
+    input.size = input.size.map(|size| size - line.len() as u64);
+    Ok(input)
+}
+
+/* --skipの分だけ読み捨てる。-cのときはバイト数、それ以外は行数。
+途中でEOFになったら、そこで止めて残りは空のまま続ける */
+fn skip_input(
+    config: &Config,
+    mut input: Input,
+    delimiter: u8,
+) -> MyResult<Input> {
+    if config.skip == 0 {
+        return Ok(input);
+    }
+    let skipped = if config.byte_mode() {
+        io::copy(
+            &mut (&mut input.reader).take(config.skip as u64),
+            &mut io::sink(),
+        )?
+    } else {
+        let mut skipped = 0;
+        for _ in 0..config.skip {
+            match input.reader.skip_until(delimiter)? {
+                0 => break,
+                num => skipped += num as u64,
+            }
+        }
+        skipped
+    };
+    input.size = input.size.map(|size| size.saturating_sub(skipped));
+    Ok(input)
+}
+
+/* --build-indexと--use-indexの索引。FILE.headr-idxに、元のファイルの大きさと更新時刻、区切り文字と、
+2行目以降の各行の始まりのバイト位置を置く。数値は全てリトルエンディアン。
+大きさと更新時刻が今のファイルと違う索引は古いものとして使わない */
+const INDEX_MAGIC: &[u8; 8] = b"HEADRIX1";
+const INDEX_HEADER_LEN: u64 = 8 + 8 + 8 + 4 + 1;
+
+fn index_path(filename: &str) -> PathBuf {
+    PathBuf::from(format!("{}.headr-idx", filename))
+}
+
+/* 索引に記録する、元のファイルの大きさと更新時刻(UNIX時刻の秒とナノ秒) */
+fn index_stamp(meta: &fs::Metadata) -> io::Result<(u64, u64, u32)> {
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((meta.len(), mtime.as_secs(), mtime.subsec_nanos()))
+}
+
+/* --build-index。ファイルごとに索引を書き、先頭は出力しない。失敗したファイルの数でエラーを返す */
+fn write_indexes(config: &Config) -> MyResult<()> {
+    let mut num_failed = 0;
+    for filename in &config.files {
+        if stop_early(config, num_failed) {
+            break;
+        }
+        if let Err(e) = build_index(filename, config.delimiter()) {
+            report_error(config, filename, &e);
+            num_failed += usize::from(is_failure(&e));
+        }
+    }
+    check_failed(config, num_failed)
+}
+
+/* 索引を作る。読み直せない標準入力や、通常のファイルでないものはエラーにする */
+fn build_index(filename: &str, delimiter: u8) -> MyResult<()> {
+    if filename == "-" {
+        return Err(HeadError::Io(io::Error::other(
+            "standard input cannot be indexed",
+        )));
+    }
+    let file = File::open(filename).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => {
+            HeadError::FileNotFound(filename.to_string())
+        }
+        _ => HeadError::Io(e),
+    })?;
+    let meta = file.metadata()?;
+    if meta.is_dir() {
+        return Err(HeadError::IsADirectory(filename.to_string()));
+    }
+    if !meta.is_file() {
+        return Err(HeadError::Io(io::Error::other(
+            "only regular files can be indexed",
+        )));
+    }
+    let (size, secs, nanos) = index_stamp(&meta)?;
+
+    let path = index_path(filename);
+    let mut out = BufWriter::new(File::create(&path).map_err(|e| {
+        io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+    })?);
+    out.write_all(INDEX_MAGIC)?;
+    out.write_all(&size.to_le_bytes())?;
+    out.write_all(&secs.to_le_bytes())?;
+    out.write_all(&nanos.to_le_bytes())?;
+    out.write_all(&[delimiter])?;
+
+    let mut reader = BufReader::new(file);
+    let mut offset = 0u64;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for (pos, _) in
+            buf.iter().enumerate().filter(|(_, b)| **b == delimiter)
+        {
+            let start = offset + pos as u64 + 1;
+            // 最後の区切り文字の後ろに行が無ければ、その位置は書かない
+            if start < size {
+                out.write_all(&start.to_le_bytes())?;
+            }
+        }
+        let len = buf.len();
+        offset += len as u64;
+        reader.consume(len);
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/* 索引からnum_lines行目の次の行の始まりを読む。行が足りなければファイルの終わりを返す。
+索引が無ければNoneを、古いか壊れていればエラーを返す */
+fn index_offset(
+    filename: &str,
+    num_lines: usize,
+    delimiter: u8,
+) -> MyResult<Option<u64>> {
+    use std::io::Seek;
+
+    let path = index_path(filename);
+    let mut index = match File::open(&path) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(HeadError::Io(e)),
+    };
+    let stale = |msg: &str| {
+        HeadError::Io(io::Error::other(format!(
+            "{}: {}, ignoring it",
+            path.display(),
+            msg
+        )))
+    };
+
+    let mut header = [0; INDEX_HEADER_LEN as usize];
+    index
+        .read_exact(&mut header)
+        .map_err(|_| stale("not a headr index"))?;
+    if &header[..8] != INDEX_MAGIC {
+        return Err(stale("not a headr index"));
+    }
+    let num = |range: std::ops::Range<usize>| {
+        let mut bytes = [0; 8];
+        bytes[..range.len()].copy_from_slice(&header[range]);
+        u64::from_le_bytes(bytes)
+    };
+    let stamp = (num(8..16), num(16..24), num(24..28) as u32);
+    if stamp != index_stamp(&fs::metadata(filename)?)? {
+        return Err(stale("the index is out of date"));
+    }
+    if header[28] != delimiter {
+        return Err(stale("the index was built for another delimiter"));
+    }
+
+    let num_entries = (index.metadata()?.len() - INDEX_HEADER_LEN) / 8;
+    if num_lines as u64 > num_entries {
+        return Ok(Some(stamp.0));
+    }
+    index.seek(io::SeekFrom::Start(
+        INDEX_HEADER_LEN + (num_lines as u64 - 1) * 8,
+    ))?;
+    let mut entry = [0; 8];
+    index.read_exact(&mut entry)?;
+    Ok(Some(u64::from_le_bytes(entry)))
+}
+
+/* --use-index。通常ファイルを行単位で--skipするときは、読み飛ばす代わりに索引の位置へ移る。
+使えない索引は警告して、いつもどおり読み飛ばす */
+fn skip_with_index(
+    config: &Config,
+    filename: &str,
+    input: Input,
+    delimiter: u8,
+) -> MyResult<Input> {
+    use std::io::Seek;
+
+    if config.skip == 0 || config.byte_mode() || input.size.is_none() {
+        return skip_input(config, input, delimiter);
+    }
+    let offset = match index_offset(filename, config.skip, delimiter) {
+        Ok(Some(offset)) => offset,
+        Ok(None) => return skip_input(config, input, delimiter),
+        Err(e) => {
+            eprintln!("headr: {}", e);
+            return skip_input(config, input, delimiter);
+        }
+    };
+    let mut file = File::open(filename)?;
+    let size = file.metadata()?.len();
+    file.seek(io::SeekFrom::Start(offset))?;
+    log::debug!("{}: skipped to byte {} with the index", filename, offset);
+    Ok(Input {
+        reader: Box::new(BufReader::new(file)),
+        size: Some(size.saturating_sub(offset)),
+    })
+}
+
+#[cfg(feature = "regex")]
+fn parse_regex(val: &str) -> MyResult<regex::Regex> {
+    regex::Regex::new(val).map_err(|e| {
+        HeadError::InvalidArgument(format!(
+            "invalid regular expression -- {}: {}",
+            val, e
+        ))
+    })
+}
+
+#[cfg(feature = "encoding")]
+fn parse_encoding(val: &str) -> MyResult<&'static encoding_rs::Encoding> {
+    encoding_rs::Encoding::for_label(val.as_bytes()).ok_or_else(|| {
+        HeadError::InvalidArgument(format!("unknown encoding -- {}", val))
+    })
+}
+
+fn parse_skip(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal skip count -- {}", val))
+    })
+}
+
+fn parse_delimiter(val: &str) -> MyResult<u8> {
+    match val.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(HeadError::InvalidArgument(format!(
+            "the delimiter must be a single byte -- {}",
+            val
+        ))),
+    }
+}
+
+/* 1以上の整数。数字でない、0、大きすぎる場合はInvalidLineCountを返す */
+pub fn parse_positive_int(val: &str) -> MyResult<usize> {
+    match val.parse::<usize>() {
+        Ok(num) if num > 0 => Ok(num),
+        _ => Err(HeadError::InvalidLineCount(val.to_string())),
+    }
+}
+
+/* parse_positive_intの符号付き版。"-5"のような負の数と0も受け付ける */
+pub fn parse_int(val: &str) -> MyResult<i64> {
+    val.parse::<i64>()
+        .map_err(|_| HeadError::InvalidLineCount(val.to_string()))
+}
+
+/* シェルが展開しなかった先頭の~と~user、$VARと${VAR}を展開する。"-"はそのまま。
+変な名前のファイルを開かないよう、展開できないものはエラーにする。
+'price$USD.csv'のように、その名前のファイルがあれば展開しない */
+fn expand_path(filename: &str) -> MyResult<String> {
+    // cmd:のコマンドはシェルが展開する
+    if filename == "-"
+        || is_command(filename)
+        || fs::symlink_metadata(filename).is_ok()
+    {
+        return Ok(filename.to_string());
+    }
+    let error = |what: &str, val: &str| {
+        HeadError::InvalidArgument(format!("{} -- {}", what, val))
+    };
+    let mut expanded = String::new();
+    let mut rest = filename;
+
+    if let Some(after) = filename.strip_prefix('~') {
+        let (user, tail) =
+            after.split_at(after.find('/').unwrap_or(after.len()));
+        let home = if user.is_empty() {
+            std::env::var("HOME")
+                .map_err(|_| error("undefined variable", "HOME"))?
+        } else {
+            user_home(user).ok_or_else(|| error("unknown user", user))?
+        };
+        expanded.push_str(&home);
+        rest = tail;
+    }
+
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, tail) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(error("unterminated variable", filename)),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        // "$"の後に名前が無ければ、そのまま"$"として扱う
+        if name.is_empty() && !after.starts_with('{') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let value = std::env::var(name)
+            .map_err(|_| error("undefined variable", name))?;
+        expanded.push_str(&value);
+        rest = tail;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/* ~userのホームディレクトリをパスワードデータベースから探す */
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<String> {
+    use std::ffi::{CStr, CString};
+
+    let name = CString::new(user).ok()?;
+    let mut buffer = vec![0; 1024];
+    loop {
+        // SAFETY: pwdの文字列はbufferを指すので、bufferが生きている間だけ読む
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => {
+                let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+                return Some(dir.to_string_lossy().into_owned());
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<String> {
+    None
+}
+
+/* --files0-from。NULで区切られたファイル名の一覧を読む。最後のNULの後ろは無視し、
+空の名前と、一覧を標準入力から読むときの"-"はエラーにする */
+fn read_files0(list: &str) -> MyResult<Vec<String>> {
+    let buffer = if list == "-" {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        buffer
+    } else {
+        fs::read(list).map_err(|e| {
+            HeadError::Io(io::Error::new(e.kind(), format!("{}: {}", list, e)))
+        })?
+    };
+    let buffer = buffer.strip_suffix(b"\0").unwrap_or(&buffer);
+    if buffer.is_empty() {
+        return Ok(Vec::new());
+    }
+    buffer
+        .split(|byte| *byte == b'\0')
+        .enumerate()
+        .map(|(num, name)| match name {
+            b"" => Err(HeadError::InvalidArgument(format!(
+                "{}:{}: invalid zero-length file name",
+                list,
+                num + 1
+            ))),
+            b"-" if list == "-" => Err(HeadError::InvalidArgument(format!(
+                "{}:{}: file name '-' is not allowed when reading file names from stdin",
+                list,
+                num + 1
+            ))),
+            name => Ok(String::from_utf8_lossy(name).into_owned()),
+        })
+        .collect()
+}
+
+/* ワイルドカードを展開するか。シェルが展開しないWindowsでは既定で展開し、Unixではしない */
+fn expand_wildcards(matches: &clap::ArgMatches) -> bool {
+    matches.is_present("glob")
+        || (cfg!(windows) && !matches.is_present("no_glob"))
+}
+
+/* シェルが展開しなかったワイルドカードを展開する。"-"やワイルドカードを含まない名前はそのまま */
+fn expand_globs(files: Vec<String>) -> MyResult<Vec<String>> {
+    let mut expanded = Vec::new();
+    for filename in files {
+        if filename == "-"
+            || is_command(&filename)
+            || !filename.contains(['*', '?', '['])
+        {
+            expanded.push(filename);
+            continue;
+        }
+
+        let paths = glob::glob(&filename).map_err(|e| {
+            HeadError::InvalidArgument(format!(
+                "invalid pattern -- {}: {}",
+                filename, e
+            ))
+        })?;
+        let before = expanded.len();
+        for path in paths {
+            let path = path.map_err(|e| HeadError::Io(e.into()))?;
+            expanded.push(path.to_string_lossy().into_owned());
+        }
+        if expanded.len() == before {
+            return Err(HeadError::InvalidArgument(format!(
+                "no match for pattern -- {}",
+                filename
+            )));
+        }
+    }
+    Ok(expanded)
+}
+
+/* --exclude。ファイル名の部分かパス全体がどれかのパターンに合うファイルを除く。"-"は除かない */
+fn exclude_files<'a>(
+    files: Vec<String>,
+    patterns: impl Iterator<Item = &'a str>,
+) -> MyResult<Vec<String>> {
+    let patterns = patterns
+        .map(|pattern| {
+            glob::Pattern::new(pattern).map_err(|e| {
+                HeadError::InvalidArgument(format!(
+                    "invalid pattern -- {}: {}",
+                    pattern, e
+                ))
+            })
+        })
+        .collect::<MyResult<Vec<_>>>()?;
+    Ok(files
+        .into_iter()
+        .filter(|filename| {
+            let path = Path::new(filename);
+            let name = path.file_name().map(Path::new);
+            filename == "-"
+                || !patterns.iter().any(|pattern| {
+                    pattern.matches_path(path)
+                        || name.is_some_and(|name| pattern.matches_path(name))
+                })
+        })
+        .collect())
+}
+
+fn parse_max_buffer(val: &str) -> MyResult<usize> {
+    parse_size(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal buffer size -- {}", val))
+    })
+}
+
+fn parse_limit_rate(val: &str) -> MyResult<u64> {
+    parse_size(val)
+        .ok()
+        .filter(|rate| *rate > 0)
+        .map(|rate| rate as u64)
+        .ok_or_else(|| {
+            HeadError::InvalidArgument(format!("illegal rate -- {}", val))
+        })
+}
+
+fn parse_chunk_size(val: &str) -> MyResult<usize> {
+    parse_size(val)
+        .ok()
+        .filter(|size| *size > 0 && *size <= MAX_CHUNK_SIZE)
+        .ok_or_else(|| {
+            HeadError::InvalidArgument(format!(
+                "illegal chunk size -- {}",
+                val
+            ))
+        })
+}
+
+fn parse_header_format(val: &str) -> MyResult<String> {
+    match val.matches("{}").count() {
+        1 => Ok(val.to_string()),
+        _ => Err(HeadError::InvalidArgument(format!(
+            "the header format must contain exactly one {{}} -- {}",
+            val
+        ))),
+    }
+}
+
+fn parse_separator_lines(val: &str) -> MyResult<usize> {
+    val.parse().map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal separator line count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_retry(val: &str) -> MyResult<usize> {
+    val.parse().map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal retry count -- {}", val))
+    })
+}
+
+fn parse_retry_delay(val: &str) -> MyResult<std::time::Duration> {
+    val.parse()
+        .map(std::time::Duration::from_millis)
+        .map_err(|_| {
+            HeadError::InvalidArgument(format!(
+                "illegal retry delay -- {}",
+                val
+            ))
+        })
+}
+
+/* --cache-ttl。0なら毎回取り直す */
+#[cfg(feature = "http")]
+fn parse_cache_ttl(val: &str) -> MyResult<std::time::Duration> {
+    val.parse()
+        .map(std::time::Duration::from_secs)
+        .map_err(|_| {
+            HeadError::InvalidArgument(format!("illegal cache ttl -- {}", val))
+        })
+}
+
+fn parse_timeout(val: &str) -> MyResult<std::time::Duration> {
+    parse_positive_int(val)
+        .map(|millis| std::time::Duration::from_millis(millis as u64))
+        .map_err(|_| {
+            HeadError::InvalidArgument(format!("illegal timeout -- {}", val))
+        })
+}
+
+fn parse_split(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal split line count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_split_width(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal suffix width -- {}", val))
+    })
+}
+
+fn parse_char_count(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal character count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_grapheme_count(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal grapheme count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_word_count(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal word count -- {}", val))
+    })
+}
+
+fn parse_context(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal context line count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_min_lines(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!(
+            "illegal minimum line count -- {}",
+            val
+        ))
+    })
+}
+
+fn parse_wrap(val: &str) -> MyResult<usize> {
+    val.parse().map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal wrap width -- {}", val))
+    })
+}
+
+fn parse_field_count(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal field count -- {}", val))
+    })
+}
+
+/* --field-sepは1文字。シェルで書きにくいので"\\t"はタブとして扱う */
+fn parse_field_sep(val: &str) -> MyResult<char> {
+    let mut chars = val.chars();
+    match (val, chars.next(), chars.next()) {
+        ("\\t", _, _) => Ok('\t'),
+        (_, Some(sep), None) => Ok(sep),
+        _ => Err(HeadError::InvalidArgument(format!(
+            "the field separator must be a single character -- {}",
+            val
+        ))),
+    }
+}
+
+fn parse_line_number(val: &str) -> MyResult<usize> {
+    parse_positive_int(val).map_err(|_| {
+        HeadError::InvalidArgument(format!("illegal line number -- {}", val))
+    })
+}
+
+/* "1-3,10,20-22"のような行の範囲の指定を読む */
+fn parse_line_ranges(val: &str) -> MyResult<Vec<(usize, usize)>> {
+    parse_ranges(val, "line")
+}
+
+/* --columnsの"1-10,20-30"。書き方は--lines-rangesと同じ */
+fn parse_column_ranges(val: &str) -> MyResult<Vec<(usize, usize)>> {
+    parse_ranges(val, "column")
+}
+
+fn parse_ranges(val: &str, unit: &str) -> MyResult<Vec<(usize, usize)>> {
+    let illegal = || {
+        HeadError::InvalidArgument(format!(
+            "illegal {} range -- {}",
+            unit, val
+        ))
+    };
+    let ranges = val
+        .split(',')
+        .map(|range| {
+            let (first, last) =
+                range.split_once('-').unwrap_or((range, range));
+            match (parse_positive_int(first), parse_positive_int(last)) {
+                (Ok(first), Ok(last)) if first <= last => Ok((first, last)),
+                _ => Err(illegal()),
+            }
+        })
+        .collect::<MyResult<Vec<_>>>()?;
+    Ok(merge_ranges(ranges))
+}
+
+/* 範囲を並べ替え、重なるものや隣り合うものを1つにまとめる */
+fn merge_ranges(mut ranges: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, prev_last)) if first <= prev_last.saturating_add(1) => {
+                *prev_last = last.max(*prev_last);
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+/* --repeatの間隔。0.5のような小数も受け付ける */
+fn parse_interval(val: &str) -> MyResult<std::time::Duration> {
+    // Durationに収まらない大きな値もエラーにする
+    match val.parse::<f64>() {
+        Ok(secs) if secs > 0.0 => std::time::Duration::try_from_secs_f64(secs)
+            .map_err(|_| illegal_interval(val)),
+        _ => Err(illegal_interval(val)),
+    }
+}
+
+fn illegal_interval(val: &str) -> HeadError {
+    HeadError::InvalidArgument(format!("illegal interval -- {}", val))
+}
+
+/* 標準入力は一度しか読めないので、2つ目以降の"-"は取り除く */
+fn dedup_stdin(files: &mut Vec<String>) {
+    let mut seen_stdin = false;
+    files.retain(|filename| {
+        if filename != "-" {
+            return true;
+        }
+        !std::mem::replace(&mut seen_stdin, true)
+    });
+}
+
+/* --sort-files。名前をバイト列として比べて並べる。標準入力(-)は--reverseでも常に先頭にする */
+fn sort_files(files: &mut [String], reverse: bool) {
+    files.sort_by(|a, b| match (a == "-", b == "-") {
+        (true, true) => std::cmp::Ordering::Equal,
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        (false, false) if reverse => b.as_bytes().cmp(a.as_bytes()),
+        (false, false) => a.as_bytes().cmp(b.as_bytes()),
+    });
+}
+
+/* ディレクトリをその下の通常ファイルに置き換える(-r)。名前順に並べ、シンボリックリンクのループは飛ばす。
+読めなかったディレクトリとループはerrorsに足し、実行するときにreport_errorで報告する */
+fn expand_dirs(
+    files: Vec<String>,
+    errors: &mut Vec<(String, HeadError)>,
+) -> Vec<String> {
+    let mut expanded = Vec::new();
+    let mut visited = HashSet::new();
+    for filename in files {
+        if filename != "-" && Path::new(&filename).is_dir() {
+            walk_dir(
+                Path::new(&filename),
+                &mut visited,
+                &mut expanded,
+                errors,
+            );
+        } else {
+            expanded.push(filename);
+        }
+    }
+    expanded
+}
+
+fn walk_dir(
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<String>,
+    errors: &mut Vec<(String, HeadError)>,
+) {
+    let dirname = dir.to_string_lossy().into_owned();
+    match fs::canonicalize(dir) {
+        Ok(real) => {
+            if !visited.insert(real) {
+                errors.push((
+                    dirname.clone(),
+                    HeadError::DirectoryLoop(dirname),
+                ));
+                return;
+            }
+        }
+        Err(e) => {
+            errors.push((dirname, HeadError::Io(e)));
+            return;
+        }
+    }
+
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            errors.push((dirname, HeadError::Io(e)));
+            return;
+        }
+    };
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            walk_dir(&path, visited, files, errors);
+        } else if path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/* "10%"や"10"を受け付ける。0より大きく100以下 */
+fn parse_percent(val: &str) -> MyResult<f64> {
+    match val.strip_suffix('%').unwrap_or(val).parse::<f64>() {
+        Ok(num) if num > 0.0 && num <= 100.0 => Ok(num),
+        _ => Err(HeadError::InvalidArgument(format!(
+            "illegal percentage -- {}",
+            val
+        ))),
+    }
+}
+
+fn parse_jobs(val: &str) -> MyResult<usize> {
+    match val.parse::<usize>() {
+        Ok(num) if num > 0 => Ok(num),
+        _ => Err(HeadError::InvalidArgument(format!(
+            "invalid number of jobs -- {}",
+            val
+        ))),
+    }
+}
+
+/* 先頭に'-'が付いていれば末尾からの数として扱う。-0は全て表示 */
+fn parse_count(val: &str) -> MyResult<Count> {
+    match val.strip_prefix('-') {
+        Some(num) => num
+            .parse::<usize>()
+            .map(Count::AllButLast)
+            .map_err(|_| HeadError::InvalidLineCount(val.to_string())),
+        None => parse_positive_int(val)
+            .map(Count::First)
+            .map_err(|_| HeadError::InvalidLineCount(val.to_string())),
+    }
+}
+
+/* --tail-lines/--tail-bytesの数。-n -N/-c -Nと同じく末尾を除く指定になる */
+fn parse_tail_lines(val: &str) -> MyResult<Count> {
+    val.parse::<usize>()
+        .map(Count::AllButLast)
+        .map_err(|_| HeadError::InvalidLineCount(val.to_string()))
+}
+
+fn parse_tail_bytes(val: &str) -> MyResult<Count> {
+    parse_size(val)
+        .map(Count::AllButLast)
+        .map_err(|_| HeadError::InvalidByteCount(val.to_string()))
+}
+
+/* バイト数用。1K、5M、2GのようなGNU headと同じ単位付きの指定を受け付ける */
+fn parse_byte_count(val: &str) -> MyResult<Count> {
+    match val.strip_prefix('-') {
+        Some(size) => parse_size(size)
+            .map(Count::AllButLast)
+            .map_err(|_| HeadError::InvalidByteCount(val.to_string())),
+        None => match parse_size(val) {
+            Ok(num) if num > 0 => Ok(Count::First(num)),
+            _ => Err(HeadError::InvalidByteCount(val.to_string())),
+        },
+    }
+}
+
+/* -c用。START-ENDの形なら範囲、そうでなければparse_byte_countと同じ */
+fn parse_byte_spec(val: &str) -> MyResult<ByteSpec> {
+    match val.split_once('-') {
+        Some((start, end)) if !start.is_empty() => {
+            match (parse_size(start), parse_size(end)) {
+                (Ok(start), Ok(end)) if 0 < start && start <= end => {
+                    Ok(ByteSpec::Range(start, end))
+                }
+                _ => Err(HeadError::InvalidByteCount(val.to_string())),
+            }
+        }
+        _ => parse_byte_count(val).map(ByteSpec::Count),
+    }
+}
+
+/* 数字部分と単位部分に分けて掛け合わせる。b=512、K=KiB=1024、KB=kB=1000 (M以降も同様) */
+fn parse_size(val: &str) -> MyResult<usize> {
+    let split = val.find(|c: char| !c.is_ascii_digit()).unwrap_or(val.len());
+    let (num, suffix) = val.split_at(split);
+
+    let multiplier = match suffix {
+        "" => Some(1),
+        "b" => Some(512),
+        "kB" => Some(1000),
+        _ => {
+            let mut chars = suffix.chars();
+            let power = chars
+                .next()
+                .and_then(|c| "KMGTPE".find(c))
+                .map(|i| i as u32 + 1);
+            let base = match chars.as_str() {
+                "" | "iB" => Some(1024usize),
+                "B" => Some(1000usize),
+                _ => None,
+            };
+            power
+                .zip(base)
+                .and_then(|(power, base)| base.checked_pow(power))
+        }
+    };
+
+    num.parse::<usize>()
+        .ok()
+        .zip(multiplier)
+        .and_then(|(num, multiplier)| num.checked_mul(multiplier))
+        .ok_or_else(|| HeadError::InvalidByteCount(val.to_string()))
+}
+
+#[test]
+fn test_parse_positive_int() {
+    // 3は正の整数なのでOK
+    let res = parse_positive_int("3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), 3);
+
+    // 数字でない文字列の場合はエラー
+    let res = parse_positive_int("foo");
+    assert!(res.is_err());
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "illegal line count -- foo".to_string()
+    );
+
+    // 0の場合もエラー
+    let res = parse_positive_int("0");
+    assert!(res.is_err());
+    assert!(matches!(
+        res.unwrap_err(),
+        HeadError::InvalidLineCount(val) if val == "0"
+    ));
+
+    // 符号の+は受け付ける
+    assert_eq!(parse_positive_int("+5").unwrap(), 5);
+
+    // usizeに収まらない場合もエラー
+    assert!(parse_positive_int("99999999999999999999999").is_err());
+}
+
+#[test]
+fn test_parse_int() {
+    assert_eq!(parse_int("5").unwrap(), 5);
+    assert_eq!(parse_int("-5").unwrap(), -5);
+    assert_eq!(parse_int("+5").unwrap(), 5);
+    assert_eq!(parse_int("0").unwrap(), 0);
+    assert_eq!(parse_int("-9223372036854775808").unwrap(), i64::MIN);
+
+    for bad in ["", "-", "foo", "5x", "--5", "9223372036854775808"] {
+        assert!(matches!(
+            parse_int(bad),
+            Err(HeadError::InvalidLineCount(val)) if val == bad
+        ));
+    }
+}
+
+#[test]
+fn test_parse_count() {
+    assert_eq!(parse_count("3").unwrap(), Count::First(3));
+    assert_eq!(parse_count("-3").unwrap(), Count::AllButLast(3));
+    assert_eq!(parse_count("-0").unwrap(), Count::AllButLast(0));
+
+    // エラーには入力値がそのまま含まれる
+    let res = parse_count("-foo");
+    assert!(res.is_err());
+    assert!(matches!(
+        res.unwrap_err(),
+        HeadError::InvalidLineCount(val) if val == "-foo"
+    ));
+
+    assert!(parse_count("-").is_err());
+    assert!(parse_count("0").is_err());
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("20").unwrap(), 20);
+    assert_eq!(parse_size("2b").unwrap(), 1024);
+    assert_eq!(parse_size("1K").unwrap(), 1024);
+    assert_eq!(parse_size("1KiB").unwrap(), 1024);
+    assert_eq!(parse_size("1kB").unwrap(), 1000);
+    assert_eq!(parse_size("1KB").unwrap(), 1000);
+    assert_eq!(parse_size("5M").unwrap(), 5 * 1024 * 1024);
+    assert_eq!(parse_size("5MB").unwrap(), 5_000_000);
+    assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+
+    // 不明な単位や数字のない指定はエラー
+    let res = parse_size("1Q");
+    assert!(res.is_err());
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "illegal byte count -- 1Q".to_string()
+    );
+    assert!(parse_size("K").is_err());
+    assert!(parse_size("1KiBB").is_err());
+    assert!(parse_size("99999999999E").is_err());
+}
+
+#[test]
+fn test_parse_byte_count() {
+    assert_eq!(parse_byte_count("1K").unwrap(), Count::First(1024));
+    assert_eq!(parse_byte_count("-1K").unwrap(), Count::AllButLast(1024));
+    assert!(parse_byte_count("0K").is_err());
+    assert!(matches!(
+        parse_byte_count("-1Q").unwrap_err(),
+        HeadError::InvalidByteCount(val) if val == "-1Q"
+    ));
+}
+
+#[test]
+fn test_head_lines() {
+    let text = "one\ntwo\r\nthree";
+    assert_eq!(
+        head_lines(text.as_bytes(), 2).unwrap(),
+        vec!["one\n".to_string(), "two\r\n".to_string()]
+    );
+    // 最後の行に改行がなくても1行として数える
+    assert_eq!(head_lines(text.as_bytes(), 5).unwrap().len(), 3);
+    assert!(head_lines("".as_bytes(), 5).unwrap().is_empty());
+
+    let res = head_lines_delimited("a\0b\0c".as_bytes(), 2, b'\0');
+    assert_eq!(res.unwrap(), vec!["a\0".to_string(), "b\0".to_string()]);
+}
+
+#[test]
+fn test_head_bytes() {
+    let data: &[u8] = &[0xe3, 0x81, 0x82, b'a', b'b'];
+    assert_eq!(head_bytes(data, 4).unwrap(), data[..4].to_vec());
+    assert_eq!(head_bytes(data, 100).unwrap(), data.to_vec());
+    assert!(head_bytes(data, 0).unwrap().is_empty());
+}
+
+#[test]
+fn test_print_bytes_streams() {
+    /* 一度に書く量を記録して、全体を溜めてから書いていないことを確かめる */
+    struct Chunks {
+        total: usize,
+        largest: usize,
+    }
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.total += buf.len();
+            self.largest = self.largest.max(buf.len());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let config = Config::default();
+    let mut input = BufReader::new(io::repeat(b'x').take(1 << 20));
+    let mut out = Chunks {
+        total: 0,
+        largest: 0,
+    };
+    let spec = ByteSpec::Count(Count::First(10 << 30));
+    print_bytes(&config, "-", spec, &mut input, None, &mut out).unwrap();
+    assert_eq!(out.total, 1 << 20);
+    assert!(out.largest <= 64 * 1024);
+}
+
+#[test]
+fn test_peek_head() {
+    let (head, mut reader) = peek_head("abc\ndef\n".as_bytes(), 5).unwrap();
+    assert_eq!(head, b"abc\nd");
+    let mut all = String::new();
+    reader.read_to_string(&mut all).unwrap();
+    assert_eq!(all, "abc\ndef\n");
+
+    // 短い入力でも、読めた分だけを返す
+    let (head, reader) = peek_head("a\nb".as_bytes(), 100).unwrap();
+    assert_eq!(head, b"a\nb");
+    let lines: Vec<_> = reader.lines().map(Result::unwrap).collect();
+    assert_eq!(lines, ["a", "b"]);
+}
+
+#[test]
+fn test_head_iter() {
+    let mut iter = head_iter("a\nb\nc\n".as_bytes(), 2);
+    assert_eq!(iter.next().unwrap().unwrap(), "a\n");
+    assert_eq!(iter.next().unwrap().unwrap(), "b\n");
+    assert!(iter.next().is_none());
+
+    // 終わりのない入力でもn行で止まる
+    let endless = BufReader::new(io::repeat(b'\n'));
+    assert_eq!(head_iter(endless, 3).count(), 3);
+}
+
+#[test]
+fn test_config_builder() {
+    let config = Config::builder().build();
+    assert_eq!(config.files, vec!["-".to_string()]);
+    assert_eq!(config.lines, Count::First(10));
+    assert_eq!(config.bytes, None);
+
+    let config = Config::builder()
+        .files(["a.txt", "b.txt"])
+        .lines(Count::AllButLast(3))
+        .bytes(20)
+        .quiet(true)
+        .build();
+    assert_eq!(config.files, vec!["a.txt".to_string(), "b.txt".to_string()]);
+    assert_eq!(config.lines, Count::AllButLast(3));
+    assert_eq!(config.bytes, Some(ByteSpec::Count(Count::First(20))));
+    assert!(config.quiet);
+}
+
+#[test]
+fn test_get_args_from() {
+    let config = get_args_from(["headr", "-n", "3", "file"]).unwrap();
+    assert_eq!(config.files, vec!["file".to_string()]);
+    assert_eq!(config.lines, Count::First(3));
+    assert_eq!(config.bytes, None);
+
+    let config =
+        get_args_from(["headr", "-c", "-1K", "-qz", "a", "b"]).unwrap();
+    assert_eq!(config.files, vec!["a".to_string(), "b".to_string()]);
+    assert_eq!(config.bytes, Some(ByteSpec::Count(Count::AllButLast(1024))));
+    assert!(config.quiet);
+    assert!(config.zero_terminated);
+    assert_eq!(config.delimiter(), b'\0');
+
+    let config = get_args_from(["headr", "-d", ";"]).unwrap();
+    assert_eq!(config.delimiter(), b';');
+    assert!(get_args_from(["headr", "-d", "ab"]).is_err());
+    assert!(get_args_from(["headr", "-d", "é"]).is_err());
+
+    let config = get_args_from(["headr"]).unwrap();
+    assert_eq!(config.files, vec!["-".to_string()]);
+    assert_eq!(config.lines, Count::First(10));
+
+    assert!(matches!(
+        get_args_from(["headr", "-n", "x"]),
+        Err(HeadError::InvalidLineCount(val)) if val == "x"
+    ));
+
+    // 組み合わせられない指定もプロセスを終了させずにエラーで返す
+    assert!(matches!(
+        get_args_from(["headr", "-n", "1", "-c", "2"]),
+        Err(HeadError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        get_args_from(["headr", "--files0-from", "-", "a"]),
+        Err(HeadError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_expand_globs() {
+    let files = vec![
+        "-".to_string(),
+        "tests/inputs/t*.txt".to_string(),
+        "missing.txt".to_string(),
+    ];
+    assert_eq!(
+        expand_globs(files).unwrap(),
+        vec![
+            "-",
+            "tests/inputs/ten.txt",
+            "tests/inputs/three.txt",
+            "tests/inputs/two.txt",
+            "missing.txt"
+        ]
+    );
+
+    let res = expand_globs(vec!["tests/inputs/*.nothing".to_string()]);
+    assert!(matches!(res, Err(HeadError::InvalidArgument(_))));
+}
+
+#[test]
+fn test_expand_wildcards_default() {
+    let config = get_args_from(["headr", "tests/inputs/t*.txt"]);
+    #[cfg(windows)]
+    assert_eq!(config.unwrap().files.len(), 3);
+    #[cfg(unix)]
+    assert_eq!(config.unwrap().files, vec!["tests/inputs/t*.txt"]);
+
+    let config = get_args_from(["headr", "--glob", "tests/inputs/t*.txt"]);
+    assert_eq!(config.unwrap().files.len(), 3);
+    let config = get_args_from(["headr", "--no-glob", "tests/inputs/t*.txt"]);
+    assert_eq!(config.unwrap().files, vec!["tests/inputs/t*.txt"]);
+}
+
+#[test]
+fn test_head_chars() {
+    assert_eq!(head_chars("Öne line".as_bytes(), 3).unwrap(), "Öne");
+    assert_eq!(head_chars("日本語テキスト".as_bytes(), 2).unwrap(), "日本");
+    assert_eq!(head_chars("🦀🦀".as_bytes(), 1).unwrap(), "🦀");
+    assert_eq!(head_chars("ab".as_bytes(), 10).unwrap(), "ab");
+    assert_eq!(head_chars("".as_bytes(), 10).unwrap(), "");
+
+    // n文字を読んだ後の続きはreaderに残る
+    let mut reader = "äbc".as_bytes();
+    assert_eq!(head_chars(&mut reader, 2).unwrap(), "äb");
+    assert_eq!(reader, b"c");
+}
+
+#[test]
+fn test_parse_percent() {
+    assert_eq!(parse_percent("10%").unwrap(), 10.0);
+    assert_eq!(parse_percent("2.5").unwrap(), 2.5);
+    assert_eq!(parse_percent("100%").unwrap(), 100.0);
+    assert!(parse_percent("0%").is_err());
+    assert!(parse_percent("101%").is_err());
+    assert!(parse_percent("ten%").is_err());
+}
+
+#[test]
+fn test_run_with() {
+    let config = Config::builder()
+        .files(["tests/inputs/ten.txt"])
+        .lines(2)
+        .build();
+    let mut out = Vec::new();
+    run_with(&config, &mut out).unwrap();
+    assert_eq!(out, b"one\ntwo\n");
+
+    let config = Config::builder()
+        .files(["tests/inputs/one.txt", "tests/inputs/two.txt"])
+        .bytes(1)
+        .build();
+    let mut out = Vec::new();
+    run_with(&config, &mut out).unwrap();
+    assert_eq!(
+        out,
+        b"==> tests/inputs/one.txt <==\n\xc3\n==> tests/inputs/two.txt <==\nT"
+    );
+}
+
+#[test]
+fn test_opener() {
+    /* ディスクに無い名前でも、openerが返す内容を読む */
+    let files: HashMap<&str, &str> =
+        [("a", "1\n2\n3\n"), ("b", "x\ny\n")].into_iter().collect();
+    let opener = move |filename: &str| -> MyResult<Box<dyn BufRead>> {
+        match files.get(filename) {
+            Some(text) => Ok(Box::new(text.as_bytes())),
+            None => Err(HeadError::FileNotFound(filename.to_string())),
+        }
+    };
+    for jobs in [1, 2] {
+        let config = Config::builder()
+            .files(["a", "missing", "b"])
+            .lines(2)
+            .jobs(jobs)
+            .opener(opener.clone())
+            .build();
+        let mut out = Vec::new();
+        assert!(matches!(
+            run_with(&config, &mut out),
+            Err(HeadError::FilesFailed(1))
+        ));
+        assert_eq!(out, b"==> a <==\n1\n2\n\n==> b <==\nx\ny\n");
+    }
+
+    let config = Config::builder().files(["missing"]).build();
+    assert!(matches!(
+        run_with_opener(config, opener),
+        Err(HeadError::FilesFailed(1))
+    ));
+}
+
+#[test]
+fn test_head() {
+    let head = Head::new(
+        Config::builder()
+            .files(["tests/inputs/ten.txt", "tests/inputs/missing.txt"])
+            .lines(2)
+            .build(),
+    );
+    let mut out = Vec::new();
+    assert!(matches!(
+        head.run_to(&mut out),
+        Err(HeadError::FilesFailed(1))
+    ));
+    assert_eq!(out, b"==> tests/inputs/ten.txt <==\none\ntwo\n");
+
+    let results = head.collect();
+    assert_eq!(results[0].1.as_ref().unwrap(), b"one\ntwo\n");
+    assert!(results[1].1.is_err());
+}
+
+#[test]
+fn test_expand_path() {
+    let home = std::env::var("HOME").unwrap();
+    assert_eq!(expand_path("-").unwrap(), "-");
+    assert_eq!(expand_path("~").unwrap(), home);
+    assert_eq!(expand_path("~/foo").unwrap(), format!("{}/foo", home));
+    assert_eq!(expand_path("$HOME/foo").unwrap(), format!("{}/foo", home));
+    assert_eq!(expand_path("${HOME}foo").unwrap(), format!("{}foo", home));
+    assert_eq!(expand_path("a~/$/b$").unwrap(), "a~/$/b$");
+    #[cfg(unix)]
+    assert_eq!(
+        expand_path("~root/x").unwrap(),
+        format!("{}/x", user_home("root").unwrap())
+    );
+
+    for bad in [
+        "$HEADR_NO_SUCH_VARIABLE/x",
+        "${HEADR_NO_SUCH_VARIABLE}",
+        "${HOME",
+        "${}",
+        "~headr_no_such_user/x",
+    ] {
+        assert!(
+            matches!(expand_path(bad), Err(HeadError::InvalidArgument(_))),
+            "{}",
+            bad
+        );
+    }
+}
+
+#[test]
+fn test_head_fields() {
+    assert_eq!(head_fields("a\tb\tc\n", 2, '\t'), "a\tb\n");
+    assert_eq!(head_fields("a\tb\tc\r\n", 1, '\t'), "a\r\n");
+    assert_eq!(head_fields("a,b,c", 2, ','), "a,b");
+    assert_eq!(head_fields("a,b,c\0", 5, ','), "a,b,c\0");
+    assert_eq!(head_fields("a\tb\n", 2, '\t'), "a\tb\n");
+
+    assert_eq!(parse_field_sep("\\t").unwrap(), '\t');
+    assert_eq!(parse_field_sep(";").unwrap(), ';');
+    assert!(parse_field_sep("").is_err());
+    assert!(parse_field_sep("ab").is_err());
+}
+
+#[test]
+fn test_wrap_line() {
+    assert_eq!(wrap_line("abcdef\n", 3, b'\n'), "abc\ndef\n");
+    assert_eq!(wrap_line("abc\n", 3, b'\n'), "abc\n");
+    // マルチバイト文字の途中では切らない
+    assert_eq!(wrap_line("aéü€😀z\r\n", 2, b'\n'), "aé\nü€\n😀z\r\n");
+    assert_eq!(wrap_line("aé€\0", 2, b'\0'), "aé\n€\0");
+    assert_eq!(wrap_line("abcd", 3, b'\n'), "abc\nd");
+}
+
+#[test]
+fn test_utf8_missing() {
+    let text = "aé€😀".as_bytes(); // 1、2、3、4バイトの文字
+    let missing: Vec<usize> = (0..=text.len())
+        .map(|len| utf8_missing(&text[..len]))
+        .collect();
+    assert_eq!(missing, [0, 0, 1, 0, 2, 1, 0, 3, 2, 1, 0]);
+    assert_eq!(utf8_missing(b"\x80\x80\x80\x80"), 0);
+    assert_eq!(utf8_missing(b"\xff"), 0);
+}
+
+#[cfg(feature = "clipboard")]
+#[test]
+fn test_clipboard_input() {
+    let mut text = String::new();
+    clipboard_input(Ok("one\ntwo\n".to_string()))
+        .unwrap()
+        .reader
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, "one\ntwo\n");
+
+    let mut input =
+        clipboard_input(Err(arboard::Error::ContentNotAvailable)).unwrap();
+    assert!(input.reader.fill_buf().unwrap().is_empty());
+    assert!(
+        clipboard_input(Err(arboard::Error::ClipboardNotSupported)).is_err()
+    );
+}
+
+#[test]
+fn test_dedup_stdin() {
+    let mut files: Vec<String> = ["-", "a", "-", "b", "-"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    dedup_stdin(&mut files);
+    assert_eq!(files, vec!["-", "a", "b"]);
+}
+
+#[test]
+fn test_absolute_path() {
+    let ten = absolute_path("tests/inputs/ten.txt");
+    assert!(Path::new(&*ten).is_absolute());
+    assert!(ten.ends_with("ten.txt"));
+    // 絶対パスにできなければ元の名前のまま
+    assert_eq!(absolute_path("no/such/file"), "no/such/file");
+    assert_eq!(absolute_path("clip:"), "clip:");
+}
+
+#[test]
+fn test_sort_files() {
+    let mut files: Vec<String> = ["b", "B", "-", "a/c", "a"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    sort_files(&mut files, false);
+    assert_eq!(files, vec!["-", "B", "a", "a/c", "b"]);
+
+    sort_files(&mut files, true);
+    assert_eq!(files, vec!["-", "b", "a/c", "a", "B"]);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn test_parse_defaults() {
+    let defaults = parse_defaults(
+        "lines = 20\nbytes = \"-1K\"\ncolor = \"always\"\nquiet = true\n",
+    )
+    .unwrap();
+    assert_eq!(
+        defaults,
+        Defaults {
+            lines: Some(Count::First(20)),
+            bytes: Some(ByteSpec::Count(Count::AllButLast(1024))),
+            quiet: Some(true),
+            color: Some(ColorChoice::Always),
+            ..Defaults::default()
+        }
+    );
+
+    assert_eq!(parse_defaults("").unwrap(), Defaults::default());
+    assert!(parse_defaults("lines = \"x\"").is_err());
+    assert!(parse_defaults("color = \"blue\"").is_err());
+    assert!(parse_defaults("unknown = 1").is_err());
+    assert!(parse_defaults("lines = [").is_err());
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn test_parse_args_with_defaults() {
+    let defaults = parse_defaults(
+        "lines = 20\ncolor = \"always\"\nverbose = true\njobs = 4\n",
+    )
+    .unwrap();
+
+    let config = parse_args(["headr"], &defaults).unwrap();
+    assert_eq!(config.lines, Count::First(20));
+    assert_eq!(config.color, ColorChoice::Always);
+    assert!(config.verbose);
+    assert_eq!(config.jobs, 4);
+
+    // コマンドラインの指定が優先される
+    let config = parse_args(
+        ["headr", "-n", "3", "--color", "never", "-q", "-j", "1"],
+        &defaults,
+    )
+    .unwrap();
+    assert_eq!(config.lines, Count::First(3));
+    assert_eq!(config.color, ColorChoice::Never);
+    assert!(config.quiet);
+    assert!(!config.verbose);
+    assert_eq!(config.jobs, 1);
+
+    // -cを指定したら設定ファイルのlinesは使わない
+    let defaults = parse_defaults("bytes = 5").unwrap();
+    let config = parse_args(["headr", "-n", "2"], &defaults).unwrap();
+    assert_eq!(config.lines, Count::First(2));
+    assert_eq!(config.bytes, None);
+}
+
+#[test]
+fn test_parse_line_ranges() {
+    assert_eq!(parse_line_ranges("5").unwrap(), vec![(5, 5)]);
+    assert_eq!(
+        get_args_from(["headr", "--line", "42"])
+            .unwrap()
+            .line_ranges,
+        Some(vec![(42, 42)])
+    );
+    assert_eq!(
+        parse_line_ranges("20-22,1-3,10").unwrap(),
+        vec![(1, 3), (10, 10), (20, 22)]
+    );
+    assert_eq!(parse_line_ranges("1-5,3-8,9").unwrap(), vec![(1, 9)]);
+    for bad in ["", "0", "3-1", "1-", "-2", "1,,2", "1-2-3"] {
+        assert!(parse_line_ranges(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_byte_spec() {
+    assert_eq!(parse_byte_spec("10-20").unwrap(), ByteSpec::Range(10, 20));
+    assert_eq!(parse_byte_spec("1-1K").unwrap(), ByteSpec::Range(1, 1024));
+    assert_eq!(parse_byte_spec("3-3").unwrap(), ByteSpec::Range(3, 3));
+    assert_eq!(
+        parse_byte_spec("5").unwrap(),
+        ByteSpec::Count(Count::First(5))
+    );
+    assert_eq!(
+        parse_byte_spec("-5").unwrap(),
+        ByteSpec::Count(Count::AllButLast(5))
+    );
+
+    for bad in ["0-3", "5-3", "1-", "1-x", "1-2-3"] {
+        assert!(matches!(
+            parse_byte_spec(bad),
+            Err(HeadError::InvalidByteCount(val)) if val == bad
+        ));
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut stats = Stats::default();
+    stats.add("Öne\ntw".as_bytes(), b'\n');
+    assert_eq!(stats.num_lines(), 2);
+    assert_eq!(stats.chars, 6);
+    assert_eq!(stats.bytes, 7);
+
+    stats.add(b"o\n", b'\n');
+    assert_eq!(stats.num_lines(), 2);
+    assert_eq!(stats.bytes, 9);
+
+    stats.add(b"", b'\n');
+    assert_eq!(stats.num_lines(), 2);
+}
+
+#[cfg(feature = "graphemes")]
+#[test]
+fn test_stats_graphemes() {
+    let mut stats = Stats::default();
+    // 結合文字が次の書き込みにあっても、eと合わせて1つと数える
+    stats.add_graphemes(b"ae");
+    assert_eq!(stats.num_graphemes(), 2);
+    stats.add_graphemes("\u{301}\n".as_bytes());
+    assert_eq!(stats.num_graphemes(), 3);
+
+    // 途中で切れたマルチバイト文字は、続きが来てから1つと数える
+    let mut stats = Stats::default();
+    let text = "あい".as_bytes();
+    stats.add_graphemes(&text[..4]);
+    stats.add_graphemes(&text[4..]);
+    assert_eq!(stats.num_graphemes(), 2);
+
+    // 不正なバイトは1バイトずつ数える
+    let mut stats = Stats::default();
+    stats.add_graphemes(b"a\xff\xfeb");
+    assert_eq!(stats.num_graphemes(), 4);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_serde_round_trip() {
+    let config = Config::builder()
+        .files(["a", "b"])
+        .lines(Count::AllButLast(2))
+        .color(ColorChoice::Never)
+        .build();
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains("\"bytes\":null"));
+
+    let loaded: Config = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.files, config.files);
+    assert_eq!(loaded.lines, Count::AllButLast(2));
+    assert_eq!(loaded.bytes, None);
+    assert_eq!(loaded.color, ColorChoice::Never);
+
+    // 足りない項目はデフォルト値になる
+    let loaded: Config = serde_json::from_str("{\"jobs\":4}").unwrap();
+    assert_eq!(loaded.jobs, 4);
+    assert_eq!(loaded.lines, Count::First(10));
 }
 
-pub fn get_args() -> MyResult<Config> {
-    let matches = App::new("headr")
-        .version("0.1.0")
-        .author("yo1956")
-        .about("Rust head")
-        .arg(
-            Arg::with_name("files")
-                .value_name("FILES")
-                .help("The files to print")
-                .multiple(true)
-                .default_value("-"),
+#[test]
+fn test_looks_binary() {
+    assert!(!looks_binary(b"", b'\n'));
+    assert!(!looks_binary(b"one\ntwo\r\n\tthree\n", b'\n'));
+    assert!(!looks_binary("Öne line\n".as_bytes(), b'\n'));
+    assert!(!looks_binary(b"\x1b[1mbold\x1b[0m\n", b'\n'));
+    assert!(looks_binary(b"abc\0def", b'\n'));
+    assert!(looks_binary(b"\x01\x02\x03abcd", b'\n'));
+
+    // -zのときはNULが区切り文字なのでバイナリとはみなさない
+    assert!(!looks_binary(b"one\0two\0", b'\0'));
+}
+
+#[test]
+fn test_head_words() {
+    let text = "  one two\n\tthree  four\n";
+    assert_eq!(head_words(text.as_bytes(), 0).unwrap(), b"");
+    assert_eq!(head_words(text.as_bytes(), 1).unwrap(), b"  one");
+    assert_eq!(
+        head_words(text.as_bytes(), 3).unwrap(),
+        b"  one two\n\tthree"
+    );
+    assert_eq!(head_words(text.as_bytes(), 10).unwrap(), text.as_bytes());
+
+    // n語目の後ろは読まずに残る
+    let mut reader = io::Cursor::new("Öne line");
+    assert_eq!(head_words(&mut reader, 1).unwrap(), "Öne".as_bytes());
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, " line");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_parse_regex() {
+    assert!(parse_regex("^#").unwrap().is_match("# title"));
+    assert!(matches!(
+        parse_regex("("),
+        Err(HeadError::InvalidArgument(msg))
+            if msg.starts_with("invalid regular expression -- (")
+    ));
+}
+
+#[test]
+fn test_run_collect() {
+    let config = Config::builder()
+        .files([
+            "tests/inputs/ten.txt",
+            "missing.txt",
+            "tests/inputs/two.txt",
+        ])
+        .lines(1)
+        .build();
+    let results = run_collect(&config);
+    assert_eq!(results.len(), 3);
+
+    assert_eq!(results[0].0, "tests/inputs/ten.txt");
+    assert_eq!(results[0].1.as_ref().unwrap(), b"one\n");
+    assert_eq!(results[1].0, "missing.txt");
+    assert!(matches!(results[1].1, Err(HeadError::FileNotFound(_))));
+    assert_eq!(results[2].1.as_ref().unwrap(), b"Two lines.\n");
+}
+
+#[test]
+fn test_hex_dump() {
+    let mut out = Vec::new();
+    let mut dump = HexDump::new(&mut out);
+    dump.write_all(b"\x7fELF\x02\x01\x01\x00").unwrap();
+    dump.write_all(b"\x00\x00\x00\x00\x00\x00\x00\x00ab\n")
+        .unwrap();
+    dump.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  \
+         |.ELF............|\n\
+         00000010  61 62 0a                                          |ab.|\n\
+         00000013\n"
+    );
+
+    let mut out = Vec::new();
+    HexDump::new(&mut out).finish().unwrap();
+    assert!(out.is_empty());
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn test_decode() {
+    let input = Input {
+        reader: Box::new(&b"caf\xe9\n"[..]),
+        size: Some(5),
+    };
+    let mut input = decode(input, Some(parse_encoding("latin1").unwrap()));
+    assert_eq!(input.size, None);
+    let mut text = String::new();
+    input.reader.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "café\n");
+
+    let input = Input {
+        reader: Box::new(&b"\x93\xfa\x96\x7b"[..]),
+        size: None,
+    };
+    let mut input = decode(input, Some(parse_encoding("Shift_JIS").unwrap()));
+    let mut text = String::new();
+    input.reader.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "日本");
+
+    assert!(matches!(
+        parse_encoding("klingon"),
+        Err(HeadError::InvalidArgument(msg)) if msg == "unknown encoding -- klingon"
+    ));
+}
+
+#[cfg(feature = "graphemes")]
+#[test]
+fn test_head_graphemes() {
+    // 国旗の絵文字は2つの地域指示記号、éはeと結合アクセントからなる
+    let text = "\u{1f1ef}\u{1f1f5}e\u{301}a\r\nb";
+    assert_eq!(head_graphemes(text.as_bytes(), 0).unwrap(), "");
+    assert_eq!(
+        head_graphemes(text.as_bytes(), 1).unwrap(),
+        "\u{1f1ef}\u{1f1f5}"
+    );
+    assert_eq!(
+        head_graphemes(text.as_bytes(), 2).unwrap(),
+        "\u{1f1ef}\u{1f1f5}e\u{301}"
+    );
+    assert_eq!(
+        head_graphemes(text.as_bytes(), 4).unwrap(),
+        "\u{1f1ef}\u{1f1f5}e\u{301}a\r\n"
+    );
+    assert_eq!(head_graphemes(text.as_bytes(), 10).unwrap(), text);
+}
+
+#[test]
+fn test_sink_writer() {
+    /* 受け取った行だけを記録するsink */
+    #[derive(Default)]
+    struct Lines(Vec<String>);
+
+    impl HeadSink for Lines {
+        fn begin_file(&mut self, _name: &str) -> MyResult<()> {
+            Ok(())
+        }
+
+        fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()> {
+            self.0
+                .push(format!("{}: {}", n, String::from_utf8_lossy(line)));
+            Ok(())
+        }
+
+        fn write_bytes(&mut self, _bytes: &[u8]) -> MyResult<()> {
+            unreachable!()
+        }
+
+        fn end_file(&mut self, _name: &str, _: Option<usize>) -> MyResult<()> {
+            Ok(())
+        }
+
+        fn fail_file(&mut self, _name: &str, _e: &HeadError) -> MyResult<()> {
+            Ok(())
+        }
+
+        fn finish(&mut self) -> MyResult<()> {
+            Ok(())
+        }
+    }
+
+    let split = |config: &Config, chunks: &[&[u8]]| {
+        let mut sink = Lines::default();
+        let mut writer = SinkWriter::new(config, &mut sink);
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        sink.0
+    };
+    let config = Config::default();
+    assert_eq!(split(&config, &[b"one\ntwo\n"]), ["1: one", "2: two"]);
+    assert_eq!(
+        split(&config, &[b"one\n\ntwo"]),
+        ["1: one", "2: ", "3: two"]
+    );
+    assert_eq!(split(&config, &[b"\n"]), ["1: "]);
+    assert!(split(&config, &[b""]).is_empty());
+    // 行の途中で切れて書かれても、区切り文字ごとに1行として渡す
+    assert_eq!(
+        split(&config, &[b"o", b"ne\ntw", b"o\nth", b"", b"ree"]),
+        ["1: one", "2: two", "3: three"]
+    );
+    let config = Config::builder().zero_terminated(true).build();
+    assert_eq!(split(&config, &[b"a\0b", b"\0"]), ["1: a", "2: b"]);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_run_async() {
+    /* 1回だけ応答するHTTPサーバー。404なら本文なしで返す */
+    let serve = |status: &'static str, body: &'static str| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        format!("http://{}/file.txt", addr)
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let compare = |build: &dyn Fn(ConfigBuilder) -> ConfigBuilder| {
+        let files = [
+            serve("200 OK", "a\nb\nc\nd\n"),
+            "tests/inputs/ten.txt".to_string(),
+            serve("404 Not Found", ""),
+            "tests/inputs/missing.txt".to_string(),
+            serve("200 OK", "e\nf"),
+        ];
+        let sync_path = dir.path().join("sync.txt");
+        let config = build(Config::builder())
+            .files(["tests/inputs/ten.txt", "tests/inputs/missing.txt"])
+            .lines(3)
+            .output(sync_path.to_str().unwrap())
+            .build();
+        assert!(matches!(run(config), Err(HeadError::FilesFailed(1))));
+
+        let async_path = dir.path().join("async.txt");
+        let config = build(Config::builder())
+            .files(&files)
+            .lines(3)
+            .output(async_path.to_str().unwrap())
+            .build();
+        assert!(matches!(
+            runtime.block_on(run_async(config)),
+            Err(HeadError::FilesFailed(2))
+        ));
+        (
+            fs::read(sync_path).unwrap(),
+            fs::read(async_path).unwrap(),
+            files,
         )
-        .arg(
-            Arg::with_name("lines")
-                .short("n")
-                .long("lines")
-                .value_name("LINES")
-                .help("Number of lines to print")
-                .default_value("10"),
+    };
+
+    // URLも引数の順番どおりに、1つのファイルと同じ書式で書き出す
+    let (sync, output, files) = compare(&|builder| builder);
+    let sync = String::from_utf8(sync).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "==> {} <==\na\nb\nc\n\n{}\n==> {} <==\ne\nf",
+            files[0], sync, files[4]
         )
-        .arg(
-            Arg::with_name("bytes")
-                .short("c")
-                .long("bytes")
-                .value_name("BYTES")
-                .help("Number of bytes to print")
-                .takes_value(true)
-                .conflicts_with("lines"),
+    );
+
+    #[cfg(feature = "json")]
+    {
+        let (_, output, files) =
+            compare(&|builder| builder.format(OutputFormat::Ndjson));
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert!(lines[0].contains(&format!(r#""file":"{}","n":1"#, files[0])));
+        assert!(
+            lines[6].contains(&format!(r#""file":"{}","error""#, files[2]))
+        );
+        assert!(lines[9].contains(r#""text":"f""#));
+    }
+
+    // ファイルごとに書き出すのでないものは、runと同じに動く
+    let path = dir.path().join("count.txt");
+    let config = Config::builder()
+        .files(["tests/inputs/ten.txt", "tests/inputs/one.txt"])
+        .count(true)
+        .output(path.to_str().unwrap())
+        .build();
+    runtime.block_on(run_async(config)).unwrap();
+    assert_eq!(
+        fs::read_to_string(path).unwrap(),
+        "10 tests/inputs/ten.txt\n1 tests/inputs/one.txt\n"
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_decompress_short_reads() {
+    /* パイプのように1回のreadで1バイトずつしか返さない */
+    struct Trickle(io::Cursor<Vec<u8>>);
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+    let trickle = |data: Vec<u8>| Input {
+        reader: Box::new(BufReader::new(Trickle(io::Cursor::new(data)))),
+        size: None,
+    };
+
+    let gz = fs::read("tests/inputs/ten.txt.gz").unwrap();
+    let mut text = String::new();
+    decompress("-", trickle(gz))
+        .unwrap()
+        .reader
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, fs::read_to_string("tests/inputs/ten.txt").unwrap());
+
+    let mut plain = Vec::new();
+    decompress("-", trickle(b"\x1fx".to_vec()))
+        .unwrap()
+        .reader
+        .read_to_end(&mut plain)
+        .unwrap();
+    assert_eq!(plain, b"\x1fx");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_decompress_dispatch() {
+    let read = |filename: &str, data: Vec<u8>| {
+        let input = Input {
+            reader: Box::new(io::Cursor::new(data)),
+            size: None,
+        };
+        let mut buffer = Vec::new();
+        decompress(filename, input)
+            .and_then(|mut input| Ok(input.reader.read_to_end(&mut buffer)?))
+            .map(|_| buffer)
+    };
+    let gz = fs::read("tests/inputs/ten.txt.gz").unwrap();
+    let ten = fs::read("tests/inputs/ten.txt").unwrap();
+
+    assert_eq!(read("ten.txt.gz", gz.clone()).unwrap(), ten);
+    assert_eq!(read("ten.txt", ten.clone()).unwrap(), ten);
+    // 拡張子が無ければマジックナンバーで決める
+    assert_eq!(read("ten", gz).unwrap(), ten);
+    // 拡張子で決めたものはgzipとして読むので、中身が違えばエラーになる
+    assert!(read("ten.txt.gz", ten).is_err());
+}
+
+#[test]
+fn test_log_events() {
+    use std::sync::Mutex;
+
+    static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    struct Capture;
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            EVENTS.lock().unwrap().push(record.args().to_string());
+        }
+        fn flush(&self) {}
+    }
+    log::set_logger(&Capture).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+
+    let config = get_args_from([
+        "headr",
+        "-n",
+        "1",
+        "tests/inputs/ten.txt",
+        "nothing.txt",
+    ])
+    .unwrap();
+    let _ = run_with(&config, &mut Vec::new());
+    // 他のテストも同時に記録するので、含まれているかだけを確かめる
+    let events = EVENTS.lock().unwrap();
+    for event in [
+        "files: [\"tests/inputs/ten.txt\", \"nothing.txt\"]",
+        "opening tests/inputs/ten.txt",
+        "tests/inputs/ten.txt: printed 1 lines, 4 bytes",
+        "nothing.txt: No such file or directory (os error 2)",
+    ] {
+        assert!(events.iter().any(|e| e == event), "{}", event);
+    }
+    // traceは既定の上限より細かいので記録されない
+    assert!(!events.iter().any(|e| e.starts_with("config: ")));
+}
+
+#[test]
+fn test_throttle() {
+    let start = std::time::Instant::now();
+    let mut input = throttle(
+        Input {
+            reader: Box::new(io::Cursor::new(vec![b'x'; 300])),
+            size: Some(300),
+        },
+        1000,
+    );
+    let mut buf = Vec::new();
+    input.reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), 300);
+    assert_eq!(input.size, None);
+    // 1000バイト/秒で300バイトなので、0.3秒はかかる
+    assert!(start.elapsed() >= std::time::Duration::from_millis(290));
+
+    assert_eq!(parse_limit_rate("1M").unwrap(), 1024 * 1024);
+    assert_eq!(
+        parse_limit_rate("0").unwrap_err().to_string(),
+        "illegal rate -- 0"
+    );
+}
+
+#[test]
+fn test_paragraphs() {
+    let text = "\na\nb\n\n\n\nc\n\n";
+    let found: Vec<_> = paragraphs(io::Cursor::new(text), b'\n')
+        .map(|paragraph| paragraph.unwrap())
+        .collect();
+    // 先頭と末尾の空行は段落にならない
+    assert_eq!(
+        found,
+        vec![
+            (1, vec![b"a\n".to_vec(), b"b\n".to_vec()]),
+            (3, vec![b"c\n".to_vec()])
+        ]
+    );
+}
+
+#[test]
+fn test_exclude_files() {
+    let files = vec![
+        "-".to_string(),
+        "src/app.js".to_string(),
+        "src/app.min.js".to_string(),
+        "vendor/lib.js".to_string(),
+    ];
+    assert_eq!(
+        exclude_files(files, ["*.min.js", "vendor/*", "-"].into_iter())
+            .unwrap(),
+        vec!["-".to_string(), "src/app.js".to_string()]
+    );
+
+    let res = exclude_files(vec![], ["["].into_iter());
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_count_lines() {
+    assert_eq!(count_lines(io::Cursor::new(""), b'\n').unwrap(), 0);
+    assert_eq!(count_lines(io::Cursor::new("a\nb\n"), b'\n').unwrap(), 2);
+    // 最後の行に改行がなくても1行と数える
+    assert_eq!(count_lines(io::Cursor::new("a\nb"), b'\n').unwrap(), 2);
+    assert_eq!(count_lines(io::Cursor::new("\n\n"), b'\n').unwrap(), 2);
+    // NUL区切りでは改行は区切りにならない
+    assert_eq!(count_lines(io::Cursor::new("a\nb\0c"), 0).unwrap(), 2);
+}
+
+#[test]
+fn test_atomic_writer() {
+    let mut out = Vec::new();
+    let mut writer = AtomicWriter::new(&mut out, true);
+    writer.write_all(b"==> a <==\n").unwrap();
+    writer.flush().unwrap();
+    writer.write_all(b"a\n").unwrap();
+    // commitするまでは何も書かない
+    assert!(writer.inner.is_empty());
+    writer.commit().unwrap();
+    assert_eq!(out, b"==> a <==\na\n");
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn test_apply_env_defaults() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, val)| val.to_string())
+        }
+    };
+
+    // 環境変数は設定ファイルの単位ごと置き換える
+    let mut defaults = parse_defaults("bytes = 5").unwrap();
+    apply_env_defaults(&mut defaults, env(&[("HEADR_LINES", "3")]));
+    assert_eq!(defaults.lines, Some(Count::First(3)));
+    assert_eq!(defaults.bytes, None);
+
+    let mut defaults = Defaults::default();
+    apply_env_defaults(&mut defaults, env(&[("HEADR_BYTES", "-2")]));
+    assert_eq!(defaults.bytes, Some(ByteSpec::Count(Count::AllButLast(2))));
+
+    // 不正な値は無視して、設定ファイルの値を残す
+    let mut defaults = parse_defaults("lines = 7").unwrap();
+    apply_env_defaults(&mut defaults, env(&[("HEADR_LINES", "x")]));
+    assert_eq!(defaults.lines, Some(Count::First(7)));
+}
+
+#[test]
+fn test_run_sink() {
+    /* 呼ばれた順に記録するだけのsink */
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl HeadSink for Recorder {
+        fn begin_file(&mut self, name: &str) -> MyResult<()> {
+            self.0.push(format!("begin {}", name));
+            Ok(())
+        }
+
+        fn write_line(&mut self, n: usize, line: &[u8]) -> MyResult<()> {
+            self.0
+                .push(format!("{}: {}", n, String::from_utf8_lossy(line)));
+            Ok(())
+        }
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> MyResult<()> {
+            self.0.push(format!("{} bytes", bytes.len()));
+            Ok(())
+        }
+
+        fn end_file(&mut self, name: &str, _: Option<usize>) -> MyResult<()> {
+            self.0.push(format!("end {}", name));
+            Ok(())
+        }
+
+        fn fail_file(&mut self, name: &str, _e: &HeadError) -> MyResult<()> {
+            self.0.push(format!("fail {}", name));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> MyResult<()> {
+            self.0.push("finish".to_string());
+            Ok(())
+        }
+    }
+
+    let config = Config::builder()
+        .files(["tests/inputs/two.txt", "tests/inputs/nothing.txt"])
+        .lines(1)
+        .build();
+    let mut sink = Recorder::default();
+    assert_eq!(run_sink(&config, &mut sink).unwrap(), 1);
+    assert_eq!(
+        sink.0,
+        [
+            "begin tests/inputs/two.txt",
+            "1: Two lines.",
+            "end tests/inputs/two.txt",
+            "fail tests/inputs/nothing.txt",
+            "finish",
+        ]
+    );
+
+    let config = Config::builder()
+        .files(["tests/inputs/two.txt"])
+        .bytes(3)
+        .build();
+    let mut sink = Recorder::default();
+    run_sink(&config, &mut sink).unwrap();
+    assert_eq!(
+        sink.0,
+        [
+            "begin tests/inputs/two.txt",
+            "3 bytes",
+            "end tests/inputs/two.txt",
+            "finish"
+        ]
+    );
+}
+
+#[test]
+fn test_print_percent_bytes() {
+    let percent = |text: &'static str, percent| {
+        let mut out = Vec::new();
+        let file: Box<dyn BufRead> = Box::new(io::Cursor::new(text));
+        print_percent_bytes(
+            &Config::default(),
+            "-",
+            file,
+            None,
+            percent,
+            &mut out,
         )
-        .get_matches();
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(percent("0123456789", 50.0), "01234");
+    assert_eq!(percent("0123456789", 100.0), "0123456789");
+    // 切り上げるので、空でなければ少なくとも1バイト
+    assert_eq!(percent("0123456789", 1.0), "0");
+    assert_eq!(percent("", 50.0), "");
+}
 
-    let lines = matches
-        .value_of("lines")
-        .map(parse_positive_int) // OptionがSomeの場合にのみ関数を適用し、Noneの場合は何もしない
-        .transpose() //Option<Result>をResult<Option>に変換する
-        .map_err(|e| format!("illegal line count -- {}", e))?;
+#[cfg(feature = "decrypt")]
+#[test]
+fn test_decrypt() {
+    let open = |path: &str| Input {
+        reader: Box::new(BufReader::new(File::open(path).unwrap())),
+        size: None,
+    };
+    let read = |input: Input| {
+        let mut buffer = String::new();
+        let mut reader = input.reader;
+        reader.read_to_string(&mut buffer).unwrap();
+        buffer
+    };
+    let ten = fs::read_to_string("tests/inputs/ten.txt").unwrap();
 
-    let bytes = matches
-        .value_of("bytes")
-        .map(parse_positive_int)
-        .transpose()
-        .map_err(|e| format!("illegal byte count -- {}", e))?;
+    let config = Config::builder()
+        .decrypt(true)
+        .decrypt_key("tests/inputs/age-key.txt")
+        .build();
+    let input =
+        decrypt(&config, "ten.txt.age", open("tests/inputs/ten.txt.age"));
+    assert_eq!(read(input.unwrap()), ten);
 
-    Ok(Config {
-        files: matches.values_of_lossy("files").unwrap(), // filesは少なくとも1つの値を持っているはずなので、unwrapしても問題ない
-        lines: lines.unwrap(),
-        bytes,
-    })
+    // 暗号化されていなければそのまま
+    let input = decrypt(&config, "ten.txt", open("tests/inputs/ten.txt"));
+    assert_eq!(read(input.unwrap()), ten);
+
+    // 鍵が無ければ、何も出力せずにエラーにする
+    let config = Config::builder().decrypt(true).build();
+    let res =
+        decrypt(&config, "ten.txt.age", open("tests/inputs/ten.txt.age"));
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "cannot decrypt: no identity given (use --decrypt-key FILE)"
+    );
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let num_files = config.files.len();
-
-    for (file_num, filename) in config.files.iter().enumerate() {
-        match open(&filename) {
-            Err(e) => eprintln!("{}: {}", filename, e),
-            Ok(mut file) => {
-                if num_files > 1 {
-                    println!(
-                        "{}==> {} <==",
-                        if file_num > 0 { "\n" } else { "" },
-                        filename
-                    );
-                }
-
-                if let Some(num_bytes) = config.bytes {
-                    /* 指定されたバイト数ファイルから読み出し表示する */
-                    let mut handle = file.take(num_bytes as u64); // fileからnum_bytesバイト文だけ取り出すためのTakeストリームを作成
-                    let mut buffer = vec![0; num_bytes]; // 0で初期化したnum_bytes長の可変なバッファ
-                    let bytes_read = handle.read(&mut buffer)?;
-                    print!(
-                        "{}",
-                        String::from_utf8_lossy(&buffer[..bytes_read]) //..bytes_read: バッファの先頭からbytes_readバイトまでのスライスを表す
-                    );
-                } else {
-                    /* 指定された行数ファイルから読み出し表示する */
-                    let mut line = String::new(); // 可変な文字列バッファをヒープ上に確保しスタックにlineを割り当て
-                    for _ in 0..config.lines {
-                        let bytes = file.read_line(&mut line)?;
-                        if bytes == 0 {
-                            break;
-                        }
-                        print! {"{}", line};
-                        line.clear();
-                    }
-                }
-            }
+#[test]
+fn test_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lines.txt");
+    let filename = path.to_str().unwrap();
+    fs::write(&path, "a\nbb\nccc\nlast").unwrap();
+
+    // 索引が無ければ使わない
+    assert_eq!(index_offset(filename, 1, b'\n').unwrap(), None);
+
+    build_index(filename, b'\n').unwrap();
+    assert_eq!(index_offset(filename, 1, b'\n').unwrap(), Some(2));
+    assert_eq!(index_offset(filename, 3, b'\n').unwrap(), Some(9));
+    // 行が足りなければファイルの終わり
+    assert_eq!(index_offset(filename, 4, b'\n').unwrap(), Some(13));
+    assert_eq!(index_offset(filename, 9, b'\n').unwrap(), Some(13));
+    assert!(index_offset(filename, 1, b'\0').is_err());
+
+    // 大きさが変わったら古い索引として使わない
+    fs::write(&path, "a\nbb\n").unwrap();
+    let e = index_offset(filename, 1, b'\n').unwrap_err();
+    assert!(e
+        .to_string()
+        .ends_with("the index is out of date, ignoring it"));
+
+    assert!(build_index("-", b'\n').is_err());
+}
+
+#[test]
+fn test_format_bytes() {
+    assert_eq!(format_bytes(1024, None), "1024 bytes");
+
+    // ちょうど基数になったら1つ上の単位にする
+    assert_eq!(format_bytes(1023, Some(ByteUnits::Iec)), "1023 B");
+    assert_eq!(format_bytes(1024, Some(ByteUnits::Iec)), "1.0 KiB");
+    assert_eq!(format_bytes(999, Some(ByteUnits::Si)), "999 B");
+    assert_eq!(format_bytes(1000, Some(ByteUnits::Si)), "1.0 kB");
+    assert_eq!(format_bytes(1024, Some(ByteUnits::Si)), "1.0 kB");
+    assert_eq!(format_bytes(1000, Some(ByteUnits::Iec)), "1000 B");
+
+    assert_eq!(format_bytes(10485760, Some(ByteUnits::Iec)), "10.0 MiB");
+    assert_eq!(format_bytes(1234, Some(ByteUnits::Si)), "1.2 kB");
+    assert_eq!(format_bytes(0, Some(ByteUnits::Si)), "0 B");
+
+    // 丸めて1024.0 KiBになる値はMiBで示す
+    assert_eq!(format_bytes(1048575, Some(ByteUnits::Iec)), "1.0 MiB");
+    assert_eq!(format_bytes(1048524, Some(ByteUnits::Iec)), "1023.9 KiB");
+}
+
+#[test]
+fn test_write_reversed() {
+    let reversed = |config: &Config, lines: &[&str]| {
+        let mut buffer = Vec::new();
+        for (n, line) in (1..).zip(lines) {
+            defer_line(n, Some(n as u64 * 10), line.as_bytes(), &mut buffer)
+                .unwrap();
         }
+        let mut out = Vec::new();
+        write_reversed(config, &buffer, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let config = Config::default();
+    assert_eq!(reversed(&config, &["one\n", "two\n"]), "two\none\n");
+    assert_eq!(reversed(&config, &[]), "");
+
+    // 最後の行に改行がなくても次の行とつながらない
+    assert_eq!(reversed(&config, &["one\n", "two"]), "two\none\n");
+
+    let config = Config::builder().zero_terminated(true).build();
+    assert_eq!(reversed(&config, &["a\0", "\0", "b"]), "b\0\0a\0");
+
+    // 行番号と位置は元の行のものを付け、整形は1行ずつ行う
+    let config = Config::builder()
+        .number(true)
+        .byte_offset(true)
+        .show_ends(true)
+        .build();
+    assert_eq!(
+        reversed(&config, &["a\n", "b"]),
+        "     2\t20\tb$\n     1\t10\ta$\n"
+    );
+}
+
+#[test]
+fn test_error_message() {
+    let e = HeadError::InvalidUtf8("-".to_string(), 3);
+    assert_eq!(e.to_string(), "-: invalid UTF-8 at byte 3");
+    assert_eq!(e.message(), "headr: -: invalid UTF-8 at byte 3");
+
+    let e = HeadError::FileNotFound("blargh".to_string());
+    assert_eq!(e.message(), e.to_string());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_error_json() {
+    let e = HeadError::FileNotFound("blargh".to_string());
+    assert_eq!(
+        error_json("blargh", &e).to_string(),
+        r#"{"file":"blargh","error":"No such file or directory (os error 2)","kind":"not_found"}"#
+    );
+
+    let e = HeadError::ShortInput("-".to_string(), 2, 5, "lines");
+    assert_eq!(
+        error_json("-", &e).to_string(),
+        r#"{"file":"-","error":"only 2 of 5 lines available","kind":"short_input"}"#
+    );
+
+    let e = HeadError::Io(io::Error::from(io::ErrorKind::PermissionDenied));
+    assert_eq!(error_json("a", &e)["kind"], "permission_denied");
+}
+
+#[test]
+fn test_parse_chunk_size() {
+    assert_eq!(parse_chunk_size("1M").unwrap(), 1 << 20);
+    assert_eq!(parse_chunk_size("3").unwrap(), 3);
+    assert_eq!(
+        parse_chunk_size("0").unwrap_err().to_string(),
+        "illegal chunk size -- 0"
+    );
+    assert_eq!(parse_chunk_size("1G").unwrap(), MAX_CHUNK_SIZE);
+    assert_eq!(
+        parse_chunk_size("1T").unwrap_err().to_string(),
+        "illegal chunk size -- 1T"
+    );
+}
+
+#[test]
+fn test_print_prefix_bytes_chunks() {
+    // バッファより長い指定でも、バッファ1つ分ずつ最後まで書き出す
+    let mut input = BufReader::with_capacity(3, &b"abcdefgh"[..]);
+    let mut out = Vec::new();
+    print_prefix_bytes(&mut input, 7, &mut out).unwrap();
+    assert_eq!(out, b"abcdefg");
+}
+
+#[test]
+fn test_trim_line() {
+    assert_eq!(trim_line(" \ta b\t \n", Trim::Both, b'\n'), "a b\n");
+    assert_eq!(trim_line(" \ta b\t \n", Trim::Start, b'\n'), "a b\t \n");
+    assert_eq!(trim_line(" \ta b\t \n", Trim::End, b'\n'), " \ta b\n");
+
+    // CRLFは残し、その前の空白だけを除く
+    assert_eq!(trim_line("  a \r\n", Trim::Both, b'\n'), "a\r\n");
+    assert_eq!(trim_line(" \t\r\n", Trim::Both, b'\n'), "\r\n");
+    assert_eq!(trim_line(" a ", Trim::Both, b'\n'), "a");
+    assert_eq!(trim_line(" a \0", Trim::Both, b'\0'), "a\0");
+}
+
+#[test]
+fn test_head_with_total() {
+    let (lines, total) = head_with_total("a\nb\nc\nd".as_bytes(), 2).unwrap();
+    assert_eq!(lines, ["a\n", "b\n"]);
+    // 改行で終わっていない最後の行もcount_linesと同じく1行と数える
+    assert_eq!(total, 4);
+
+    let (lines, total) = head_with_total("a\n".as_bytes(), 5).unwrap();
+    assert_eq!(lines, ["a\n"]);
+    assert_eq!(total, 1);
+
+    let (lines, total) = head_with_total("".as_bytes(), 5).unwrap();
+    assert!(lines.is_empty());
+    assert_eq!(total, 0);
+}
+
+#[test]
+fn test_waits_on_terminal() {
+    let config = get_args_from(["headr"]).unwrap();
+    assert!(waits_on_terminal(&config, true));
+    assert!(!waits_on_terminal(&config, false));
+
+    // "-"を明示したときは端末からでも読む
+    let config = get_args_from(["headr", "-"]).unwrap();
+    assert!(!waits_on_terminal(&config, true));
+
+    let config = Config::builder().build();
+    assert!(!waits_on_terminal(&config, true));
+}
+
+#[test]
+fn test_hold_delimiter() {
+    let mut buffer = Vec::new();
+    {
+        let mut out = HoldDelimiter::new(&mut buffer, b'\n');
+        out.write_all(b"a\n").unwrap();
+        out.write_all(b"").unwrap();
+        out.write_all(b"b\nc\n").unwrap();
+        out.flush().unwrap();
     }
-    Ok(())
+    assert_eq!(buffer, b"a\nb\nc");
+
+    let mut buffer = Vec::new();
+    HoldDelimiter::new(&mut buffer, b'\0')
+        .write_all(b"a\nb")
+        .unwrap();
+    assert_eq!(buffer, b"a\nb");
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(std::io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+#[test]
+fn test_spill_buffer() {
+    let config = Config::builder().max_buffer(4).build();
+    let mut spilled = SpillBuffer::new(&config);
+    spilled.write_all(b"ab").unwrap();
+    assert!(spilled.file.is_none());
+    spilled.write_all(b"cd\nef").unwrap();
+    assert!(spilled.file.is_some());
+    assert_eq!(spilled.len(), 7);
+
+    // 何度読んでも先頭から読める
+    for _ in 0..2 {
+        let mut text = String::new();
+        spilled.reader().unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "abcd\nef");
     }
+    let mut text = String::new();
+    spilled
+        .into_reader()
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, "abcd\nef");
 }
 
-fn parse_positive_int(val: &str) -> MyResult<usize> {
-    match val.parse::<usize>() {
-        Ok(num) if num > 0 => Ok(num),
-        _ => Err(From::from(val)),
+#[test]
+fn test_utf8_check() {
+    let check = |writes: &[&[u8]]| {
+        let mut out = Vec::new();
+        let mut checked = Utf8Check::new(&mut out);
+        let result = writes
+            .iter()
+            .try_for_each(|buf| checked.write_all(buf))
+            .and_then(|()| checked.finish());
+        let invalid_at = checked.invalid_at;
+        assert_eq!(result.is_err(), invalid_at.is_some());
+        (out, invalid_at)
+    };
+    // 書き込みの境目で切れた文字はつなげて調べる
+    assert_eq!(
+        check(&[b"a\xc3", b"\x96\n"]),
+        (b"a\xc3\x96\n".to_vec(), None)
+    );
+    assert_eq!(check(&[b"ab\n", b"c\xffd"]), (b"ab\nc".to_vec(), Some(4)));
+    assert_eq!(check(&[b"ab\xc3"]), (b"ab".to_vec(), Some(2)));
+}
+
+#[test]
+fn test_format_elapsed() {
+    use std::time::Duration;
+
+    assert_eq!(format_elapsed(Duration::from_micros(250)), "250us");
+    assert_eq!(format_elapsed(Duration::from_micros(12_700)), "12ms");
+    assert_eq!(format_elapsed(Duration::from_secs(2)), "2000ms");
+}
+
+#[test]
+fn test_open_any() {
+    let source = open_any("tests/inputs/ten.txt").unwrap();
+    assert_eq!(source.size, Some(49));
+    assert!(source.seekable);
+    assert!(!source.decompressed);
+    assert_eq!(
+        format!("{:?}", source),
+        "Source { size: Some(49), seekable: true, decompressed: false, .. }"
+    );
+    assert_eq!(head_lines(source.reader, 1).unwrap(), ["one\n"]);
+
+    assert!(matches!(
+        open_any("tests/inputs/missing.txt"),
+        Err(HeadError::FileNotFound(_))
+    ));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_open_any_gzip() {
+    let source = open_any("tests/inputs/ten.txt.gz").unwrap();
+    assert_eq!(source.size, None);
+    assert!(!source.seekable);
+    assert!(source.decompressed);
+    assert_eq!(head_lines(source.reader, 1).unwrap(), ["one\n"]);
+}
+
+#[test]
+fn test_select_columns() {
+    let ranges = parse_column_ranges("5-6,1-2").unwrap();
+    assert_eq!(select_columns("abcdefgh\n", &ranges, b'\n'), "abef\n");
+    assert_eq!(select_columns("abcdef\r\n", &ranges, b'\n'), "abef\r\n");
+    // 文字単位で数え、行より後ろの範囲は切り詰める
+    assert_eq!(select_columns("Ötzi", &ranges, b'\n'), "Öt");
+    assert_eq!(select_columns("\n", &ranges, b'\n'), "\n");
+    assert!(parse_column_ranges("3-1").is_err());
+}
+
+#[test]
+fn test_retry_interrupted() {
+    /* 1回おきにInterruptedを返す入力 */
+    struct Interrupting<R> {
+        inner: R,
+        interrupt: bool,
+    }
+    impl<R: Read> Read for Interrupting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            match self.interrupt {
+                true => Err(io::Error::from(io::ErrorKind::Interrupted)),
+                false => {
+                    let len = buf.len().min(2);
+                    self.inner.read(&mut buf[..len])
+                }
+            }
+        }
     }
+    let interrupting = || Interrupting {
+        inner: &b"one\ntwo\n"[..],
+        interrupt: false,
+    };
+
+    let mut out = Vec::new();
+    let mut file = BufReader::new(interrupting());
+    assert!(print_prefix_bytes(&mut file, 6, &mut out).is_err());
+
+    let mut out = Vec::new();
+    let mut file = RetryInterrupted(BufReader::new(interrupting()));
+    print_prefix_bytes(&mut file, 6, &mut out).unwrap();
+    assert_eq!(out, b"one\ntw");
+
+    let mut buf = [0; 4];
+    assert_eq!(RetryInterrupted(interrupting()).read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"on");
 }
 
+#[cfg(feature = "http")]
 #[test]
-fn test_parse_positive_int() {
-    // 3は正の整数なのでOK
-    let res = parse_positive_int("3");
-    assert!(res.is_ok());
-    assert_eq!(res.unwrap(), 3);
+fn test_cache_max_age() {
+    assert_eq!(cache_max_age(None, 300), Some((300, false)));
+    assert_eq!(
+        cache_max_age(Some("public, max-age=60"), 300),
+        Some((60, true))
+    );
+    assert_eq!(cache_max_age(Some("No-Cache"), 300), Some((0, true)));
+    assert_eq!(cache_max_age(Some("max-age=60, no-store"), 300), None);
+    assert_eq!(cache_max_age(Some("max-age=soon"), 300), Some((300, false)));
+}
 
-    // 数字でない文字列の場合はエラー
-    let res = parse_positive_int("foo");
-    assert!(res.is_err());
-    assert_eq!(res.unwrap_err().to_string(), "foo".to_string());
+#[cfg(feature = "http")]
+#[test]
+fn test_read_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = "http://example.com/log";
+    let path = cache_path(dir.path(), url);
+    let entry = CacheEntry {
+        url: url.to_string(),
+        fetched: 1000,
+        max_age: 60,
+        from_header: false,
+        complete: false,
+        len: 4,
+    };
+    write_cache(&path, &entry, b"one\n").unwrap();
+    assert_eq!(
+        read_cache(&path, url, 1059, 300),
+        Some((entry.clone(), b"one\n".to_vec()))
+    );
+    // 古いもの、別のURLのもの
+    assert_eq!(read_cache(&path, url, 1060, 300), None);
+    assert_eq!(
+        read_cache(&path, "http://example.com/other", 1030, 300),
+        None
+    );
 
-    // 0の場合もエラー
-    let res = parse_positive_int("0");
-    assert!(res.is_err());
-    assert_eq!(res.unwrap_err().to_string(), "0".to_string());
+    // 後から縮めた--cache-ttlも効く。Cache-Controlで決まった期限はそのまま
+    assert_eq!(read_cache(&path, url, 1030, 10), None);
+    let entry = CacheEntry {
+        from_header: true,
+        ..entry
+    };
+    write_cache(&path, &entry, b"one\n").unwrap();
+    assert!(read_cache(&path, url, 1030, 10).is_some());
+
+    // 壊れたものは取り直させる
+    let mut data = fs::read(&path).unwrap();
+    data.pop();
+    fs::write(&path, &data).unwrap();
+    assert_eq!(read_cache(&path, url, 1030, 300), None);
+    fs::write(&path, b"{not json\none\n").unwrap();
+    assert_eq!(read_cache(&path, url, 1030, 300), None);
+}
+
+#[test]
+fn test_line_filter() {
+    let config = Config::builder()
+        .files(["tests/inputs/ten.txt"])
+        .lines(Count::First(2))
+        .filter(|line: &mut String| {
+            if line.starts_with('t') {
+                return false;
+            }
+            *line = line.to_uppercase();
+            true
+        })
+        .build();
+    let mut out = Vec::new();
+    run_with(&config, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "ONE\nFOUR\n");
+
+    // --skip-emptyで落とした行は-nに数えない
+    let config = Config {
+        trim: Some(Trim::Both),
+        skip_empty: true,
+        number: true,
+        ..Config::default()
+    };
+    let mut out = Vec::new();
+    let chain = FilterChain::new(&config).unwrap();
+    let line = print_line(&config, &chain, 1, None, "  \n", &mut out).unwrap();
+    assert!(!line);
+    assert!(out.is_empty());
+}
+
+#[test]
+fn test_filter_chain() {
+    // --trimの後に--squeeze-blankを適用するので、空白だけの行も空行としてまとめる
+    let config = Config {
+        trim: Some(Trim::Both),
+        squeeze_blank: true,
+        ..Config::default()
+    };
+    let chain = FilterChain::new(&config).unwrap();
+    let kept: Vec<_> = ["a\n", "\n", " \n", "b\n", "\n"]
+        .into_iter()
+        .filter_map(|line| chain.apply(line))
+        .collect();
+    assert_eq!(kept, ["a\n", "\n", "b\n", "\n"]);
+
+    // --rawの行は書き換えられない
+    let config = Config {
+        raw: true,
+        ..Config::builder().filter(|_: &mut String| true).build()
+    };
+    assert_eq!(
+        FilterChain::new(&config).err().unwrap().to_string(),
+        "line filters cannot be used with --raw or --null-data"
+    );
 }
@@ -1,6 +1,11 @@
 fn main() {
-    if let Err(e) = headr::get_args().and_then(headr::run) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    match headr::get_args().and_then(headr::run) {
+        Ok(()) => {}
+        // 個々のファイルのエラーは表示済みなので、終了コードだけを返す
+        Err(headr::HeadError::FilesFailed(_)) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e.message());
+            std::process::exit(1);
+        }
     }
 }
@@ -5,6 +5,7 @@ use std::io::prelude::*;
 use std::{
     error::Error,
     fs::{self, File},
+    path::Path,
 };
 
 type TestResult = Result<(), Box<dyn Error>>;
@@ -25,6 +26,19 @@ fn random_string() -> String {
         .collect()
 }
 
+// --------------------------------------------------
+/* --statsの期待値。graphemes機能がないときは書記素数の欄を出さないので取り除く */
+fn stats(expected: &str) -> String {
+    if cfg!(feature = "graphemes") {
+        return expected.to_string();
+    }
+    expected
+        .split(", ")
+        .filter(|field| !field.ends_with(" graphemes"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 // --------------------------------------------------
 fn gen_bad_file() -> String {
     loop {
@@ -41,7 +55,7 @@ fn dies_bad_bytes() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal byte count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-c", &bad, EMPTY])
+        .args(["-c", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -55,7 +69,7 @@ fn dies_bad_lines() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal line count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-n", &bad, EMPTY])
+        .args(["-n", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -70,7 +84,7 @@ fn dies_bytes_and_lines() -> TestResult {
                used with '--bytes <BYTES>'";
 
     Command::cargo_bin(PRG)?
-        .args(&["-n", "1", "-c", "2"])
+        .args(["-n", "1", "-c", "2"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(msg));
@@ -78,6 +92,85 @@ fn dies_bytes_and_lines() -> TestResult {
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn expands_variables_in_paths() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .env("HEADR_INPUTS", "tests/inputs")
+        .args(["-n", "1", "${HEADR_INPUTS}/ten.txt"])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    Command::cargo_bin(PRG)?
+        .env_remove("HEADR_INPUTS")
+        .args(["$HEADR_INPUTS/ten.txt"])
+        .assert()
+        .failure()
+        .stderr("undefined variable -- HEADR_INPUTS\n");
+
+    // 名前どおりのファイルがあれば、そのまま開く
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("price$USD.csv"), "1\n")?;
+    Command::cargo_bin(PRG)?
+        .current_dir(dir.path())
+        .env_remove("USD")
+        .arg("price$USD.csv")
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn first_limit_whichever_first() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--first-limit", "-n", "3", "-c", "10", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nth");
+
+    Command::cargo_bin(PRG)?
+        .args(["--first-limit", "-n", "2", "-c", "100", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--first-limit", "-n", "-2", "-c", "100", TEN])
+        .assert()
+        .failure()
+        .stderr("--first-limit needs positive counts for -n and -c\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn continues_after_read_error() -> TestResult {
+    // ディレクトリを標準入力にすると、開けても読むときにEISDIRで失敗する
+    for args in [["-q", "-a"], ["-q", "-j2"], ["--interleave", "-a"]] {
+        let output =
+            std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+                .args(args)
+                .args(["-n", "1", "-", ONE])
+                .stdin(File::open("tests/inputs")?)
+                .output()?;
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stderr)?,
+            "-: Is a directory (os error 21)\n"
+        );
+        let stdout = String::from_utf8(output.stdout)?;
+        assert!(stdout.ends_with("Öne line, four words.\n"), "{}", stdout);
+    }
+
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn skips_bad_file() -> TestResult {
@@ -86,6 +179,7 @@ fn skips_bad_file() -> TestResult {
     Command::cargo_bin(PRG)?
         .args([EMPTY, &bad, ONE])
         .assert()
+        .failure()
         .stderr(predicate::str::is_match(expected)?);
 
     Ok(())
@@ -93,17 +187,16 @@ fn skips_bad_file() -> TestResult {
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
+    // -c can cut through a multibyte character, so compare raw bytes
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
         .success()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(expected);
 
     Ok(())
 }
@@ -114,18 +207,16 @@ fn run_stdin(
     input_file: &str,
     expected_file: &str,
 ) -> TestResult {
-    // Extra work here due to lossy UTF
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
-    let input = fs::read_to_string(input_file)?;
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
+    let input = fs::read(input_file)?;
 
     Command::cargo_bin(PRG)?
         .write_stdin(input)
         .args(args)
         .assert()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(expected);
 
     Ok(())
 }
@@ -419,3 +510,3860 @@ fn multiple_files_c4() -> TestResult {
         "tests/expected/all.c4.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn empty_n_minus2() -> TestResult {
+    run(&[EMPTY, "-n", "-2"], "tests/expected/empty.txt.n-2.out")
+}
+
+#[test]
+fn one_n_minus2() -> TestResult {
+    run(&[ONE, "-n", "-2"], "tests/expected/one.txt.n-2.out")
+}
+
+#[test]
+fn two_n_minus2() -> TestResult {
+    run(&[TWO, "-n", "-2"], "tests/expected/two.txt.n-2.out")
+}
+
+#[test]
+fn three_n_minus2() -> TestResult {
+    run(&[THREE, "-n", "-2"], "tests/expected/three.txt.n-2.out")
+}
+
+#[test]
+fn ten_n_minus2() -> TestResult {
+    run(&[TEN, "-n", "-2"], "tests/expected/ten.txt.n-2.out")
+}
+
+#[test]
+fn ten_n_minus2_stdin() -> TestResult {
+    run_stdin(&["-n", "-2"], TEN, "tests/expected/ten.txt.n-2.out")
+}
+
+#[test]
+fn ten_n_minus0() -> TestResult {
+    run(&[TEN, "-n", "-0"], TEN)
+}
+
+#[test]
+fn multiple_files_n_minus2() -> TestResult {
+    run(
+        &[EMPTY, ONE, TWO, THREE, TEN, "-n", "-2"],
+        "tests/expected/all.n-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_c_minus3() -> TestResult {
+    run(&[EMPTY, "-c", "-3"], "tests/expected/empty.txt.c-3.out")
+}
+
+#[test]
+fn one_c_minus3() -> TestResult {
+    run(&[ONE, "-c", "-3"], "tests/expected/one.txt.c-3.out")
+}
+
+#[test]
+fn one_c_minus3_stdin() -> TestResult {
+    run_stdin(&["-c", "-3"], ONE, "tests/expected/one.txt.c-3.out")
+}
+
+#[test]
+fn three_c_minus3() -> TestResult {
+    run(&[THREE, "-c", "-3"], "tests/expected/three.txt.c-3.out")
+}
+
+#[test]
+fn ten_c_minus3() -> TestResult {
+    run(&[TEN, "-c", "-3"], "tests/expected/ten.txt.c-3.out")
+}
+
+#[test]
+fn ten_c_minus3_stdin() -> TestResult {
+    run_stdin(&["-c", "-3"], TEN, "tests/expected/ten.txt.c-3.out")
+}
+
+#[test]
+fn multiple_files_c_minus3() -> TestResult {
+    run(
+        &[EMPTY, ONE, TWO, THREE, TEN, "-c", "-3"],
+        "tests/expected/all.c-3.out",
+    )
+}
+
+#[test]
+fn c_minus_keeps_raw_bytes() -> TestResult {
+    let input: Vec<u8> = vec![0xff, 0xfe, 0x00, 0x80, b'a', b'b', b'c'];
+    Command::cargo_bin(PRG)?
+        .write_stdin(input.clone())
+        .args(["-a", "-c", "-2"])
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..5]));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_quiet() -> TestResult {
+    run(
+        &["-q", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n2.q.out",
+    )
+}
+
+#[test]
+fn multiple_files_silent() -> TestResult {
+    run(
+        &["--silent", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n2.q.out",
+    )
+}
+
+#[test]
+fn multiple_files_no_filename() -> TestResult {
+    run(
+        &["--no-filename", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n2.q.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn one_verbose() -> TestResult {
+    run(&["-v", "-n", "2", ONE], "tests/expected/one.txt.n2.v.out")
+}
+
+#[test]
+fn stdin_and_file_verbose() -> TestResult {
+    let expected = format!(
+        "==> standard input <==\nabc\n\n==> {} <==\nÖne line, four words.\n",
+        ONE
+    );
+    Command::cargo_bin(PRG)?
+        .write_stdin("abc\n")
+        .args(["-v", "-", ONE])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    // 1つだけでも-vならヘッダーを付ける
+    Command::cargo_bin(PRG)?
+        .write_stdin("abc\n")
+        .args(["-v"])
+        .assert()
+        .success()
+        .stdout("==> standard input <==\nabc\n");
+
+    Ok(())
+}
+
+#[test]
+fn two_files_verbose() -> TestResult {
+    run(
+        &["--verbose", "-n", "2", ONE, TWO],
+        "tests/expected/one_two.n2.v.out",
+    )
+}
+
+#[test]
+fn dies_quiet_and_verbose() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-v", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\nb\0c\0d\0e\0")
+        .args(["-z", "-n", "3"])
+        .assert()
+        .success()
+        .stdout("a\nb\0c\0d\0");
+
+    Ok(())
+}
+
+#[test]
+fn zero_terminated_all_but_last() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\0b\0c\0d")
+        .args(["--zero-terminated", "-n", "-2"])
+        .assert()
+        .success()
+        .stdout("a\0b\0");
+
+    Ok(())
+}
+
+#[test]
+fn zero_terminated_ignored_in_byte_mode() -> TestResult {
+    run_stdin(&["-z", "-c", "4"], TEN, "tests/expected/ten.txt.c4.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_suffix() -> TestResult {
+    run(&[TEN, "-c", "1K"], TEN)
+}
+
+#[test]
+fn dies_bad_byte_suffix() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1Q", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("illegal byte count -- 1Q"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "gzip")]
+#[test]
+fn ten_gz() -> TestResult {
+    run(&["./tests/inputs/ten.txt.gz"], "tests/expected/ten.txt.out")
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn ten_gz_n2_stdin() -> TestResult {
+    let input = fs::read("./tests/inputs/ten.txt.gz")?;
+    let expected = fs::read("tests/expected/ten.txt.n2.out")?;
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["-n", "2"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn ten_xz() -> TestResult {
+    run(&["./tests/inputs/ten.txt.xz"], "tests/expected/ten.txt.out")
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn ten_zst() -> TestResult {
+    run(
+        &["./tests/inputs/ten.txt.zst"],
+        "tests/expected/ten.txt.out",
+    )
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn ten_bz2() -> TestResult {
+    run(
+        &["./tests/inputs/ten.txt.bz2"],
+        "tests/expected/ten.txt.out",
+    )
+}
+
+// 標準入力には拡張子が無いので、マジックナンバーで形式を決める
+#[cfg(all(feature = "xz", feature = "zstd", feature = "bzip2"))]
+#[test]
+fn compressed_stdin_by_magic() -> TestResult {
+    let expected = fs::read("tests/expected/ten.txt.n2.out")?;
+    for ext in ["xz", "zst", "bz2"] {
+        let input = fs::read(format!("./tests/inputs/ten.txt.{}", ext))?;
+        Command::cargo_bin(PRG)?
+            .write_stdin(input)
+            .args(["-n", "2"])
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+
+    Ok(())
+}
+
+// マジックナンバーで始まるだけのテキストは展開しない
+#[cfg(feature = "bzip2")]
+#[test]
+fn plain_text_with_magic() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("BZh is not bzip2\nsecond\n")
+        .args(["-n", "1"])
+        .assert()
+        .success()
+        .stdout("BZh is not bzip2\n");
+
+    Ok(())
+}
+
+#[test]
+fn skips_bad_file_continues() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = fs::read_to_string("tests/expected/one.txt.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["-q", &bad, ONE])
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(expected)
+        .stderr(predicate::str::contains("could not be read").not());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_jobs() -> TestResult {
+    run(
+        &["-j", "3", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.out",
+    )
+}
+
+#[test]
+fn multiple_files_jobs_c2() -> TestResult {
+    run(
+        &["--jobs", "8", EMPTY, ONE, TWO, THREE, TEN, "-c", "2"],
+        "tests/expected/all.c2.out",
+    )
+}
+
+#[test]
+fn jobs_with_stdin_and_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n==> standard input <==\none\ntwo\n",
+        ONE
+    );
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\n")
+        .args(["-j", "2", ONE, &bad, "-"])
+        .assert()
+        .failure()
+        .stdout(expected)
+        .stderr(predicate::str::contains(bad));
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-j", "0", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid number of jobs -- 0"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "http")]
+fn serve_once(body: &'static str) -> Result<String, Box<dyn Error>> {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        if let Ok((mut stream, _)) = listener.accept() {
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+    Ok(format!("http://{}/ten.txt", addr))
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn url_n2() -> TestResult {
+    let url = serve_once("one\ntwo\nthree\n")?;
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", &url])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn url_cache() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let cache = dir.path().to_str().unwrap();
+    let url = serve_once("one\ntwo\nthree\n")?;
+    Command::cargo_bin(PRG)?
+        .args(["--cache", cache, "-n", "1", &url])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    // サーバーはもう応答しないので、キャッシュから読む
+    Command::cargo_bin(PRG)?
+        .args(["--cache", cache, "-n", "2", &url])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    // キャッシュにある行しか読まなくても、期限が0秒なら取り直そうとして失敗する
+    Command::cargo_bin(PRG)?
+        .args(["--cache", cache, "--cache-ttl", "0", "-n", "1", &url])
+        .assert()
+        .failure()
+        .stdout("");
+    Ok(())
+}
+
+#[test]
+fn strip_bom_without_bom() -> TestResult {
+    run(
+        &["--strip-bom", "-c", "4", TEN],
+        "tests/expected/ten.txt.c4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expands_glob() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n==> {} <==\nTwo lines.\n",
+        "tests/inputs/one.txt", "tests/inputs/two.txt"
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--glob", "-n", "1", "./tests/inputs/[ot][nw][eo].txt"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_glob_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--glob", "./tests/inputs/*.nothing"])
+        .assert()
+        .failure()
+        .stderr("no match for pattern -- ./tests/inputs/*.nothing\n");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(windows)]
+fn expands_glob_by_default() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-q", "./tests/inputs/[ot][nw][eo].txt"])
+        .assert()
+        .success()
+        .stdout("Öne line, four words.\nTwo lines.\n");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn no_glob_by_default() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["./tests/inputs/*.nothing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(
+            r"^\./tests/inputs/\*\.nothing: .* [(]os error 2[)]",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn no_glob_takes_names_literally() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--glob", "--no-glob", "./tests/inputs/*.nothing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("os error 2"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn one_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-C", "3", ONE])
+        .assert()
+        .success()
+        .stdout("Öne");
+
+    Ok(())
+}
+
+#[test]
+fn chars_multibyte_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("日本語\n")
+        .args(["--chars", "2"])
+        .assert()
+        .success()
+        .stdout("日本");
+
+    Ok(())
+}
+
+#[test]
+fn dies_chars_and_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-C", "1", "-c", "2", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_n3_number() -> TestResult {
+    run(&["-N", "-n", "3", TEN], "tests/expected/ten.txt.n3.N.out")
+}
+
+#[test]
+fn number_resets_per_file() -> TestResult {
+    let expected = format!(
+        "==> {} <==\n     1\tÖne line, four words.\n\n\
+         ==> {} <==\n     1\tTwo lines.\n     2\tFour words.\n",
+        ONE, TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--number", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn number_ignored_in_byte_mode() -> TestResult {
+    run(&["-N", "-c", "4", TEN], "tests/expected/ten.txt.c4.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn output_to_file() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("out.txt");
+    Command::cargo_bin(PRG)?
+        .args([EMPTY, ONE, TWO, THREE, TEN, "-o"])
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read(&output)?, fs::read("tests/expected/all.out")?);
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_output() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("missing").join("out.txt");
+    Command::cargo_bin(PRG)?
+        .args([ONE, "--output"])
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(output.to_string_lossy()));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    let expected = format!(
+        "\x1b[1;36m==> {} <==\x1b[0m\nÖne line, four words.\n\n\
+         \x1b[1;36m==> {} <==\x1b[0m\nTwo lines.\n",
+        ONE, TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-n", "1", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn color_auto_when_piped() -> TestResult {
+    run(
+        &["--color", "auto", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive() -> TestResult {
+    let expected = "==> ./tests/inputs/tree/a.txt <==\na1\n\n\
+        ==> ./tests/inputs/tree/c.txt <==\nc1\n\n\
+        ==> ./tests/inputs/tree/sub/b.txt <==\nb1\n";
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-n", "1", "./tests/inputs/tree"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn recursive_exclude() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-n", "1", "--exclude", "c.*", "--exclude", "*/sub/*"])
+        .arg("./tests/inputs/tree")
+        .assert()
+        .success()
+        .stdout("a1\n");
+
+    Ok(())
+}
+
+#[test]
+fn glob_exclude() -> TestResult {
+    let expected = "==> tests/inputs/three.txt <==\nThree\r\n\n\
+        ==> tests/inputs/two.txt <==\nTwo lines.\n";
+    Command::cargo_bin(PRG)?
+        .args(["--glob", "-n", "1", "tests/inputs/t*.txt"])
+        .args(["--exclude", "ten.txt", "tests/inputs/empty.txt"])
+        .args(["--exclude", "tests/inputs/e*"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_skips_symlink_loop() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a.txt"), "a\n")?;
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("loop"))?;
+    Command::cargo_bin(PRG)?
+        .arg("--recursive")
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stdout("a\n")
+        .stderr(predicate::str::contains("loop"));
+
+    // ほかのファイルのエラーと同じく、--ignore-errorsと--error-formatに従う
+    Command::cargo_bin(PRG)?
+        .args(["--recursive", "--ignore-errors"])
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stdout("a\n")
+        .stderr("");
+
+    #[cfg(feature = "json")]
+    Command::cargo_bin(PRG)?
+        .args(["--recursive", "--error-format", "json"])
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(r#""kind":"directory_loop""#));
+
+    Ok(())
+}
+
+#[test]
+fn dies_directory_without_recursive() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["./tests/inputs/tree", ONE])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Öne line"))
+        .stderr("./tests/inputs/tree: Is a directory\n");
+
+    Ok(())
+}
+
+#[test]
+fn dies_directory_in_every_mode() -> TestResult {
+    // ディレクトリは読む前に報告し、どの読み方でも残りのファイルに進む
+    for args in [
+        &["-c", "4"][..],
+        &["-j", "2"],
+        &["--interleave"],
+        &["--count"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .args(["./tests/inputs/tree", ONE])
+            .assert()
+            .failure()
+            .stdout(
+                predicate::str::contains("Öne")
+                    .or(predicate::str::contains("1 ")),
+            )
+            .stderr("./tests/inputs/tree: Is a directory\n");
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_percent() -> TestResult {
+    run(&["-p", "20%", TEN], "tests/expected/ten.txt.n2.out")
+}
+
+#[test]
+fn ten_percent_rounds_up() -> TestResult {
+    run(&["--percent", "11", TEN], "tests/expected/ten.txt.n2.out")
+}
+
+#[test]
+fn ten_percent_stdin() -> TestResult {
+    run_stdin(&["-p", "40%"], TEN, "tests/expected/ten.txt.n4.out")
+}
+
+#[test]
+fn ten_percent_after_skip() -> TestResult {
+    // 残りの6行の50%なので3行。標準入力でも同じ
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "4", "-p", "50", TEN])
+        .assert()
+        .success()
+        .stdout("five\nsix\nseven\n");
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "4", "-p", "50"])
+        .write_stdin(fs::read(TEN)?)
+        .assert()
+        .success()
+        .stdout("five\nsix\nseven\n");
+
+    // --bytes-percentも読み飛ばした残りのバイト数で割合を求める。ten.txtの残りは30バイト
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "19", "--bytes-percent", "50", TEN])
+        .assert()
+        .success()
+        .stdout("five\nsix\nseven\n");
+
+    Ok(())
+}
+
+#[test]
+fn dies_percent_and_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-p", "10%", "-n", "2", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_are_not_lossy() -> TestResult {
+    // "café" in Latin-1 is not valid UTF-8
+    let input: Vec<u8> = vec![b'c', b'a', b'f', 0xe9, b'\n', 0x00, 0xff];
+    Command::cargo_bin(PRG)?
+        .write_stdin(input.clone())
+        .args(["-a", "-c", "6"])
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..6]));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_read_once() -> TestResult {
+    let expected = format!(
+        "==> standard input <==\none\ntwo\n\n==> {} <==\nÖne line, four words.\n",
+        ONE
+    );
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\n")
+        .args(["-", ONE, "-"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn broken_pipe_exits_cleanly() -> TestResult {
+    use std::process::{Command as StdCommand, Stdio};
+
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("big.txt");
+    let line = "x".repeat(99) + "\n";
+    fs::write(&input, line.repeat(100_000))?;
+
+    let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-n", "100000"])
+        .arg(&input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().ok_or("no stdout")?;
+    let mut buffer = [0; 10];
+    stdout.read_exact(&mut buffer)?;
+    drop(stdout);
+
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_total_lines() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n\
+         ==> {} <==\nTwo lines.\nFour words.\n",
+        ONE, TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--max-total-lines", "3", ONE, TWO, THREE, TEN])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn max_total_lines_jobs() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nTwo lines.\nFour words.\n\n==> {} <==\nThree\r\n",
+        TWO, THREE
+    );
+    Command::cargo_bin(PRG)?
+        .args(["-j", "2", "--max-total-lines", "3", TWO, THREE, TEN])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn max_total_bytes() -> TestResult {
+    let expected = format!("==> {} <==\nTwo lines.", TWO);
+    Command::cargo_bin(PRG)?
+        .args(["--max-total-bytes", "10", TWO, THREE])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_max_total_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--max-total-bytes", "3X", TWO])
+        .assert()
+        .failure()
+        .stderr("illegal byte count -- 3X\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "config")]
+#[test]
+fn config_file_defaults() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "lines = 3\nquiet = true\n")?;
+
+    Command::cargo_bin(PRG)?
+        .env("HEADR_CONFIG", &path)
+        .arg(TEN)
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\n");
+
+    // コマンドラインの指定が優先される
+    Command::cargo_bin(PRG)?
+        .env("HEADR_CONFIG", &path)
+        .args(["-n", "1", "-v", TEN])
+        .assert()
+        .success()
+        .stdout(format!("==> {} <==\none\n", TEN));
+
+    Ok(())
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn config_file_invalid_warns() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "lines = \"many\"\n")?;
+
+    Command::cargo_bin(PRG)?
+        .env("HEADR_CONFIG", &path)
+        .args(["-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n")
+        .stderr(predicate::str::contains("illegal line count -- many"));
+
+    Command::cargo_bin(PRG)?
+        .env("HEADR_CONFIG", dir.path().join("missing.toml"))
+        .args(["-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n")
+        .stderr(predicate::str::contains("missing.toml"));
+
+    Ok(())
+}
+
+// config機能が無ければ設定ファイルは読まず、そのことを警告する
+#[cfg(not(feature = "config"))]
+#[test]
+fn config_file_needs_feature() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "lines = 3\n")?;
+
+    Command::cargo_bin(PRG)?
+        .env("HEADR_CONFIG", &path)
+        .args(["-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n")
+        .stderr("headr: HEADR_CONFIG: config files need the config feature\n");
+
+    Ok(())
+}
+
+#[test]
+fn env_defaults() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .env("HEADR_LINES", "2")
+        .arg(TEN)
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .env("HEADR_BYTES", "4")
+        .arg(TEN)
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    // コマンドラインの指定が優先される
+    Command::cargo_bin(PRG)?
+        .env("HEADR_LINES", "2")
+        .args(["-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    // 不正な値は警告して、いつもの10行にする
+    let expected = fs::read_to_string("tests/expected/ten.txt.out")?;
+    Command::cargo_bin(PRG)?
+        .env("HEADR_LINES", "many")
+        .arg(TEN)
+        .assert()
+        .success()
+        .stdout(expected)
+        .stderr("headr: HEADR_LINES: illegal line count -- many\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_range() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "5-9", TEN])
+        .assert()
+        .success()
+        .stdout("two\nt");
+
+    Ok(())
+}
+
+#[test]
+fn byte_range_stdin_past_end() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("abcdef")
+        .args(["-c", "4-100"])
+        .assert()
+        .success()
+        .stdout("def");
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_byte_range() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "9-5", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal byte count -- 9-5\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats_to_stderr() -> TestResult {
+    let expected = stats(&format!(
+        "{}: 1 lines, 22 chars, 22 graphemes, 23 bytes (limit: first 5 lines)\n\
+         {}: 2 lines, 23 chars, 23 graphemes, 23 bytes (limit: first 5 lines)\n",
+        ONE, TWO
+    ));
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "-n", "5", ONE, TWO])
+        .assert()
+        .success()
+        .stderr(expected);
+
+    Ok(())
+}
+
+#[test]
+fn stats_with_byte_limit() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\nthree\n")
+        .args(["--stats", "-c", "6"])
+        .assert()
+        .success()
+        .stdout("one\ntw")
+        .stderr(stats(
+            "-: 2 lines, 6 chars, 6 graphemes, 6 bytes (limit: first 6 bytes)\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn stats_total_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "--total-lines", "-n", "2", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n")
+        .stderr(stats(&format!(
+            "{}: 2 lines, 8 chars, 8 graphemes, 8 bytes, 10 lines in total \
+             (limit: first 2 lines)\n",
+            TEN
+        )));
+
+    // 改行で終わっていない最後の行も数える
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\nb\nc")
+        .args(["--stats", "--total-lines", "-c", "1"])
+        .assert()
+        .success()
+        .stderr(stats(
+            "-: 1 lines, 1 chars, 1 graphemes, 1 bytes, 3 lines in total \
+                 (limit: first 1 bytes)\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn stats_pretty_bytes() -> TestResult {
+    let input = "x".repeat(3000);
+    Command::cargo_bin(PRG)?
+        .write_stdin(input.clone())
+        .args(["--stats", "--pretty-bytes", "-c", "2048"])
+        .assert()
+        .success()
+        .stderr(stats(
+            "-: 1 lines, 2048 chars, 2048 graphemes, 2.0 KiB \
+             (limit: first 2048 bytes)\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--stats", "--pretty-bytes=si", "-c", "1500"])
+        .assert()
+        .success()
+        .stderr(stats(
+            "-: 1 lines, 1500 chars, 1500 graphemes, 1.5 kB \
+             (limit: first 1500 bytes)\n",
+        ));
+
+    // --statsなしでは意味がないのでエラー
+    Command::cargo_bin(PRG)?
+        .args(["--pretty-bytes", ONE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn stats_multibyte() -> TestResult {
+    // eと結合文字のアクセントは2文字で1つの書記素
+    #[cfg(feature = "graphemes")]
+    Command::cargo_bin(PRG)?
+        .write_stdin("e\u{301}x\n")
+        .args(["--stats", "--graphemes", "1"])
+        .assert()
+        .success()
+        .stdout("e\u{301}")
+        .stderr(
+            "-: 1 lines, 2 chars, 1 graphemes, 3 bytes \
+             (limit: first 1 graphemes)\n",
+        );
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("e\u{301}x\n")
+        .args(["--stats", "--chars", "3"])
+        .assert()
+        .success()
+        .stdout("e\u{301}x")
+        .stderr(stats(
+            "-: 1 lines, 3 chars, 2 graphemes, 4 bytes \
+             (limit: first 3 characters)\n",
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exact_lines() -> TestResult {
+    let expected = format!("==> {} <==\none\ntwo\n", TEN);
+    Command::cargo_bin(PRG)?
+        .args(["--exact", "-n", "2", TEN, ONE])
+        .assert()
+        .failure()
+        .stdout(expected)
+        .stderr(format!("headr: {}: only 1 of 2 lines available\n", ONE));
+
+    Ok(())
+}
+
+#[test]
+fn exact_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("abc")
+        .args(["--exact", "-c", "3"])
+        .assert()
+        .success()
+        .stdout("abc");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("abc")
+        .args(["--exact", "-c", "2-5"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr("headr: -: only 2 of 4 bytes available\n");
+
+    Ok(())
+}
+
+#[test]
+fn exact_parallel() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--exact", "-j", "2", "-c", "30", ONE, TEN])
+        .assert()
+        .failure()
+        .stdout(format!(
+            "==> {} <==\n{}",
+            TEN, "one\ntwo\nthree\nfour\nfive\nsix\nse"
+        ))
+        .stderr(format!("headr: {}: only 23 of 30 bytes available\n", ONE));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_dir() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    Command::cargo_bin(PRG)?
+        .arg("--output-dir")
+        .arg(dir.path())
+        .args(["-n", "2", TEN, TWO])
+        .assert()
+        .success()
+        .stdout("");
+
+    let inputs = dir.path().join("tests").join("inputs");
+    assert_eq!(fs::read_to_string(inputs.join("ten.txt"))?, "one\ntwo\n");
+    assert_eq!(
+        fs::read_to_string(inputs.join("two.txt"))?,
+        "Two lines.\nFour words.\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn output_dir_policy() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let dest = dir.path().join("tests").join("inputs").join("ten.txt");
+    Command::cargo_bin(PRG)?
+        .arg("--output-dir")
+        .arg(dir.path())
+        .args(["-n", "1", TEN, "tests/inputs/ten.txt", "../x", "-"])
+        .write_stdin("stdin\n")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(format!(
+            "tests/inputs/ten.txt: {} was already written for {}\n\
+             ../x: paths with .. cannot be mirrored under --output-dir\n\
+             -: standard input has no path to mirror under --output-dir\n",
+            dest.display(),
+            TEN
+        ));
+    assert_eq!(fs::read_to_string(dest)?, "one\n");
+
+    Ok(())
+}
+
+#[test]
+fn output_dir_same_file() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("f.txt");
+    fs::write(&path, "1\n2\n3\n")?;
+    Command::cargo_bin(PRG)?
+        .current_dir(dir.path())
+        .args(["--output-dir", ".", "-n", "2", "f.txt"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr("f.txt: refusing to write ./f.txt over the input\n");
+    assert_eq!(fs::read_to_string(&path)?, "1\n2\n3\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sort_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--sort-files", "-n", "1", TWO, "-", ONE])
+        .write_stdin("stdin\n")
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> standard input <==\nstdin\n\n\
+             ==> {} <==\nÖne line, four words.\n\n\
+             ==> {} <==\nTwo lines.\n",
+            ONE, TWO
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--sort-files", "--reverse", "-q", "-n", "1", ONE, "-", TWO])
+        .write_stdin("stdin\n")
+        .assert()
+        .success()
+        .stdout("stdin\nTwo lines.\nÖne line, four words.\n");
+
+    Ok(())
+}
+
+#[test]
+fn dies_reverse_without_sort_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--reverse", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--sort-files"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", "--context", "2", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n...\nnine\nten\n");
+
+    // 標準入力でも末尾の行は手元に置いておく。-Nは元の行番号
+    Command::cargo_bin(PRG)?
+        .write_stdin(fs::read(TEN)?)
+        .args(["-n", "1", "--context", "1", "-N"])
+        .assert()
+        .success()
+        .stdout("     1\tone\n...\n    10\tten\n");
+
+    // 飛ばす行が無ければ"..."は入れず、同じ行を2回は出力しない
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "--context", "5", THREE, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\n{}\n==> {} <==\nTwo lines.\nFour words.\n",
+            THREE,
+            fs::read_to_string(THREE)?,
+            TWO
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--context", "0", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal context line count -- 0\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--context", "1", "-n", "-2", TEN])
+        .assert()
+        .failure()
+        .stderr("--context needs a positive line count\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn absolute_paths() -> TestResult {
+    let ten = fs::canonicalize(TEN)?;
+    Command::cargo_bin(PRG)?
+        .write_stdin("stdin\n")
+        .args(["--absolute-paths", "-n", "1", TEN, "-"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\none\n\n==> standard input <==\nstdin\n",
+            ten.display()
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn separator_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--separator-lines", "3", "-n", "1", TEN, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\none\n\n\n\n==> {} <==\nTwo lines.\n",
+            TEN, TWO
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--separator-lines", "0", "-n", "1", TEN, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\none\n==> {} <==\nTwo lines.\n",
+            TEN, TWO
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--separator-lines", "x", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal separator line count -- x\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_only() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--count", "-n", "3", TEN])
+        .assert()
+        .success()
+        .stdout("3\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--count", "-n", "3", TEN, TWO, "missing.txt", EMPTY])
+        .assert()
+        .failure()
+        .stdout(format!("3 {}\n2 {}\n0 {}\n", TEN, TWO, EMPTY))
+        .stderr("missing.txt: No such file or directory (os error 2)\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--count", "-c", "-5", "-q", TEN, ONE])
+        .assert()
+        .success()
+        .stdout("44\n18\n");
+
+    #[cfg(feature = "graphemes")]
+    Command::cargo_bin(PRG)?
+        .write_stdin("e\u{301}e\u{301}e\n")
+        .args(["--count", "--graphemes", "2"])
+        .assert()
+        .success()
+        .stdout("2\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn raw_keeps_invalid_utf8() -> TestResult {
+    let input: &[u8] = b"a\xff\n\xe3\x81\r\nb\nc\n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--raw", "-n", "2"])
+        .assert()
+        .success()
+        .stdout(&input[..7]);
+
+    // --rawでなければ不正なバイトはU+FFFDになる
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["-n", "1"])
+        .assert()
+        .success()
+        .stdout("a\u{fffd}\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--raw", "-n", "-2", "-N", "--unix-lines"])
+        .assert()
+        .success()
+        .stdout(&b"     1\ta\xff\n     2\t\xe3\x81\n"[..]);
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--raw", "--stats", "-n", "1", "--context", "1"])
+        .assert()
+        .success()
+        .stdout(&b"a\xff\n...\nc\n"[..])
+        .stderr("-: 9 bytes (limit: first 1 lines and the last 1 lines)\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn squeeze_blank() -> TestResult {
+    let input = "\n\n\na\n\n\nb\nc\n\n\n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .arg("--squeeze-blank")
+        .assert()
+        .success()
+        .stdout("\na\n\nb\nc\n\n");
+
+    // まとめた後の行で数える
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["-s", "-n", "3", "-N"])
+        .assert()
+        .success()
+        .stdout("     1\t\n     2\ta\n     3\t\n");
+
+    // 空白だけの行はまとめない
+    Command::cargo_bin(PRG)?
+        .write_stdin(" \n \n")
+        .arg("-s")
+        .assert()
+        .success()
+        .stdout(" \n \n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn min_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--min-lines", "3", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--min-lines", "3", "-n", "1", TEN, TWO])
+        .assert()
+        .failure()
+        .stdout(format!("==> {} <==\none\n", TEN))
+        .stderr(format!("headr: {}: only 2 of 3 lines available\n", TWO));
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\nb")
+        .args(["--min-lines", "2", "-n", "1"])
+        .assert()
+        .success()
+        .stdout("a\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--min-lines", "3", "-j", "2", TWO, TEN])
+        .assert()
+        .failure()
+        .stderr(format!("headr: {}: only 2 of 3 lines available\n", TWO));
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_min_lines() -> TestResult {
+    for bad in ["0", "x"] {
+        Command::cargo_bin(PRG)?
+            .args(["--min-lines", bad, TEN])
+            .assert()
+            .failure()
+            .stderr(format!("illegal minimum line count -- {}\n", bad));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn repeat_until_interrupted() -> TestResult {
+    use std::io::BufReader;
+    use std::process::{Command as StdCommand, Stdio};
+
+    let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["--repeat", "0.1", "-n", "1", TEN])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().ok_or("no stdout")?);
+
+    /* 端末でなければ、画面を消さずに続けて書き足す */
+    let mut line = String::new();
+    for _ in 0..2 {
+        line.clear();
+        stdout.read_line(&mut line)?;
+        assert_eq!(line, "one\n");
+    }
+    StdCommand::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_repeat() -> TestResult {
+    for bad in ["0", "0.0", "x", "inf", "1e30"] {
+        Command::cargo_bin(PRG)?
+            .args(["--repeat", bad, TEN])
+            .assert()
+            .failure()
+            .stderr(format!("illegal interval -- {}\n", bad));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn line_buffered_flushes_each_line() -> TestResult {
+    use std::io::BufReader;
+    use std::process::{Command as StdCommand, Stdio};
+
+    let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["--line-buffered", "-n", "2"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or("no stdin")?;
+    let mut stdout = BufReader::new(child.stdout.take().ok_or("no stdout")?);
+
+    /* 入力を閉じる前に、書いた行がそのまま出てくる */
+    let mut line = String::new();
+    for expected in ["one\n", "two\n"] {
+        stdin.write_all(expected.as_bytes())?;
+        stdin.flush()?;
+        line.clear();
+        stdout.read_line(&mut line)?;
+        assert_eq!(line, expected);
+    }
+    drop(stdin);
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "serde")]
+#[test]
+fn dump_config() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--dump-config", "-n", "3", TEN])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "\"lines\": {\n    \"First\": 3\n  }",
+        ))
+        .stdout(predicate::str::contains("\"bytes\": null"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strip_bom() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("bom.txt");
+    fs::write(&path, b"\xef\xbb\xbfone\ntwo\nthree\n")?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "-n", "1"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "-c", "5"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("one\nt");
+
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "-c", "-6"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Ok(())
+}
+
+#[test]
+fn strip_bom_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin(&b"\xef\xbb\xbfab"[..])
+        .args(["--strip-bom", "-c", "1"])
+        .assert()
+        .success()
+        .stdout("a");
+
+    // --strip-bomを付けなければBOMもそのまま出力する
+    Command::cargo_bin(PRG)?
+        .write_stdin("\u{feff}ab".to_string())
+        .args(["-c", "3"])
+        .assert()
+        .success()
+        .stdout(&b"\xef\xbb\xbf"[..]);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn custom_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a;b;c;d;e")
+        .args(["-n", "3", "-d", ";"])
+        .assert()
+        .success()
+        .stdout("a;b;c;");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a|b|c")
+        .args(["-n", "-1", "--delimiter", "|"])
+        .assert()
+        .success()
+        .stdout("a|b|");
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", "ab", TEN])
+        .assert()
+        .failure()
+        .stderr("the delimiter must be a single byte -- ab\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-d", ";", "-z", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skips_binary_file() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("data.bin");
+    fs::write(&path, b"\x7fELF\x02\x01\x01\x00\x00\x00")?;
+    let binary = path.to_string_lossy().to_string();
+
+    let expected = format!("==> {} <==\nÖne line, four words.\n", ONE);
+    Command::cargo_bin(PRG)?
+        .args([ONE, &binary])
+        .assert()
+        .success()
+        .stdout(expected)
+        .stderr(format!("headr: {}: binary file matches\n", binary));
+
+    Ok(())
+}
+
+#[test]
+fn text_prints_binary_file() -> TestResult {
+    let input = b"\x7fELF\x02\x01\x01\x00\x00\x00";
+    Command::cargo_bin(PRG)?
+        .write_stdin(&input[..])
+        .args(["--text", "-c", "4"])
+        .assert()
+        .success()
+        .stdout(&input[..4])
+        .stderr("");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_words() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--words", "3", THREE])
+        .assert()
+        .success()
+        .stdout("Three\r\nlines,\r\nfour");
+
+    Ok(())
+}
+
+#[test]
+fn dies_words_and_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--words", "3", "-n", "2", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Command::cargo_bin(PRG)?
+        .args(["--words", "x", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal word count -- x\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "checksum")]
+#[test]
+fn checksum_to_stderr() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--checksum", "sha256", "-n", "3", "-j", "2", TEN, TWO])
+        .assert()
+        .success()
+        .stderr(format!(
+            "{}: sha256=b6285c57e8797db5d4c51c80d6f11938afda9b11c6a003549709189e9b4b92a2\n\
+             {}: sha256=344b2df4aa3a17449c49ee877c14aa281792703c2861c7d0fb62ac8e3d8c54e9\n",
+            TEN, TWO
+        ));
+
+    Ok(())
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn checksum_to_stdout() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--checksum", "md5", "--checksum-stdout", "-c", "5", TEN])
+        .assert()
+        .success()
+        .stdout(format!(
+            "one\nt\n{}: md5=92dec7f978c07c66ed179f686d24c56e\n",
+            TEN
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "decode")]
+#[test]
+fn decode_base64() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let token = dir.path().join("token.b64");
+    // base64コマンドのように折り返してあっても読める
+    fs::write(&token, "b25lCnR3bwp0aHJl\nZQpmb3VyCg==\n")?;
+    let token = token.to_str().ok_or("bad path")?;
+    Command::cargo_bin(PRG)?
+        .args(["--decode", "base64", "-n", "2", token])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--decode", "base64", "-c", "9", token])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nt");
+
+    Ok(())
+}
+
+#[cfg(feature = "decode")]
+#[test]
+fn dies_invalid_base64() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--decode", "base64", "-"])
+        .write_stdin("b25l!")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::ends_with(
+            "invalid base64: Invalid symbol 33, offset 4.\n",
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ignore_errors() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--ignore-errors", "-n", "1", &bad, TEN])
+        .assert()
+        .failure()
+        .stdout(format!("==> {} <==\none\n", TEN))
+        .stderr("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--ignore-errors", "--force-success", &bad])
+        .assert()
+        .success()
+        .stderr("");
+
+    Ok(())
+}
+
+#[test]
+fn force_success_still_reports() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--force-success", &bad])
+        .assert()
+        .success()
+        .stderr(predicate::str::is_match(format!(
+            "{}: .* [(]os error 2[)]",
+            bad
+        ))?);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn print0_between_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--print0", "-z", "-n", "1", TEN, "missing.txt", TWO])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stdout(format!(
+            "{}\0{}",
+            fs::read_to_string(TEN)?,
+            fs::read_to_string(TWO)?
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--print0", "-v", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n==> {} <==\nTwo lines.\n",
+        ONE, TWO
+    );
+    let dir = tempfile::tempdir()?;
+    let list = dir.path().join("list");
+    fs::write(&list, format!("{}\0{}\0", ONE, TWO))?;
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "--files0-from"])
+        .arg(&list)
+        .assert()
+        .success()
+        .stdout(expected.clone());
+
+    // 最後のNULは無くてもよい
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "--files0-from", "-"])
+        .write_stdin(format!("{}\0{}", ONE, TWO))
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_files0_from() -> TestResult {
+    for (list, expected) in [
+        (format!("{}\0\0{}", ONE, TWO), "-:2: invalid zero-length file name\n"),
+        (
+            format!("{}\0-", ONE),
+            "-:2: file name '-' is not allowed when reading file names from stdin\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--files0-from", "-"])
+            .write_stdin(list)
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr(expected);
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", "-", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn wrap() -> TestResult {
+    // 折り返しても-nでは1行と数える
+    Command::cargo_bin(PRG)?
+        .args(["--wrap", "4", "-n", "2", "-N", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\n     1\tÖne \nline\n, fo\nur w\nords\n.\n\n\
+             ==> {} <==\n     1\tTwo \nline\ns.\n     2\tFour\n wor\nds.\n",
+            ONE, TWO
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--wrap", "0", "-n", "1", ONE])
+        .assert()
+        .success()
+        .stdout("Öne line, four words.\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--wrap", "x", ONE])
+        .assert()
+        .failure()
+        .stderr("illegal wrap width -- x\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tail_lines_and_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--tail-lines", "8", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--tail-bytes", "4", "-"])
+        .write_stdin("abc\ndef\n")
+        .assert()
+        .success()
+        .stdout("abc\n");
+
+    // -n -8/-c -4と同じ設定になる
+    Command::cargo_bin(PRG)?
+        .args(["--dry-run", "--tail-bytes", "1K"])
+        .assert()
+        .success()
+        .stdout("file: -\nlimit: all but the last 1024 bytes\n");
+
+    Ok(())
+}
+
+#[test]
+fn dies_tail_lines_and_bytes() -> TestResult {
+    for args in [
+        ["--tail-lines", "1", "-n", "2"],
+        ["--tail-bytes", "1", "-c", "2"],
+        ["--tail-lines", "1", "--tail-bytes", "2"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("cannot be used with"));
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--tail-lines", "x", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal line count -- x\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--tail-bytes", "1Q", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal byte count -- 1Q\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dry_run() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("out.txt");
+    let output = output.to_str().ok_or("bad path")?;
+    Command::cargo_bin(PRG)?
+        .args(["--dry-run", "--glob", "-o", output, "--skip", "2"])
+        .args(["./tests/inputs/t*.txt", "-", "missing.txt", "-"])
+        .assert()
+        .success()
+        .stdout(
+            "file: tests/inputs/ten.txt\n\
+             file: tests/inputs/three.txt\n\
+             file: tests/inputs/two.txt\n\
+             file: -\n\
+             file: missing.txt\n\
+             limit: first 10 lines\n\
+             skip: 2 lines\n",
+        );
+    // 何も読み書きしない
+    assert!(!Path::new(output).exists());
+
+    for (args, limit) in [
+        (["-c", "-5"], "all but the last 5 bytes"),
+        (["-c", "3-9"], "bytes 3-9"),
+        (["--lines-ranges", "1-3,7"], "lines 1-3,7"),
+        (["-C", "4"], "first 4 characters"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg("--dry-run")
+            .args(args)
+            .assert()
+            .success()
+            .stdout(format!("file: -\nlimit: {}\n", limit));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_format() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--header-format", "### {} ###", "-n", "1", ONE, "-"])
+        .write_stdin("x\n")
+        .assert()
+        .success()
+        .stdout(format!(
+            "### {} ###\nÖne line, four words.\n\n### standard input ###\nx\n",
+            ONE
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_header_format() -> TestResult {
+    for bad in ["file", "{} and {}"] {
+        Command::cargo_bin(PRG)?
+            .args(["--header-format", bad, ONE])
+            .assert()
+            .failure()
+            .stderr(format!(
+                "the header format must contain exactly one {{}} -- {}\n",
+                bad
+            ));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn interleave() -> TestResult {
+    let expected = format!(
+        "{ten}: one\n{two}: Two lines.\nstandard input: a\n\
+         {ten}: two\n{two}: Four words.\n{ten}: three\n",
+        ten = TEN,
+        two = TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--interleave", "-n", "3", TEN, TWO, "missing.txt", EMPTY])
+        .arg("-")
+        .write_stdin("a")
+        .assert()
+        .failure()
+        .stdout(expected)
+        .stderr(predicate::str::is_match(
+            "^missing.txt: .* [(]os error 2[)]\n$",
+        )?);
+
+    Command::cargo_bin(PRG)?
+        .args(["--interleave", "-n", "-1", TEN])
+        .assert()
+        .failure()
+        .stderr("--interleave needs a positive line count\n");
+
+    // 落とした行にはラベルも付けない
+    let t1 = tempfile::NamedTempFile::new()?;
+    fs::write(&t1, "  \nx\n")?;
+    let t1 = t1.path().to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args(["--interleave", "--trim", "--skip-empty", "-n", "2", t1, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{two}: Two lines.\n{t1}: x\n{two}: Four words.\n",
+            t1 = t1,
+            two = TWO
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_empty() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n==> {} <==\nTwo lines.\n",
+        ONE, TWO
+    );
+    for jobs in ["1", "2"] {
+        Command::cargo_bin(PRG)?
+            .args(["--skip-empty", "-j", jobs, "-n", "1", EMPTY, ONE])
+            .args([EMPTY, "-", TWO, EMPTY])
+            .write_stdin("")
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--quiet-empty", "-v", "-"])
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout("");
+
+    // 読んだ結果が空になるファイルも出さない
+    Command::cargo_bin(PRG)?
+        .args(["--skip-empty", "-n", "-2", TWO, THREE])
+        .assert()
+        .success()
+        .stdout(format!("==> {} <==\nThree\r\n", THREE));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_buffer() -> TestResult {
+    let ten = fs::read_to_string(TEN)?;
+    let msg = "headr: -: dropping the end needs more than 5 bytes of memory \
+               (raise --max-buffer, default 1G)\n";
+    for count in [["-n", "-2"], ["-c", "-6"]] {
+        Command::cargo_bin(PRG)?
+            .args(count)
+            .args(["--max-buffer", "5", "-"])
+            .write_stdin(ten.clone())
+            .assert()
+            .failure()
+            .stderr(msg);
+    }
+
+    // 上限に収まれば今までどおり
+    Command::cargo_bin(PRG)?
+        .args(["-n", "-8", "--max-buffer", "1K", "-"])
+        .write_stdin(ten.clone())
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    // 大きな数でも最初に領域を確保しない
+    Command::cargo_bin(PRG)?
+        .args(["-n", "-9999999999", "-"])
+        .write_stdin(ten)
+        .assert()
+        .success()
+        .stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_max_buffer() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "-1", "--max-buffer", "1Q", ONE])
+        .assert()
+        .failure()
+        .stderr("illegal buffer size -- 1Q\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn split() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let prefix = dir.path().join("out_");
+    let prefix = prefix.to_str().ok_or("bad path")?;
+    Command::cargo_bin(PRG)?
+        .args(["--split", "3", "--split-prefix", prefix, "-n", "8", TEN])
+        .assert()
+        .success()
+        .stdout("");
+    for (suffix, expected) in [
+        ("000", "one\ntwo\nthree\n"),
+        ("001", "four\nfive\nsix\n"),
+        ("002", "seven\neight\n"),
+    ] {
+        assert_eq!(
+            fs::read_to_string(format!("{}{}", prefix, suffix))?,
+            expected
+        );
+    }
+    assert!(!dir.path().join("out_003").exists());
+
+    // 既定の接頭辞はx。最後のファイルが行の途中で終わってもそのまま書く
+    Command::cargo_bin(PRG)?
+        .current_dir(dir.path())
+        .args(["--split", "1", "--split-width", "1", "-c", "6", "-"])
+        .write_stdin("ab\ncd\nef\n")
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(dir.path().join("x0"))?, "ab\n");
+    assert_eq!(fs::read_to_string(dir.path().join("x1"))?, "cd\n");
+    assert!(!dir.path().join("x2").exists());
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_split() -> TestResult {
+    for (args, expected) in [
+        (["--split", "0"], "illegal split line count -- 0\n"),
+        (
+            ["--split=1", "--split-width=x"],
+            "illegal suffix width -- x\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .args([ONE])
+            .assert()
+            .failure()
+            .stderr(expected);
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn timeout_fifo_without_writer() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let fifo = dir.path().join("fifo");
+    assert!(std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()?
+        .success());
+    let fifo = fifo.to_str().ok_or("bad path")?;
+    Command::cargo_bin(PRG)?
+        .args(["--timeout", "100", fifo, ONE])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .failure()
+        .stdout(format!("==> {} <==\nÖne line, four words.\n", ONE))
+        .stderr(format!("{}: no data within 100 ms (--timeout)\n", fifo));
+
+    Ok(())
+}
+
+#[test]
+fn timeout_stdin_without_data() -> TestResult {
+    use std::process::Stdio;
+
+    let mut child =
+        std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(["--timeout", "100"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+    let stdin = child.stdin.take(); // 閉じずに持っておく
+    let output = child.wait_with_output()?;
+    drop(stdin);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "-: no data within 100 ms (--timeout)\n"
+    );
+
+    // 通常のファイルとすぐに届く標準入力には影響しない
+    Command::cargo_bin(PRG)?
+        .args(["--timeout", "1", "-n", "1", "-", TWO])
+        .write_stdin("data\n")
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> standard input <==\ndata\n\n==> {} <==\nTwo lines.\n",
+            TWO
+        ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn retry_transient_errors() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let fifo = dir.path().join("fifo");
+    assert!(std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()?
+        .success());
+    let fifo = fifo.to_str().ok_or("bad path")?;
+    // --timeoutで待ちきれなかったのは一時的なエラーとしてやり直す
+    let msg = "no data within 50 ms (--timeout)";
+    Command::cargo_bin(PRG)?
+        .args([
+            "--timeout",
+            "50",
+            "--retry",
+            "2",
+            "--retry-delay",
+            "10",
+            fifo,
+        ])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .failure()
+        .stderr(format!(
+            "headr: {f}: {m}; retrying (1/2)\n\
+             headr: {f}: {m}; retrying (2/2)\n\
+             {f}: {m}\n",
+            f = fifo,
+            m = msg
+        ));
+
+    // 存在しないファイルはやり直さない
+    Command::cargo_bin(PRG)?
+        .args(["--retry", "3", "missing.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(
+            "^missing.txt: .* [(]os error 2[)]\n$",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_retry() -> TestResult {
+    for (args, expected) in [
+        (["--retry", "x"], "illegal retry count -- x\n"),
+        (
+            ["--retry=1", "--retry-delay=-1"],
+            "illegal retry delay -- -1\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(ONE)
+            .assert()
+            .failure()
+            .stderr(expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_timeout() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--timeout", "0", ONE])
+        .assert()
+        .failure()
+        .stderr("illegal timeout -- 0\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn utf8_clean() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("utf8.txt");
+    fs::write(&input, "aé€😀z")?;
+    let input = input.to_str().ok_or("bad path")?;
+
+    for (bytes, expected) in [
+        ("2", "aé"),
+        ("4", "aé€"),
+        ("7", "aé€😀"),
+        ("8", "aé€😀"),
+        ("2-3", "é"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--utf8-clean", "-c", bytes, input])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn first_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\tb\tc\nd\te\tf\ng\n")
+        .args(["--fields", "2", "-n", "2"])
+        .assert()
+        .success()
+        .stdout("a\tb\nd\te\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a,b,c\nd\n")
+        .args(["--fields", "1", "--field-sep", ","])
+        .assert()
+        .success()
+        .stdout("a\nd\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "progress")]
+#[test]
+fn progress_only_on_terminal() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--progress", "-c", "7", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo")
+        .stderr("");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lines_ranges() -> TestResult {
+    let expected = format!(
+        "==> {} <==\none\ntwo\neight\nnine\nten\n\n\
+         ==> {} <==\nTwo lines.\nFour words.\n",
+        TEN, TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--lines-ranges", "8-20,2,1-2", TEN, TWO])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_lines_ranges() -> TestResult {
+    for bad in ["3-1", "0", "1,", "x-2"] {
+        Command::cargo_bin(PRG)?
+            .args(["--lines-ranges", bad, TEN])
+            .assert()
+            .failure()
+            .stderr(format!("illegal line range -- {}\n", bad));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn single_line() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--line", "3", TEN, TWO])
+        .assert()
+        .success()
+        .stdout(format!("==> {} <==\nthree\n\n==> {} <==\n", TEN, TWO));
+
+    Command::cargo_bin(PRG)?
+        .args(["--line", "3", "--exact", TEN, TWO])
+        .assert()
+        .failure()
+        .stdout(format!("==> {} <==\nthree\n", TEN))
+        .stderr(format!("headr: {}: only 2 of 3 lines available\n", TWO));
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_line() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--line", "0", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal line number -- 0\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "5", "-n", "2", TEN])
+        .assert()
+        .success()
+        .stdout("six\nseven\n");
+
+    // 末尾を除く指定とも組み合わせられる
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "7", "-n", "-1", TEN])
+        .assert()
+        .success()
+        .stdout("eight\nnine\n");
+
+    Ok(())
+}
+
+#[test]
+fn skip_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "4", "-c", "3", TEN])
+        .assert()
+        .success()
+        .stdout("two");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\n")
+        .args(["--skip", "4", "-c", "-1"])
+        .assert()
+        .success()
+        .stdout("two");
+
+    Ok(())
+}
+
+#[test]
+fn skip_past_end() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "100", TEN])
+        .assert()
+        .success()
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "100", "-c", "-3", TEN])
+        .assert()
+        .success()
+        .stdout("");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "regex")]
+#[test]
+fn until_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^t.r", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^t.r", "--until-exclusive", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    // 一致する行が無ければ最後まで表示する
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\nb")
+        .args(["--until", "z"])
+        .assert()
+        .success()
+        .stdout("a\nb");
+
+    Ok(())
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn dies_bad_until_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "(", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(
+            "invalid regular expression -- (",
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn unix_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--unix-lines", THREE])
+        .assert()
+        .success()
+        .stdout("Three\nlines,\nfour words.\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\r\nb\nc\r\rd\r\n")
+        .args(["--unix-lines", "-n", "-1"])
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\r\nb\nc\r\rd\r\n")
+        .args(["--unix-lines", "-n", "4"])
+        .assert()
+        .success()
+        .stdout("a\nb\nc\r\rd\n");
+
+    Ok(())
+}
+
+#[test]
+fn unix_lines_ignored_in_byte_mode() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\r\nb\r\n")
+        .args(["--unix-lines", "-c", "5"])
+        .assert()
+        .success()
+        .stdout("a\r\nb\r");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hex_dump() -> TestResult {
+    let expected = format!(
+        "==> {} <==\n\
+         00000000  6f 6e 65 0a 74 77 6f 0a  74 68 72 65 65 0a 66 6f  \
+         |one.two.three.fo|\n\
+         00000010  75 72 0a                                          |ur.|\n\
+         00000013\n\n\
+         ==> {} <==\n\
+         00000000  54 77 6f 20 6c 69 6e 65  73 2e 0a 46 6f 75 72 20  \
+         |Two lines..Four |\n\
+         00000010  77 6f 72                                          |wor|\n\
+         00000013\n",
+        TEN, TWO
+    );
+    Command::cargo_bin(PRG)?
+        .args(["-c", "19", "--hex", TEN, TWO])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_hex_without_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--hex", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--bytes"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pager_not_used_when_piped() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .env("PAGER", "sed s/^/paged:/")
+        .args(["--pager", "always", "-n", "2", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_pager() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--pager", "sometimes", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("isn't a valid value"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "encoding")]
+#[test]
+fn encoding_shift_jis() -> TestResult {
+    // "日本語\n二行目\n" in Shift_JIS
+    let input: Vec<u8> = vec![
+        0x93, 0xfa, 0x96, 0x7b, 0x8c, 0xea, b'\n', 0x93, 0xf1, 0x8d, 0x73,
+        0x96, 0xda, b'\n',
+    ];
+    Command::cargo_bin(PRG)?
+        .write_stdin(input.clone())
+        .args(["--encoding", "shift_jis", "-n", "1"])
+        .assert()
+        .success()
+        .stdout("日本語\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--encoding", "sjis", "-C", "2"])
+        .assert()
+        .success()
+        .stdout("日本");
+
+    Ok(())
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn dies_unknown_encoding() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "klingon", TEN])
+        .assert()
+        .failure()
+        .stderr("unknown encoding -- klingon\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "graphemes")]
+#[test]
+fn graphemes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("\u{1f1ef}\u{1f1f5}e\u{301}tc\n")
+        .args(["--graphemes", "2"])
+        .assert()
+        .success()
+        .stdout("\u{1f1ef}\u{1f1f5}e\u{301}");
+
+    Ok(())
+}
+
+#[cfg(feature = "graphemes")]
+#[test]
+fn dies_graphemes_and_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--graphemes", "2", "-C", "2", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "json")]
+#[test]
+fn format_json() -> TestResult {
+    let expected = format!(
+        "[\n  {{\n    \"file\": \"{}\",\n    \"lines\": [\n      \"one\",\n      \"two\"\n    ]\n  }},\n  \
+         {{\n    \"file\": \"missing.txt\",\n    \
+         \"error\": \"missing.txt: No such file or directory (os error 2)\"\n  }}\n]\n",
+        TEN
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "-n", "2", TEN, "missing.txt"])
+        .assert()
+        .failure()
+        .stdout(expected)
+        .stderr("");
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn format_json_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\n")
+        .args(["--format", "json", "-c", "4"])
+        .assert()
+        .success()
+        .stdout("[\n  {\n    \"file\": \"-\",\n    \"bytes\": \"b25lCg==\"\n  }\n]\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn format_csv() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "csv", "-n", "2", TEN, "missing.txt", "-"])
+        .write_stdin("a,b\n\"q\"\nc\n")
+        .assert()
+        .failure()
+        .stdout(format!(
+            "file,line_number,content\n{ten},1,one\n{ten},2,two\n\
+             -,1,\"a,b\"\n-,2,\"\"\"q\"\"\"\n",
+            ten = TEN
+        ))
+        .stderr(predicate::str::is_match(
+            "^missing.txt: .* [(]os error 2[)]\n$",
+        )?);
+
+    Ok(())
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn dies_format_csv_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "csv", "-c", "4", TEN])
+        .assert()
+        .failure()
+        .stderr("--format csv works only with lines, not bytes, characters or words\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn format_ndjson() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "ndjson", "-n", "2", TEN, "missing.txt", "-"])
+        .write_stdin("say \"hi\"\\\ttab\n")
+        .assert()
+        .failure()
+        .stdout(format!(
+            "{{\"file\":\"{ten}\",\"n\":1,\"text\":\"one\"}}\n\
+             {{\"file\":\"{ten}\",\"n\":2,\"text\":\"two\"}}\n\
+             {{\"file\":\"missing.txt\",\"error\":\
+             \"missing.txt: No such file or directory (os error 2)\"}}\n\
+             {{\"file\":\"-\",\"n\":1,\"text\":\"say \\\"hi\\\"\\\\\\ttab\"}}\n",
+            ten = TEN
+        ))
+        .stderr("");
+
+    // -zでは行の中の改行もエスケープして1行に収める
+    Command::cargo_bin(PRG)?
+        .args(["--format", "ndjson", "-z"])
+        .write_stdin("a\nb\0c\0")
+        .assert()
+        .success()
+        .stdout(
+            "{\"file\":\"-\",\"n\":1,\"text\":\"a\\nb\"}\n\
+             {\"file\":\"-\",\"n\":2,\"text\":\"c\"}\n",
+        );
+
+    Command::cargo_bin(PRG)?
+        .args(["--format", "ndjson", "--chars", "4", TEN])
+        .assert()
+        .failure()
+        .stderr("--format ndjson works only with lines, not bytes, characters or words\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tee_to_file() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("copy.txt");
+
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", "--tee"])
+        .arg(&path)
+        .arg(TEN)
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    assert_eq!(fs::read_to_string(&path)?, "one\ntwo\n");
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn tee_write_failure_keeps_stdout() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", "--tee", "/dev/full", TEN])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n")
+        .stderr(predicate::str::starts_with("headr: /dev/full: "));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_after_unterminated_line() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("partial.txt");
+    fs::write(&path, "abc")?;
+    let partial = path.to_string_lossy().to_string();
+
+    let expected =
+        format!("==> {} <==\nabc\n==> {} <==\nTwo lines.\n", partial, TWO);
+    for jobs in ["1", "2"] {
+        Command::cargo_bin(PRG)?
+            .args(["-n", "1", "-j", jobs, &partial, TWO])
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn no_blank_line_before_first_header() -> TestResult {
+    let expected = format!(
+        "==> {} <==\nÖne line, four words.\n\n==> {} <==\nTwo lines.\n",
+        ONE, TWO
+    );
+    for jobs in ["1", "2"] {
+        Command::cargo_bin(PRG)?
+            .args(["-n", "1", "-j", jobs, "missing.txt", ONE, TWO])
+            .assert()
+            .failure()
+            .stdout(expected.clone());
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "tar")]
+#[test]
+fn tar_member() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "tar://tests/inputs/archive.tar!docs/two.txt"])
+        .assert()
+        .success()
+        .stdout("Two lines.\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", "-4", "tar://tests/inputs/archive.tar!./ten.txt"])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\n");
+
+    Ok(())
+}
+
+#[cfg(all(feature = "tar", feature = "gzip"))]
+#[test]
+fn tar_gz_member() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", "tar://tests/inputs/archive.tar.gz!ten.txt"])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn dies_missing_tar_member() -> TestResult {
+    let spec = "tar://tests/inputs/archive.tar!missing.txt";
+    Command::cargo_bin(PRG)?
+        .arg(spec)
+        .assert()
+        .failure()
+        .stderr(format!(
+            "{}: no such member in tests/inputs/archive.tar\n",
+            spec
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn file_descriptor() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("one\ntwo\n")
+        .args(["-n", "1", "fd:0"])
+        .assert()
+        .success()
+        .stdout("one\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn dies_bad_file_descriptor() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["fd:x", "fd:99"])
+        .assert()
+        .failure()
+        .stderr(
+            "fd:x: invalid file descriptor\n\
+             fd:99: Bad file descriptor (os error 9)\n",
+        );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn limit_rate() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/ten.txt.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["--limit-rate", "1M", TEN])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+#[test]
+fn dies_bad_limit_rate() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--limit-rate", "0", ONE])
+        .assert()
+        .failure()
+        .stderr("illegal rate -- 0\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn paragraph() -> TestResult {
+    let input = "\na\nb\n\n\nc\n\nd\n\n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--paragraph", "-n", "2"])
+        .assert()
+        .success()
+        .stdout("a\nb\n\nc\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--paragraph", "-n", "-1"])
+        .assert()
+        .success()
+        .stdout("a\nb\n\nc\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn atomic_headers() -> TestResult {
+    run(
+        &["--atomic-headers", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n2.out",
+    )?;
+    run(
+        &[
+            "--atomic-headers",
+            "-j",
+            "2",
+            "-n",
+            "2",
+            EMPTY,
+            ONE,
+            TWO,
+            THREE,
+            TEN,
+        ],
+        "tests/expected/all.n2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn show_ends_and_tabs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\tb \nc\r\nlast")
+        .args(["-E", "-T"])
+        .assert()
+        .success()
+        .stdout("a^Ib $\nc\r$\nlast");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\tb\r\n")
+        .args(["--show-ends", "--unix-lines"])
+        .assert()
+        .success()
+        .stdout("a\tb$\n");
+
+    // バイト単位では何もしない
+    Command::cargo_bin(PRG)?
+        .write_stdin("a\tb\n")
+        .args(["-E", "-T", "-c", "4"])
+        .assert()
+        .success()
+        .stdout("a\tb\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_percent() -> TestResult {
+    // ten.txtは49バイトなので、10%は切り上げて5バイト
+    Command::cargo_bin(PRG)?
+        .args(["--bytes-percent", "10%", TEN])
+        .assert()
+        .success()
+        .stdout("one\nt");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin("0123456789")
+        .args(["--bytes-percent", "30"])
+        .assert()
+        .success()
+        .stdout("012");
+
+    Command::cargo_bin(PRG)?
+        .args(["--bytes-percent", "10%", "-n", "2", TEN])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "decrypt")]
+#[test]
+fn decrypt_age() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--decrypt", "--decrypt-key", "tests/inputs/age-key.txt"])
+        .args(["-n", "2", "tests/inputs/ten.txt.age", ONE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> tests/inputs/ten.txt.age <==\none\ntwo\n\n==> {} <==\nÖne line, four words.\n",
+            ONE
+        ));
+
+    Ok(())
+}
+
+#[cfg(feature = "decrypt")]
+#[test]
+fn dies_decrypt_without_key() -> TestResult {
+    // 復号できなければ、暗号文も途中までの中身も出力しない
+    Command::cargo_bin(PRG)?
+        .args(["--decrypt", "tests/inputs/ten.txt.age"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(
+            "tests/inputs/ten.txt.age: cannot decrypt: \
+             no identity given (use --decrypt-key FILE)\n",
+        );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fail_fast() -> TestResult {
+    let bad = gen_bad_file();
+    let expected =
+        format!("{}: No such file or directory (os error 2)\n", bad);
+    for args in [&[][..], &["-j", "2"], &["--interleave"], &["--count"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .args(["--fail-fast", ONE, &bad, TWO])
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("Two").not())
+            .stderr(expected.clone());
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn use_index() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("ten.txt");
+    fs::copy(TEN, &path)?;
+
+    Command::cargo_bin(PRG)?
+        .arg("--build-index")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("");
+    assert!(dir.path().join("ten.txt.headr-idx").exists());
+
+    Command::cargo_bin(PRG)?
+        .args(["--use-index", "--skip", "7", "-n", "2"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("eight\nnine\n")
+        .stderr("");
+
+    // 書き換えた後の古い索引は、警告して使わない
+    fs::write(&path, "1\n2\n3\n4\n")?;
+    Command::cargo_bin(PRG)?
+        .args(["--use-index", "--skip", "2"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("3\n4\n")
+        .stderr(predicate::str::contains("out of date, ignoring it"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reverse_lines() -> TestResult {
+    // ファイルごとに逆順にし、ファイルの順番は変えない
+    Command::cargo_bin(PRG)?
+        .args(["--reverse-lines", "-n", "3", TEN, "-"])
+        .write_stdin("a\nb")
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\nthree\ntwo\none\n\n==> standard input <==\nb\na\n",
+            TEN
+        ));
+
+    // 行番号は元の行のものを付ける
+    Command::cargo_bin(PRG)?
+        .args(["--reverse-lines", "-N", "-n", "-8", TEN])
+        .assert()
+        .success()
+        .stdout("     2\ttwo\n     1\tone\n");
+
+    // 折り返しや行末の$は、逆順にした後の1行ごとに付ける
+    Command::cargo_bin(PRG)?
+        .args(["--reverse-lines", "--wrap", "2"])
+        .write_stdin("abcd\nefgh\n")
+        .assert()
+        .success()
+        .stdout("ef\ngh\nab\ncd\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--reverse-lines", "-E"])
+        .write_stdin("a\nb")
+        .assert()
+        .success()
+        .stdout("b$\na$\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--reverse-lines", "-c", "3", TEN])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_header() -> TestResult {
+    let csv = "id,name\n1,a\n2,b\n3,c\n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(csv)
+        .args(["--keep-header", "-n", "2"])
+        .assert()
+        .success()
+        .stdout("id,name\n1,a\n2,b\n");
+
+    // 見出しには行番号を付けず、--skipも見出しの後から数える
+    Command::cargo_bin(PRG)?
+        .write_stdin(csv)
+        .args(["--keep-header", "--skip", "1", "-N", "-n", "1"])
+        .assert()
+        .success()
+        .stdout("id,name\n     1\t2,b\n");
+
+    // 見出ししかなければ見出しだけを出力する
+    Command::cargo_bin(PRG)?
+        .write_stdin("id,name\n")
+        .args(["--keep-header", "-n", "5"])
+        .assert()
+        .success()
+        .stdout("id,name\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "json")]
+#[test]
+fn error_format_json() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--error-format", "json", "-q", &bad, ONE, "tests"])
+        .assert()
+        .failure()
+        .stdout("Öne line, four words.\n")
+        .stderr(format!(
+            "{{\"file\":\"{}\",\"error\":\"No such file or directory (os error 2)\",\
+             \"kind\":\"not_found\"}}\n\
+             {{\"file\":\"tests\",\"error\":\"Is a directory\",\
+             \"kind\":\"is_a_directory\"}}\n",
+            bad
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn chunk_size() -> TestResult {
+    // 読む単位を変えても出力は同じ
+    for args in [["-c", "7"], ["-n", "3"]] {
+        Command::cargo_bin(PRG)?
+            .args(["--chunk-size", "2"])
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .success()
+            .stdout(if args[0] == "-c" {
+                "one\ntwo"
+            } else {
+                "one\ntwo\nthree\n"
+            });
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--chunk-size", "0", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal chunk size -- 0\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn diff() -> TestResult {
+    let a = tempfile::NamedTempFile::new()?;
+    fs::write(&a, "1\n2\n3\n4\n5")?;
+    Command::cargo_bin(PRG)?
+        .args(["--diff", "-n", "2", TEN])
+        .arg(a.path())
+        .assert()
+        .success()
+        .stdout("a: one\nb: 1\na: two\nb: 2\n");
+
+    // 片方が先に終わったら、残った方の行だけを出す
+    Command::cargo_bin(PRG)?
+        .args(["--diff", "-N", "-"])
+        .arg(a.path())
+        .write_stdin("1\n2\n3\n4\n5\n6\n")
+        .assert()
+        .success()
+        .stdout("a:      6\t6\n");
+
+    // 同じなら何も出力しない
+    Command::cargo_bin(PRG)?
+        .args(["--diff", TEN, TEN])
+        .assert()
+        .success()
+        .stdout("");
+
+    // 落とした行にはラベルも付けない
+    Command::cargo_bin(PRG)?
+        .args(["--diff", "--trim", "--skip-empty", "-n", "1", "-"])
+        .arg(a.path())
+        .write_stdin(" \n")
+        .assert()
+        .success()
+        .stdout("b: 1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--diff", TEN])
+        .assert()
+        .failure()
+        .stderr("--diff needs exactly two files, got 1\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn trim() -> TestResult {
+    let input = "  one\t\r\n\t \r\n\ttwo  \n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .arg("--trim")
+        .assert()
+        .success()
+        .stdout("one\r\n\r\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .arg("--trim-start")
+        .assert()
+        .success()
+        .stdout("one\t\r\n\r\ntwo  \n");
+
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .arg("--trim-end")
+        .assert()
+        .success()
+        .stdout("  one\r\n\r\n\ttwo\n");
+
+    // --skip-emptyでは空になった行を出力しない
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--trim", "--skip-empty"])
+        .assert()
+        .success()
+        .stdout("one\r\ntwo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--trim", "--trim-end", TEN])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(all(feature = "watch", unix))]
+#[test]
+fn watch_dir() -> TestResult {
+    use std::{thread, time::Duration};
+
+    let dir = tempfile::tempdir()?;
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-n", "1", "--watch-dir"])
+        .arg(dir.path())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    thread::sleep(Duration::from_millis(500));
+
+    // 書き込み中のファイルは書き終わるまで待つ
+    let mut partial = File::create(dir.path().join("b.txt"))?;
+    partial.write_all(b"fir")?;
+    fs::write(dir.path().join("a.txt"), "one\ntwo\n")?;
+    thread::sleep(Duration::from_millis(200));
+    partial.write_all(b"st\nsecond\n")?;
+    drop(partial);
+    thread::sleep(Duration::from_millis(1500));
+
+    // Ctrl-Cで止めたときも正常終了にする
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!(
+            "==> {} <==\none\n\n==> {} <==\nfirst\n",
+            a.display(),
+            b.display()
+        )
+    );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_offset() -> TestResult {
+    // マルチバイト文字とCRLFも、そのままのバイト数で数える
+    let input = "Öne\r\ntwo\n\nthree\n";
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--byte-offset", "-n", "3"])
+        .assert()
+        .success()
+        .stdout("0\tÖne\r\n6\ttwo\n10\t\n");
+
+    // 行番号の後ろに書き、--skipで読み飛ばした分も数える
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(["--byte-offset", "-N", "--skip", "1", "-n", "2"])
+        .assert()
+        .success()
+        .stdout("     1\t6\ttwo\n     2\t10\t\n");
+
+    // ファイルごとに0から数える
+    Command::cargo_bin(PRG)?
+        .args(["--byte-offset", "-q", "-n", "2", TEN, TEN])
+        .assert()
+        .success()
+        .stdout("0\tone\n4\ttwo\n0\tone\n4\ttwo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--byte-offset", "-n", "-2", TEN])
+        .assert()
+        .failure()
+        .stderr("--byte-offset needs a positive line count\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(all(feature = "cmd", unix))]
+#[test]
+fn command_input() -> TestResult {
+    // 必要な行を読んだら止めるので、終わらないコマンドでも返ってくる
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", "cmd:yes", "cmd:printf 'a\\nb\\nc\\n'"])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("==> cmd:yes <==\ny\ny\n\n==> cmd:printf 'a\\nb\\nc\\n' <==\na\nb\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["cmd:echo partial; exit 3"])
+        .assert()
+        .failure()
+        .stdout("partial\n")
+        .stderr("cmd:echo partial; exit 3: command failed: exit status: 3\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_final_newline() -> TestResult {
+    // 複数のファイルでも最後の1行だけ改行を付けない
+    Command::cargo_bin(PRG)?
+        .args(["--no-final-newline", "-n", "1", ONE, TWO])
+        .assert()
+        .success()
+        .stdout("==> ./tests/inputs/one.txt <==\nÖne line, four words.\n\n==> ./tests/inputs/two.txt <==\nTwo lines.");
+
+    Command::cargo_bin(PRG)?
+        .args(["--no-final-newline", "-c", "23", ONE])
+        .assert()
+        .success()
+        .stdout("Öne line, four words.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn spill_to_disk() -> TestResult {
+    let input: String = (1..=100).map(|num| format!("{}\n", num)).collect();
+    let expected: String = (1..=97).map(|num| format!("{}\n", num)).collect();
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-buffer", "8", "-n", "-3"])
+        .write_stdin(input.clone())
+        .assert()
+        .failure();
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-buffer", "8", "--spill-to-disk", "-n", "-3"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-buffer", "8", "--spill-to-disk", "-c", "-288"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout("1\n2\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-buffer", "8", "--spill-to-disk", "--percent", "3"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout("1\n2\n3\n");
+
+    // --spill-to-diskが無ければ、割合でも--max-bufferを超えて読み込まない
+    for args in [["--percent", "3"], ["--bytes-percent", "3"]] {
+        Command::cargo_bin(PRG)?
+            .args(["--max-buffer", "8"])
+            .args(args)
+            .write_stdin(input.clone())
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr(predicate::str::contains("more than 8 bytes of memory"));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn validate_utf8() -> TestResult {
+    let input = b"ok\n\xffbad\n".to_vec();
+    Command::cargo_bin(PRG)?
+        .args(["--validate-utf8", "-n", "1"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout("ok\n");
+
+    // 不正なバイトの手前までを出力して、その位置を報告する
+    Command::cargo_bin(PRG)?
+        .args(["--validate-utf8"])
+        .write_stdin(input.clone())
+        .assert()
+        .failure()
+        .stdout("ok\n")
+        .stderr("headr: -: invalid UTF-8 at byte 3\n");
+
+    // 位置は入力の中で数える。-Nの表示も、不正な行の行頭も書かない
+    for args in [
+        &["-N"][..],
+        &["--byte-offset"],
+        &["--skip", "1"],
+        &["-n", "-0"],
+    ] {
+        let expected = match args[0] {
+            "-N" => "     1\tok\n",
+            "--byte-offset" => "0\tok\n",
+            "--skip" => "",
+            _ => "ok\n",
+        };
+        Command::cargo_bin(PRG)?
+            .arg("--validate-utf8")
+            .args(args)
+            .write_stdin(input.clone())
+            .assert()
+            .failure()
+            .stdout(expected)
+            .stderr("headr: -: invalid UTF-8 at byte 3\n");
+    }
+
+    // -cで文字の途中で切れた場合もエラーにする
+    Command::cargo_bin(PRG)?
+        .args(["--validate-utf8", "-c", "2", ONE])
+        .assert()
+        .success()
+        .stdout("Ö");
+    Command::cargo_bin(PRG)?
+        .args(["--validate-utf8", "-c", "1", ONE])
+        .assert()
+        .failure()
+        .stderr(format!("headr: {}: invalid UTF-8 at byte 0\n", ONE));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "regex")]
+#[test]
+fn files_with_head_matching() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files-with-head-matching", "^Four", ONE, TWO, THREE])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", TWO));
+
+    // -nで調べる範囲を先頭の行に限る
+    Command::cargo_bin(PRG)?
+        .args([
+            "--files-with-head-matching",
+            "words",
+            "-n",
+            "1",
+            ONE,
+            TWO,
+            THREE,
+        ])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", ONE));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn timing() -> TestResult {
+    // 時間はstderrにだけ書き、stdoutは--timingなしと変わらない
+    let expected = fs::read_to_string("tests/expected/all.out")?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["--timing", EMPTY, ONE, TWO, THREE, TEN])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+
+    let stderr = String::from_utf8(output.stderr)?;
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines.len(), 6);
+    for (line, file) in lines.iter().zip([EMPTY, ONE, TWO, THREE, TEN]) {
+        assert!(line.starts_with(&format!("{}: ", file)));
+        assert!(line.ends_with("ms") || line.ends_with("us"));
+    }
+    assert!(lines[5].starts_with("total: "));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn with_total() -> TestResult {
+    // -nより短いファイルでも、最後まで数えた行数を付ける
+    Command::cargo_bin(PRG)?
+        .args(["--with-total", "-n", "2", TEN, TWO, EMPTY])
+        .assert()
+        .success()
+        .stdout(
+            "==> ./tests/inputs/ten.txt <==\none\ntwo\n(10 lines total)\n\n\
+             ==> ./tests/inputs/two.txt <==\nTwo lines.\nFour words.\n(2 lines total)\n\n\
+             ==> ./tests/inputs/empty.txt <==\n(0 lines total)\n",
+        );
+
+    // 最後の行が改行で終わっていなければ、行を改めてから書く
+    Command::cargo_bin(PRG)?
+        .args(["--with-total"])
+        .write_stdin("a\nb")
+        .assert()
+        .success()
+        .stdout("a\nb\n(2 lines total)\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--with-total", "-c", "3", TEN])
+        .assert()
+        .success()
+        .stdout("one");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn columns() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--columns", "1-3,6-9", "-n", "1", ONE])
+        .assert()
+        .success()
+        .stdout("Öneine,\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--columns", "0-3", ONE])
+        .assert()
+        .failure()
+        .stderr("illegal column range -- 0-3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dedup_heads() -> TestResult {
+    // 先頭の3行だけが同じファイル
+    let mut copy = tempfile::NamedTempFile::new()?;
+    write!(copy, "one\ntwo\nthree\nother\n")?;
+    let copy = copy.path().to_str().unwrap();
+
+    Command::cargo_bin(PRG)?
+        .args(["--dedup-heads", "-n", "3", TEN, ONE, copy])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\none\ntwo\nthree\n\n\
+             ==> {} <==\nÖne line, four words.\n\n\
+             ==> {} <== (same as {})\n",
+            TEN, ONE, copy, TEN
+        ));
+
+    // 4行目からは違うので、そのまま出力する
+    Command::cargo_bin(PRG)?
+        .args(["--dedup-heads", "-q", "-n", "4", TEN, copy])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\nfour\none\ntwo\nthree\nother\n");
+
+    // -qでも同じ先頭を黙って落とさない
+    Command::cargo_bin(PRG)?
+        .args(["--dedup-heads", "-q", "-n", "3", TEN, copy])
+        .assert()
+        .success()
+        .stdout(format!("one\ntwo\nthree\n(same as {})\n", TEN));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data() -> TestResult {
+    // 制御文字や不正なUTF-8もそのまま、NULまでを1件と数える
+    let input = b"a\x01\x02\0b\xff\0c".to_vec();
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-n", "2"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(b"a\x01\x02\0b\xff\0".to_vec());
+
+    // NULで終わらない最後のレコードにはNULを足さない
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-n", "-1"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(b"a\x01\x02\0b\xff\0".to_vec());
+    Command::cargo_bin(PRG)?
+        .args(["--null-data"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(b"a\x01\x02\0b\xff\0c".to_vec());
+
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-c", "3"])
+        .assert()
+        .failure();
+    Ok(())
+}